@@ -26,6 +26,8 @@ pub mod ssh;
 pub mod tab;
 pub mod termwiztermtab;
 pub mod tmux;
+mod tmux_commands;
+pub mod tmux_pane;
 pub mod window;
 
 use crate::activity::Activity;
@@ -467,6 +469,14 @@ impl Mux {
         self.windows.borrow().keys().cloned().collect()
     }
 
+    /// Forgets a domain that has no panes and will not be used again
+    pub fn remove_domain(&self, domain: DomainId) {
+        self.domains.borrow_mut().remove(&domain);
+        self.domains_by_name
+            .borrow_mut()
+            .retain(|_, named| named.domain_id() != domain);
+    }
+
     pub fn iter_domains(&self) -> Vec<Arc<dyn Domain>> {
         self.domains.borrow().values().cloned().collect()
     }
@@ -40,6 +40,12 @@ pub struct LocalPane {
     process: RefCell<ProcessState>,
     pty: RefCell<Box<dyn MasterPty>>,
     domain_id: DomainId,
+    /// Set while the pty is carrying the tmux control protocol.
+    /// Anything that the terminal model would write to the pty in
+    /// response to user activity (keys, mouse and focus reports,
+    /// pastes) must be suppressed while this is set, as tmux would
+    /// interpret it as a command.  Input for the remote panes is
+    /// routed via the TmuxPane instances hosted by the TmuxDomain.
     tmux_domain: RefCell<Option<Arc<TmuxDomainState>>>,
 }
 
@@ -71,7 +77,7 @@ impl Pane for LocalPane {
                 if let Some(line) = lines.get_mut(idx as usize) {
                     line.overlay_text_with_attribute(
                         0,
-                        "This pane is running tmux control mode. Press q to detach.",
+                        "This pane is running tmux control mode. Press q or CTRL-C to detach.",
                         CellAttributes::default(),
                     );
                 }
@@ -86,6 +92,11 @@ impl Pane for LocalPane {
     }
 
     fn kill(&self) {
+        // The tmux session outlives the pane that was carrying its
+        // control channel, so keep its tabs around for a reattach
+        if let Some(tmux) = self.tmux_domain.borrow_mut().take() {
+            tmux.disconnected();
+        }
         let mut proc = self.process.borrow_mut();
         log::debug!(
             "killing process in pane {}, state is {:?}",
@@ -149,16 +160,34 @@ impl Pane for LocalPane {
     }
 
     fn mouse_event(&self, event: MouseEvent) -> Result<(), Error> {
-        self.terminal.borrow_mut().mouse_event(event)
+        if self.tmux_domain.borrow().is_some() {
+            // Mouse reports would also corrupt the control protocol
+            Ok(())
+        } else {
+            self.terminal.borrow_mut().mouse_event(event)
+        }
     }
 
     fn key_down(&self, key: KeyCode, mods: KeyModifiers) -> Result<(), Error> {
-        if self.tmux_domain.borrow().is_some() {
-            log::error!("key: {:?}", key);
-            if key == KeyCode::Char('q') {
-                self.terminal.borrow_mut().send_paste("detach\n")?;
+        let tmux_domain_id = self.tmux_domain.borrow().as_ref().map(|t| t.domain_id);
+        if let Some(domain_id) = tmux_domain_id {
+            // The pty is carrying the control protocol, so regular
+            // input must not be sent to it.  Only the keys that
+            // request a detach are acted upon.
+            let detach = match (key, mods) {
+                (KeyCode::Char('q'), KeyModifiers::NONE) => true,
+                (KeyCode::Char('c'), KeyModifiers::CTRL) => true,
+                _ => false,
+            };
+            if detach {
+                let mux = Mux::get().expect("to be called on main thread");
+                if let Some(domain) = mux.get_domain(domain_id) {
+                    domain.detach()?;
+                }
+            } else {
+                log::trace!("ignoring key {:?} while in tmux control mode", key);
             }
-            return Ok(());
+            Ok(())
         } else {
             self.terminal.borrow_mut().key_down(key, mods)
         }
@@ -215,7 +244,10 @@ impl Pane for LocalPane {
     }
 
     fn focus_changed(&self, focused: bool) {
-        self.terminal.borrow_mut().focus_changed(focused);
+        if self.tmux_domain.borrow().is_none() {
+            // Focus reports would otherwise corrupt the control protocol
+            self.terminal.borrow_mut().focus_changed(focused);
+        }
     }
 
     fn is_mouse_grabbed(&self) -> bool {
@@ -357,6 +389,37 @@ impl Pane for LocalPane {
 struct LocalPaneDCSHandler {
     pane_id: PaneId,
     tmux_domain: Option<Arc<TmuxDomainState>>,
+    /// Set when what looked like tmux control mode turned out not to
+    /// be; the rest of the DCS sequence is discarded
+    abandoned: bool,
+}
+
+impl LocalPaneDCSHandler {
+    /// Tears down the tmux domain, returning the pane to normal use.
+    /// We're detaching rather than closing the remote panes, so we
+    /// don't send kill-pane for them.
+    fn exit_tmux_mode(&mut self) {
+        if let Some(tmux) = self.tmux_domain.take() {
+            let mux = Mux::get().expect("to be called on main thread");
+            if let Some(pane) = mux.get_pane(self.pane_id) {
+                let pane = pane.downcast_ref::<LocalPane>().unwrap();
+                pane.tmux_domain.borrow_mut().take();
+            }
+            tmux.detached();
+        }
+    }
+
+    /// Feeds the control channel to `tmux_domain` from now on
+    fn set_tmux_domain(&mut self, tmux_domain: Arc<TmuxDomainState>) {
+        let mux = Mux::get().expect("to be called on main thread");
+        if let Some(pane) = mux.get_pane(self.pane_id) {
+            let pane = pane.downcast_ref::<LocalPane>().unwrap();
+            pane.tmux_domain
+                .borrow_mut()
+                .replace(Arc::clone(&tmux_domain));
+        }
+        self.tmux_domain.replace(tmux_domain);
+    }
 }
 
 impl wezterm_term::DeviceControlHandler for LocalPaneDCSHandler {
@@ -369,44 +432,47 @@ impl wezterm_term::DeviceControlHandler for LocalPaneDCSHandler {
                     && mode.intermediates.is_empty()
                 {
                     log::error!("tmux -CC mode requested");
+                    self.abandoned = false;
+
+                    // Control mode starting afresh before the previous
+                    // session ended means that we lost the channel
+                    // carrying it, such as when ssh was disconnected
+                    if let Some(tmux) = self.tmux_domain.take() {
+                        tmux.disconnected();
+                    }
 
-                    // Create a new domain to host these tmux tabs
+                    // Create a new domain to host these tmux tabs.  Once
+                    // it knows which server and session it is attached to,
+                    // a disconnected domain that was attached to the same
+                    // ones may take over from it; see `take_successor`.
+                    let mux = Mux::get().expect("to be called on main thread");
                     let domain = TmuxDomain::new(self.pane_id);
                     let tmux_domain = Arc::clone(&domain.inner);
-
                     let domain: Arc<dyn Domain> = Arc::new(domain);
-                    let mux = Mux::get().expect("to be called on main thread");
                     mux.add_domain(&domain);
 
-                    if let Some(pane) = mux.get_pane(self.pane_id) {
-                        let pane = pane.downcast_ref::<LocalPane>().unwrap();
-                        pane.tmux_domain
-                            .borrow_mut()
-                            .replace(Arc::clone(&tmux_domain));
-                    }
-
-                    self.tmux_domain.replace(tmux_domain);
-
-                // TODO: do we need to proactively list available tabs here?
-                // if so we should arrange to call domain.attach() and make
-                // it do the right thing.
+                    // The domain lists the remote panes as soon as tmux
+                    // has sent its initial response, creating tabs for them
+                    self.set_tmux_domain(tmux_domain);
                 } else {
                     log::error!("unknown DeviceControlMode::Enter {:?}", mode,);
                 }
             }
             DeviceControlMode::Exit => {
-                if let Some(tmux) = self.tmux_domain.take() {
-                    let mux = Mux::get().expect("to be called on main thread");
-                    if let Some(pane) = mux.get_pane(self.pane_id) {
-                        let pane = pane.downcast_ref::<LocalPane>().unwrap();
-                        pane.tmux_domain.borrow_mut().take();
-                    }
-                    mux.domain_was_detached(tmux.domain_id);
-                }
+                self.exit_tmux_mode();
+                self.abandoned = false;
             }
             DeviceControlMode::Data(c) => {
                 if let Some(tmux) = self.tmux_domain.as_ref() {
-                    tmux.advance(c);
+                    if !tmux.advance(c) {
+                        log::warn!("output doesn't look like tmux control mode; ignoring it");
+                        self.exit_tmux_mode();
+                        self.abandoned = true;
+                    } else if let Some(successor) = tmux.take_successor() {
+                        self.set_tmux_domain(successor);
+                    }
+                } else if self.abandoned {
+                    // Discard the remainder of the sequence
                 } else {
                     log::error!(
                         "unhandled DeviceControlMode::Data {:x} {}",
@@ -422,8 +488,8 @@ impl wezterm_term::DeviceControlHandler for LocalPaneDCSHandler {
     }
 }
 
-struct LocalPaneNotifHandler {
-    pane_id: PaneId,
+pub(crate) struct LocalPaneNotifHandler {
+    pub pane_id: PaneId,
 }
 
 impl AlertHandler for LocalPaneNotifHandler {
@@ -448,6 +514,7 @@ impl LocalPane {
         terminal.set_device_control_handler(Box::new(LocalPaneDCSHandler {
             pane_id,
             tmux_domain: None,
+            abandoned: false,
         }));
         terminal.set_notification_handler(Box::new(LocalPaneNotifHandler { pane_id }));
         Self {
@@ -1,153 +1,287 @@
 use crate::domain::{alloc_domain_id, Domain, DomainId, DomainState};
 use crate::pane::{Pane, PaneId};
-use crate::tab::{SplitDirection, Tab, TabId};
+use crate::tab::{PaneEntry, PaneNode, SplitDirection, SplitDirectionAndSize, Tab, TabId};
+use crate::tmux_commands::{
+    Awaited, CaptureCursor, CapturePane, ContinuePane, DetachClient, EnableFlowControl, KillPane,
+    ListAllPanes, NewWindow, PaneItem, RefreshClient, ResizePane, ResizeWindow, ServerIdentity,
+    SplitWindow, SwitchSession, TmuxCommand,
+};
+use crate::tmux_pane::TmuxPane;
 use crate::window::WindowId;
-use crate::Mux;
-use anyhow::anyhow;
+use crate::{Mux, MuxNotification};
 use async_trait::async_trait;
 use portable_pty::{CommandBuilder, PtySize};
+use promise::{Future, Promise};
 use std::cell::RefCell;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Range;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tmux_cc::layout::{parse_layout, LayoutCell, LayoutContent};
 use tmux_cc::*;
+use wezterm_term::StableRowIndex;
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
-enum State {
-    WaitForInitialGuard,
-    Idle,
-    WaitingForResponse,
+/// The most output that we'll hold on to for a remote pane that we
+/// are not yet mirroring
+const MAX_PENDING_OUTPUT: usize = 64 * 1024;
+/// How long we'll hold on to output for a remote pane that we are
+/// not yet mirroring before deciding that we never will be
+const PENDING_OUTPUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Output for remote panes that we have not yet created local panes
+/// for.  `%output` for a new window can arrive before we've learned
+/// about its panes, so it is held here until `create_pane`.
+#[derive(Default)]
+struct PendingOutput {
+    panes: HashMap<TmuxPaneId, (Instant, Vec<u8>)>,
+}
+
+impl PendingOutput {
+    fn push(&mut self, pane: TmuxPaneId, bytes: &[u8], now: Instant) {
+        self.expire(now);
+        let (_, buffer) = self.panes.entry(pane).or_insert_with(|| (now, vec![]));
+        if buffer.len() + bytes.len() > MAX_PENDING_OUTPUT {
+            log::warn!(
+                "tmux: discarding {} bytes of output for unknown pane %{}",
+                bytes.len(),
+                pane
+            );
+            return;
+        }
+        buffer.extend_from_slice(bytes);
+    }
+
+    fn take(&mut self, pane: TmuxPaneId) -> Option<Vec<u8>> {
+        self.panes.remove(&pane).map(|(_, buffer)| buffer)
+    }
+
+    /// Discards output that has been waiting for too long
+    fn expire(&mut self, now: Instant) {
+        self.panes.retain(|pane, (since, buffer)| {
+            if now.duration_since(*since) < PENDING_OUTPUT_TIMEOUT {
+                return true;
+            }
+            log::warn!(
+                "tmux: discarding {} bytes of output for unknown pane %{}",
+                buffer.len(),
+                pane
+            );
+            false
+        });
+    }
+
+    fn clear(&mut self) {
+        self.panes.clear();
+    }
 }
 
-trait TmuxCommand {
-    fn get_command(&self) -> String;
-    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()>;
+/// The most bytes that we'll accept before tmux begins its initial
+/// response; anything longer means that the DCS sequence introducing
+/// control mode was not actually emitted by tmux
+const MAX_PREAMBLE: usize = 256;
+
+/// How many seconds of output tmux buffers for a pane before
+/// pausing it; see `refresh-client -f pause-after`
+const PAUSE_AFTER_SECS: u64 = 2;
+/// When tmux can't pause panes for us, the most output that we'll
+/// apply to a pane before we've caught up with the control stream
+const MAX_OUTPUT_BACKLOG: usize = 1024 * 1024;
+
+/// Keeps a pane whose output arrives faster than we can process it
+/// from flooding us.  tmux 3.2 and later pause such a pane for us,
+/// and we ask it to continue once we've caught up.  With older
+/// versions, we stop applying output to a pane that exceeds its
+/// budget, and refresh it with capture-pane once we've caught up.
+#[derive(Default)]
+struct FlowControl {
+    /// Whether tmux accepted `pause-after`
+    supported: bool,
+    /// Bytes applied to each pane since we last caught up; only
+    /// tracked when tmux doesn't support pausing
+    backlog: HashMap<TmuxPaneId, usize>,
+    /// Panes that tmux paused or that exceeded their budget
+    paused: HashSet<TmuxPaneId>,
+    /// Whether `caught_up` is going to be called
+    catch_up_scheduled: bool,
 }
 
-struct ListAllPanes;
-impl TmuxCommand for ListAllPanes {
-    fn get_command(&self) -> String {
-        "list-panes -aF '#{session_id} #{window_id} #{pane_id} \
-            #{pane_index} #{cursor_x} #{cursor_y} #{pane_width} #{pane_height} \
-            #{pane_left} #{pane_top}'\n"
-            .to_owned()
+impl FlowControl {
+    /// Accounts for `len` bytes of output for `pane`.
+    /// Returns false if the output should be discarded.
+    fn output(&mut self, pane: TmuxPaneId, len: usize) -> bool {
+        if self.paused.contains(&pane) {
+            return false;
+        }
+        if self.supported {
+            return true;
+        }
+        let backlog = self.backlog.entry(pane).or_insert(0);
+        *backlog += len;
+        if *backlog > MAX_OUTPUT_BACKLOG {
+            log::trace!("tmux: pane %{} is over budget; discarding output", pane);
+            self.paused.insert(pane);
+            return false;
+        }
+        true
+    }
+
+    fn pause(&mut self, pane: TmuxPaneId) {
+        self.paused.insert(pane);
     }
 
-    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
-        #[derive(Debug)]
-        struct Item {
-            session_id: TmuxSessionId,
-            window_id: TmuxWindowId,
-            pane_id: TmuxPaneId,
-            pane_index: u64,
-            cursor_x: u64,
-            cursor_y: u64,
-            pane_width: u64,
-            pane_height: u64,
-            pane_left: u64,
-            pane_top: u64,
+    /// Returns true if a call to `caught_up` needs to be scheduled
+    fn schedule_catch_up(&mut self) -> bool {
+        if self.catch_up_scheduled || (self.backlog.is_empty() && self.paused.is_empty()) {
+            return false;
         }
+        self.catch_up_scheduled = true;
+        true
+    }
 
-        let mut items = vec![];
+    /// Called once we've processed the output that was queued up
+    /// when the catch up was scheduled.  Returns the panes that
+    /// should be resumed.
+    fn caught_up(&mut self) -> Vec<TmuxPaneId> {
+        self.catch_up_scheduled = false;
+        self.backlog.clear();
+        let mut panes: Vec<TmuxPaneId> = self.paused.drain().collect();
+        panes.sort_unstable();
+        panes
+    }
 
-        for line in result.output.split('\n') {
-            if line.is_empty() {
-                continue;
-            }
-            let mut fields = line.split(' ');
-            let session_id = fields.next().ok_or_else(|| anyhow!("missing session_id"))?;
-            let window_id = fields.next().ok_or_else(|| anyhow!("missing window_id"))?;
-            let pane_id = fields.next().ok_or_else(|| anyhow!("missing pane_id"))?;
-            let pane_index = fields
-                .next()
-                .ok_or_else(|| anyhow!("missing pane_index"))?
-                .parse()?;
-            let cursor_x = fields
-                .next()
-                .ok_or_else(|| anyhow!("missing cursor_x"))?
-                .parse()?;
-            let cursor_y = fields
-                .next()
-                .ok_or_else(|| anyhow!("missing cursor_y"))?
-                .parse()?;
-            let pane_width = fields
-                .next()
-                .ok_or_else(|| anyhow!("missing pane_width"))?
-                .parse()?;
-            let pane_height = fields
-                .next()
-                .ok_or_else(|| anyhow!("missing pane_height"))?
-                .parse()?;
-            let pane_left = fields
-                .next()
-                .ok_or_else(|| anyhow!("missing pane_left"))?
-                .parse()?;
-            let pane_top = fields
-                .next()
-                .ok_or_else(|| anyhow!("missing pane_top"))?
-                .parse()?;
-
-            // These ids all have various sigils such as `$`, `%`, `@`,
-            // so skip those prior to parsing them
-            let session_id = session_id[1..].parse()?;
-            let window_id = window_id[1..].parse()?;
-            let pane_id = pane_id[1..].parse()?;
-
-            items.push(Item {
-                session_id,
-                window_id,
-                pane_id,
-                pane_index,
-                cursor_x,
-                cursor_y,
-                pane_width,
-                pane_height,
-                pane_left,
-                pane_top,
-            });
-        }
-
-        log::error!("panes in domain_id {}: {:?}", domain_id, items);
-        Ok(())
+    fn clear(&mut self) {
+        self.backlog.clear();
+        self.paused.clear();
     }
 }
 
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+enum State {
+    WaitForInitialGuard,
+    Idle,
+    WaitingForResponse,
+    /// We've detached from tmux; no further commands will be sent
+    Detached,
+    /// The control channel went away without tmux telling us that we
+    /// were detached, such as when the ssh connection carrying it drops.
+    /// The local panes are kept so that we can reattach to them.
+    Disconnected,
+}
+
 pub(crate) struct TmuxDomainState {
-    pane_id: PaneId,
+    /// The local pane that is running tmux; this changes when we
+    /// reattach via a different pane
+    pane_id: RefCell<PaneId>,
     pub domain_id: DomainId,
     parser: RefCell<Parser>,
     state: RefCell<State>,
     cmd_queue: RefCell<VecDeque<Box<dyn TmuxCommand>>>,
+    /// The number of bytes received before the initial response began
+    preamble: RefCell<usize>,
+    /// Whether the initial response has begun, after which we know
+    /// that we are talking to tmux
+    initial_block_seen: RefCell<bool>,
+    /// Notifications that change the set of windows or their layout,
+    /// received while a response block was open.  They are held until
+    /// the block is complete so that they are applied in the same order
+    /// that tmux applied them.
+    deferred: RefCell<Vec<Event>>,
+    /// Maps remote tmux pane ids to the local panes that mirror them
+    panes: RefCell<HashMap<TmuxPaneId, PaneId>>,
+    /// Maps remote tmux windows to the local tabs that mirror them.
+    /// Along with `panes`, this survives the loss of the control
+    /// channel so that `reattach` can match the tabs up again.
+    tabs: RefCell<HashMap<TmuxWindowId, TabId>>,
+    /// Set by `reattach` until the next pane listing has been applied
+    resyncing: RefCell<bool>,
+    /// The tmux server and session that we attached to, as reported
+    /// by `ServerIdentity`.  A domain is only reattached via a control
+    /// channel that reports the same identity.
+    identity: RefCell<Option<String>>,
+    /// Set until we know our identity; notifications are held until
+    /// then, as they may be for another domain; see `identified`
+    identifying: RefCell<bool>,
+    /// The disconnected domain that took over our control channel
+    successor: RefCell<Option<Arc<TmuxDomainState>>>,
+    /// Output for remote panes that we are not yet mirroring
+    pending_output: RefCell<PendingOutput>,
+    flow: RefCell<FlowControl>,
+    /// The names of the remote windows, as reported by `%window-renamed`.
+    /// A rename may arrive before we have created the local panes for
+    /// the window, so we remember it here and apply it when we do.
+    window_names: RefCell<HashMap<TmuxWindowId, String>>,
+    /// The local window that hosts the tabs for this session
+    gui_window: RefCell<Option<WindowId>>,
+    /// The most recently requested client size
+    client_size: RefCell<Option<(u16, u16)>>,
+    /// Whether a refresh-client command is queued or awaiting its
+    /// response; further resizes are coalesced until it completes
+    resize_pending: RefCell<bool>,
+    /// The session that our client is attached to, as reported
+    /// by `%session-changed`
+    session: RefCell<Option<TmuxSessionId>>,
+    config: TmuxDomainConfig,
+}
+
+/// Configures which tmux session a `TmuxDomain` operates on
+#[derive(Debug, Clone, Default)]
+pub struct TmuxDomainConfig {
+    /// The name of the session to switch to after attaching.  The
+    /// session is created if it doesn't already exist.  When not set,
+    /// we use whichever session the control client attached to.
+    pub session: Option<String>,
+    /// Where new windows are placed within the session, such as
+    /// a window index; when not set, tmux picks the next free index
+    pub window: Option<String>,
 }
 
 pub struct TmuxDomain {
     pub(crate) inner: Arc<TmuxDomainState>,
+    name: String,
 }
 
 impl TmuxDomainState {
-    pub fn advance(&self, b: u8) {
-        let mut parser = self.parser.borrow_mut();
-        if let Some(event) = parser.advance_byte(b) {
-            let state = *self.state.borrow();
-            log::error!("tmux: {:?} in state {:?}", event, state);
-            if let Event::Guarded(response) = event {
-                match state {
-                    State::WaitForInitialGuard => {
-                        *self.state.borrow_mut() = State::Idle;
-                    }
-                    State::WaitingForResponse => {
-                        let cmd = self.cmd_queue.borrow_mut().pop_front().unwrap();
-                        let domain_id = self.domain_id;
-                        *self.state.borrow_mut() = State::Idle;
-                        promise::spawn::spawn(async move {
-                            if let Err(err) = cmd.process_result(domain_id, &response) {
-                                log::error!("error processing result: {}", err);
-                            }
-                        })
-                        .detach();
+    /// Feeds a byte of the control mode stream to the domain.
+    /// Returns false if the stream doesn't look like the tmux control
+    /// protocol, which happens when something other than tmux emits
+    /// the DCS sequence that introduces it, such as when a file that
+    /// contains it is printed.  The caller should stop feeding us.
+    pub fn advance(&self, b: u8) -> bool {
+        let (event, in_block) = {
+            let mut parser = self.parser.borrow_mut();
+            let event = parser.advance_byte(b);
+            (event, parser.is_in_block())
+        };
+        if in_block {
+            *self.initial_block_seen.borrow_mut() = true;
+        }
+        if !*self.initial_block_seen.borrow() {
+            // tmux always starts with the response to the command that
+            // started it, so the first line must begin a block.  Once it
+            // has, the newline that ends the block (and everything else)
+            // is fine, even though the block isn't processed until below.
+            let mut preamble = self.preamble.borrow_mut();
+            *preamble += 1;
+            if b == b'\n' || *preamble > MAX_PREAMBLE {
+                return false;
+            }
+        }
+        if let Some(event) = event {
+            match event {
+                Event::Guarded(response) => {
+                    self.process_response(response);
+                    if !*self.identifying.borrow() {
+                        let deferred = std::mem::take(&mut *self.deferred.borrow_mut());
+                        for event in deferred {
+                            self.process_notification(event);
+                        }
                     }
-                    State::Idle => {}
                 }
+                event if *self.identifying.borrow() || (in_block && is_topology_change(&event)) => {
+                    self.deferred.borrow_mut().push(event);
+                }
+                event => self.process_notification(event),
             }
         }
         if *self.state.borrow() == State::Idle && !self.cmd_queue.borrow().is_empty() {
@@ -162,39 +296,1033 @@ impl TmuxDomainState {
             })
             .detach();
         }
+        true
+    }
+
+    /// Handles a complete %begin/%end (or %begin/%error) block.
+    /// tmux processes the commands that we send strictly in order,
+    /// so the block is the response to the oldest command in the queue.
+    fn process_response(&self, response: Guarded) {
+        let state = *self.state.borrow();
+        match state {
+            State::WaitForInitialGuard => {
+                log::trace!("tmux: initial response #{}", response.number);
+                *self.state.borrow_mut() = State::Idle;
+                self.queue_command(Box::new(EnableFlowControl {
+                    pause_after: PAUSE_AFTER_SECS,
+                }));
+            }
+            State::WaitingForResponse => {
+                *self.state.borrow_mut() = State::Idle;
+                let cmd = match self.cmd_queue.borrow_mut().pop_front() {
+                    Some(cmd) => cmd,
+                    None => {
+                        log::error!(
+                            "tmux: response #{} has no pending command: {:?}",
+                            response.number,
+                            response
+                        );
+                        return;
+                    }
+                };
+                log::trace!(
+                    "tmux: response #{} for {:?}",
+                    response.number,
+                    cmd.get_command()
+                );
+                if response.error {
+                    self.report_error(cmd.pane(), Some(&cmd.get_command()), &response);
+                }
+                if *self.identifying.borrow() {
+                    // This must be the response to `ServerIdentity`,
+                    // as that is always the first command we send
+                    let identity = if response.error {
+                        None
+                    } else {
+                        Some(response.text().trim().to_owned())
+                    };
+                    self.identified(identity);
+                    return;
+                }
+                let domain_id = self.domain_id;
+                promise::spawn::spawn(async move {
+                    if let Err(err) = cmd.process_result(domain_id, &response) {
+                        log::error!("error processing result: {}", err);
+                    }
+                })
+                .detach();
+            }
+            State::Detached | State::Disconnected => {
+                log::trace!("tmux: ignoring response #{} after detach", response.number);
+            }
+            State::Idle => {
+                log::error!(
+                    "tmux: unexpected response #{} while idle: {:?}",
+                    response.number,
+                    response
+                );
+                if response.error {
+                    self.report_error(None, None, &response);
+                }
+            }
+        }
+    }
+
+    /// Shows an error reported by tmux in the local pane that mirrors
+    /// `pane`, falling back to the pane that is running tmux when the
+    /// error can't be attributed to a particular pane.
+    /// The error only affects the command that produced it; the
+    /// domain remains attached and continues with the next command.
+    fn report_error(&self, pane: Option<TmuxPaneId>, command: Option<&str>, response: &Guarded) {
+        log::error!(
+            "tmux: error response #{} to {:?}: {}",
+            response.number,
+            command,
+            response.text().trim()
+        );
+        let local_pane_id = match pane.and_then(|pane| self.get_pane(pane)) {
+            Some(local_pane) => local_pane.pane_id(),
+            None => *self.pane_id.borrow(),
+        };
+        let text = match command {
+            Some(command) => format!(
+                "error: command #{} `{}` failed: {}",
+                response.number,
+                command.trim_end(),
+                response.text().trim_end()
+            ),
+            None => format!(
+                "error: command #{} failed: {}",
+                response.number,
+                response.text().trim_end()
+            ),
+        };
+        write_to_pane(local_pane_id, format_message(&text));
+    }
+
+    /// Shows a message in the pane that is running tmux, so that the
+    /// user can see eg: why their session went away
+    pub(crate) fn emit_to_embedding(&self, text: &str) {
+        write_to_pane(*self.pane_id.borrow(), format_message(text));
+    }
+
+    /// Handles an asynchronous notification; these are not associated
+    /// with any particular command and may arrive at any time, including
+    /// while we are waiting for the response to a command.
+    fn process_notification(&self, event: Event) {
+        log::trace!("tmux: notification {:?}", event);
+        match event {
+            Event::Output { pane, text } => self.pane_output(pane, &text),
+            Event::Pause { pane } => {
+                log::trace!("tmux: pane %{} paused", pane);
+                self.flow.borrow_mut().pause(pane);
+                self.schedule_catch_up();
+            }
+            Event::Continue { pane } => {
+                // Output was discarded while the pane was paused
+                self.queue_capture(pane);
+            }
+            Event::SessionChanged { session, name } => {
+                log::trace!("tmux: attached to session ${} {}", session, name);
+                self.session.borrow_mut().replace(session);
+            }
+            Event::SessionRenamed { session, name } => {
+                // We target our session by id where we can, so that
+                // this doesn't affect where new windows are created
+                log::trace!("tmux: session {:?} renamed to {}", session, name);
+            }
+            Event::WindowAdd { window } => self.window_added(window),
+            Event::UnlinkedWindowAdd { window } => {
+                // The window belongs to some other session
+                log::trace!("tmux: ignoring unlinked window @{}", window);
+            }
+            Event::WindowClose { window } => self.window_closed(window),
+            Event::WindowRenamed { window, name } => self.window_renamed(window, name),
+            Event::LayoutChange { window, layout, .. } => self.layout_changed(window, &layout),
+            Event::Exit { reason } => match reason {
+                Some(reason) => self.emit_to_embedding(&format!("exited: {}", reason)),
+                None => self.emit_to_embedding("exited"),
+            },
+            _ => {}
+        }
+    }
+
+    /// Returns the `-t` target that causes new windows to be created
+    /// in our session, rather than in whichever session tmux considers
+    /// to be current.  The session id is preferred over its name, as
+    /// the session may have been renamed since we attached.
+    fn spawn_target(&self) -> Option<String> {
+        let window = self.config.window.as_deref().unwrap_or("");
+        if let Some(session) = *self.session.borrow() {
+            return Some(format!("${}:{}", session, window));
+        }
+        self.config
+            .session
+            .as_ref()
+            .map(|session| format!("={}:{}", session, window))
+    }
+
+    /// Requests that tmux resize our client.  During a live resize
+    /// we may be called many times in quick succession, so only one
+    /// request is in flight at a time, followed by one for the most
+    /// recent size once it has completed.
+    /// Nothing is sent until tmux has sent its initial response.
+    pub(crate) fn resize_client(&self, cols: u16, rows: u16) {
+        let (cols, rows) = clamp_window_size(cols, rows);
+        self.client_size.borrow_mut().replace((cols, rows));
+        if *self.resize_pending.borrow() || self.is_detached() {
+            return;
+        }
+        *self.resize_pending.borrow_mut() = true;
+        self.queue_command(Box::new(RefreshClient { cols, rows }));
+    }
+
+    /// Called when tmux has processed a refresh-client request for `size`
+    pub(crate) fn client_resized(&self, size: (u16, u16)) {
+        *self.resize_pending.borrow_mut() = false;
+        let latest = *self.client_size.borrow();
+        if let Some((cols, rows)) = latest {
+            if (cols, rows) != size {
+                self.resize_client(cols, rows);
+            }
+        }
+    }
+
+    /// tmux has changed the layout of `window`, which happens when it
+    /// is resized, or when its panes are split, resized or closed.
+    /// Rather than assuming that our own requests were honored, the
+    /// local tab is arranged according to the layout.
+    fn layout_changed(&self, window: TmuxWindowId, layout: &str) {
+        let layout = match parse_layout(layout) {
+            Ok(layout) => layout,
+            Err(err) => {
+                log::error!("tmux: ignoring layout for window @{}: {:#}", window, err);
+                return;
+            }
+        };
+        if let Err(err) = self.apply_layout(window, &layout, None) {
+            log::error!(
+                "tmux: failed to apply the layout of window @{}: {:#}",
+                window,
+                err
+            );
+        }
+    }
+
+    /// Arranges the local tab that mirrors `window` according to its
+    /// tmux `layout`, creating local panes for new remote panes and
+    /// removing those that are no longer in the window.
+    /// If we don't have a tab for the window yet, one is created in
+    /// `gui_window`; when that is not set, the window is ignored.
+    fn apply_layout(
+        &self,
+        window: TmuxWindowId,
+        layout: &LayoutCell,
+        gui_window: Option<WindowId>,
+    ) -> anyhow::Result<()> {
+        let mux = Mux::get().expect("to be called on main thread");
+        let tab = self
+            .tabs
+            .borrow()
+            .get(&window)
+            .and_then(|tab_id| mux.get_tab(*tab_id));
+        if tab.is_none() && gui_window.is_none() {
+            log::trace!("tmux: ignoring layout of unmirrored window @{}", window);
+            return Ok(());
+        }
+
+        let size = cell_size(layout.width, layout.height);
+        let remote_panes = layout.panes();
+        let mut local_panes = HashMap::new();
+        for pane in &remote_panes {
+            let local_pane = match self.get_pane(*pane) {
+                Some(local_pane) => local_pane,
+                None => {
+                    let local_pane = self.create_pane(window, *pane, size)?;
+                    mux.add_pane(&local_pane)?;
+                    local_pane
+                }
+            };
+            local_panes.insert(*pane, local_pane);
+        }
+
+        // Keep the same pane active, if it is still there
+        let active = tab
+            .as_ref()
+            .and_then(|tab| tab.get_active_pane())
+            .and_then(|pane| pane.downcast_ref::<TmuxPane>().map(TmuxPane::tmux_pane_id));
+        let root = layout_to_pane_node(layout, active);
+        let make_pane = |entry: PaneEntry| {
+            let local_pane = Rc::clone(&local_panes[&(entry.pane_id as TmuxPaneId)]);
+            if let Some(tmux_pane) = local_pane.downcast_ref::<TmuxPane>() {
+                tmux_pane.resize_terminal(entry.size);
+            }
+            mux.notify(MuxNotification::PaneOutput(local_pane.pane_id()));
+            local_pane
+        };
+
+        match (tab, gui_window) {
+            (Some(tab), _) => tab.sync_with_pane_tree(size, root, make_pane),
+            (None, Some(gui_window)) => {
+                let tab = Rc::new(Tab::new(&size));
+                tab.sync_with_pane_tree(size, root, make_pane);
+                mux.add_tab_no_panes(&tab);
+                mux.add_tab_to_window(&tab, gui_window)?;
+                self.tabs.borrow_mut().insert(window, tab.tab_id());
+            }
+            (None, None) => unreachable!(),
+        }
+
+        // Panes that are no longer in the window were closed, or
+        // moved to some other window, in which case they are mirrored
+        // afresh when we learn of their new home
+        let mut dead = vec![];
+        self.panes.borrow_mut().retain(|pane, pane_id| {
+            if remote_panes.contains(pane) {
+                return true;
+            }
+            match mux.get_pane(*pane_id) {
+                Some(local_pane) => match local_pane.downcast_ref::<TmuxPane>() {
+                    Some(tmux_pane) if tmux_pane.tmux_window_id() == window => {
+                        tmux_pane.mark_dead();
+                        dead.push(*pane_id);
+                        false
+                    }
+                    _ => true,
+                },
+                None => false,
+            }
+        });
+        remove_panes(dead);
+        Ok(())
+    }
+
+    /// Called when the local pane that mirrors the remote `pane` has
+    /// been resized.  The size of the local tab determines the size of
+    /// our client, and so of the remote window; within the window, the
+    /// remote pane is resized to match, so that moving a split locally
+    /// moves it remotely too.
+    pub(crate) fn pane_resized(&self, window: TmuxWindowId, pane: TmuxPaneId, size: PtySize) {
+        // We are called while the tab is resizing its panes, so
+        // look at it once it has finished
+        let domain_id = self.domain_id;
+        promise::spawn::spawn(async move {
+            let tmux = match get_tmux_domain(domain_id) {
+                Some(tmux) => tmux,
+                None => return,
+            };
+            let tab_id = tmux.tabs.borrow().get(&window).cloned();
+            let tab = tab_id.and_then(|tab_id| Mux::get()?.get_tab(tab_id));
+            match tab {
+                Some(tab) if tab.count_panes() > 1 => {
+                    let tab_size = tab.get_size();
+                    tmux.resize_client(tab_size.cols, tab_size.rows);
+                    tmux.queue_command(Box::new(ResizePane {
+                        pane,
+                        cols: size.cols,
+                        rows: size.rows,
+                    }));
+                }
+                _ => tmux.resize_client(size.cols, size.rows),
+            }
+        })
+        .detach();
+    }
+
+    /// A window was added to the session, either by us or by another
+    /// client.  We don't know its panes yet, so ask tmux for the full
+    /// list; `sync_panes` only creates tabs for the windows that we
+    /// don't already mirror, such as those created by our own `spawn`.
+    fn window_added(&self, window: TmuxWindowId) {
+        log::trace!("tmux: window @{} added", window);
+        self.queue_command(Box::new(ListAllPanes));
+    }
+
+    /// The remote `window` was renamed; this is reflected in the title
+    /// of the local tab(s) that mirror its panes
+    fn window_renamed(&self, window: TmuxWindowId, name: String) {
+        self.window_names.borrow_mut().insert(window, name.clone());
+        let mux = match Mux::get() {
+            Some(mux) => mux,
+            None => return,
+        };
+        for pane_id in self.panes.borrow().values() {
+            if let Some(local_pane) = mux.get_pane(*pane_id) {
+                if let Some(tmux_pane) = local_pane.downcast_ref::<TmuxPane>() {
+                    if tmux_pane.tmux_window_id() == window {
+                        tmux_pane.set_window_name(Some(name.clone()));
+                        mux.notify(MuxNotification::PaneOutput(*pane_id));
+                    }
+                }
+            }
+        }
+    }
+
+    /// The remote `window` was closed; remove the local panes that
+    /// were mirroring its panes.  This is a no-op for a window that
+    /// we never mirrored.
+    fn window_closed(&self, window: TmuxWindowId) {
+        self.window_names.borrow_mut().remove(&window);
+        self.tabs.borrow_mut().remove(&window);
+        let mut dead = vec![];
+        self.panes.borrow_mut().retain(|_, pane_id| {
+            let local_pane = match Mux::get().and_then(|mux| mux.get_pane(*pane_id)) {
+                Some(pane) => pane,
+                None => return false,
+            };
+            match local_pane.downcast_ref::<TmuxPane>() {
+                Some(tmux_pane) if tmux_pane.tmux_window_id() == window => {
+                    tmux_pane.mark_dead();
+                    dead.push(*pane_id);
+                    false
+                }
+                _ => true,
+            }
+        });
+
+        remove_panes(dead);
+    }
+
+    /// Marks all of the local panes as dead and removes them from the
+    /// mux, without asking tmux to kill the remote panes; used when we
+    /// detach from tmux, so that the session can be attached again later.
+    fn mark_panes_dead(&self) {
+        let mux = match Mux::get() {
+            Some(mux) => mux,
+            None => return,
+        };
+        let mut dead = vec![];
+        for (_, pane_id) in self.panes.borrow_mut().drain() {
+            if let Some(pane) = mux.get_pane(pane_id) {
+                if let Some(tmux_pane) = pane.downcast_ref::<TmuxPane>() {
+                    tmux_pane.mark_dead();
+                    dead.push(pane_id);
+                }
+            }
+        }
+        self.tabs.borrow_mut().clear();
+        remove_panes(dead);
+    }
+
+    /// Called when the control mode session has ended, either because
+    /// we asked to detach or because tmux exited.  The local panes are
+    /// marked dead, the mux is told that they are gone, and no further
+    /// commands will be sent.  When we ask to detach, tmux then ends
+    /// the control channel too, so this only acts the first time.
+    pub(crate) fn detached(&self) {
+        if *self.state.borrow() == State::Detached {
+            return;
+        }
+        *self.state.borrow_mut() = State::Detached;
+        self.reset_session();
+        self.mark_panes_dead();
+        if let Some(mux) = Mux::get() {
+            mux.domain_was_detached(self.domain_id);
+        }
+    }
+
+    /// Called when the control channel has gone away without tmux
+    /// detaching us, so the remote session is most likely still alive.
+    /// The local panes are left in place, with a note explaining what
+    /// happened, until either `reattach` or `detached` is called.
+    pub(crate) fn disconnected(&self) {
+        if self.is_detached() {
+            return;
+        }
+        log::warn!(
+            "tmux: lost the control channel for domain {}",
+            self.domain_id
+        );
+        *self.state.borrow_mut() = State::Disconnected;
+        self.reset_session();
+        for pane_id in self.panes.borrow().values() {
+            write_to_pane(
+                *pane_id,
+                format_message("connection lost; waiting to reattach"),
+            );
+        }
+    }
+
+    /// Returns true if no further commands can be sent to tmux,
+    /// whether or not we can reattach
+    fn is_detached(&self) -> bool {
+        match *self.state.borrow() {
+            State::Detached | State::Disconnected => true,
+            _ => false,
+        }
+    }
+
+    /// Discards the state associated with the control channel
+    fn reset_session(&self) {
+        let cancelled = std::mem::take(&mut *self.cmd_queue.borrow_mut());
+        for cmd in cancelled {
+            cmd.cancel();
+        }
+        self.deferred.borrow_mut().clear();
+        self.pending_output.borrow_mut().clear();
+        self.flow.borrow_mut().clear();
+        *self.resize_pending.borrow_mut() = false;
+        *self.resyncing.borrow_mut() = false;
+    }
+
+    /// Called with the identity of the tmux server and session that
+    /// we attached to.  If a domain that lost its control channel was
+    /// attached to the same ones, it takes over our channel and carries
+    /// on where it left off, and this domain is removed from the mux;
+    /// otherwise we start afresh.
+    fn identified(&self, identity: Option<String>) {
+        *self.identifying.borrow_mut() = false;
+        let previous = identity
+            .as_ref()
+            .and_then(|identity| get_disconnected_tmux_domain(*self.pane_id.borrow(), identity));
+        log::trace!(
+            "tmux: domain {} is attached to {:?}",
+            self.domain_id,
+            identity
+        );
+        *self.identity.borrow_mut() = identity;
+
+        if let Some(previous) = previous {
+            match previous.reattach(self) {
+                Ok(()) => {
+                    self.hand_over(&previous);
+                    self.successor.borrow_mut().replace(previous);
+                    return;
+                }
+                Err(err) => log::error!("tmux: failed to reattach: {:#}", err),
+            }
+        }
+        // The notifications that we held are processed by our caller
+        self.queue_initial_commands();
+    }
+
+    /// Removes this domain, which never mirrored anything, from the mux
+    /// now that `successor` has taken over our control channel
+    fn hand_over(&self, successor: &TmuxDomainState) {
+        let mux = match Mux::get() {
+            Some(mux) => mux,
+            None => return,
+        };
+        mux.remove_domain(self.domain_id);
+        // We may have been registered under the same name, in which
+        // case the name refers to the successor again
+        if let Some(domain) = mux.get_domain(successor.domain_id) {
+            if mux.get_domain_by_name(domain.domain_name()).is_none() {
+                mux.add_domain(&domain);
+            }
+        }
+    }
+
+    /// Returns the domain that took over our control channel, if any.
+    /// Whoever is feeding us the control channel should feed it to that
+    /// domain from now on.
+    pub(crate) fn take_successor(&self) -> Option<Arc<TmuxDomainState>> {
+        self.successor.borrow_mut().take()
+    }
+
+    /// Continues a session that was `disconnected` over the control
+    /// channel established by `channel`, a new domain that has found
+    /// that it is attached to the same tmux server and session that we
+    /// were.  The remote panes are listed again and matched up with the
+    /// local panes by id; see `sync_panes`.
+    pub(crate) fn reattach(&self, channel: &TmuxDomainState) -> anyhow::Result<()> {
+        if *self.state.borrow() != State::Disconnected {
+            anyhow::bail!("tmux domain {} is not disconnected", self.domain_id);
+        }
+        if self.identity.borrow().is_none() || *self.identity.borrow() != *channel.identity.borrow()
+        {
+            anyhow::bail!(
+                "tmux domain {} was attached to {:?}, not {:?}",
+                self.domain_id,
+                self.identity.borrow(),
+                channel.identity.borrow()
+            );
+        }
+        let pane_id = *channel.pane_id.borrow();
+        log::trace!(
+            "tmux: reattaching domain {} via pane {}",
+            self.domain_id,
+            pane_id
+        );
+        *self.pane_id.borrow_mut() = pane_id;
+        *self.parser.borrow_mut() =
+            std::mem::replace(&mut *channel.parser.borrow_mut(), Parser::new());
+        *self.initial_block_seen.borrow_mut() = true;
+        self.session.borrow_mut().take();
+        *self.resyncing.borrow_mut() = true;
+        *self.cmd_queue.borrow_mut() = std::mem::take(&mut *channel.cmd_queue.borrow_mut());
+        let deferred = std::mem::take(&mut *channel.deferred.borrow_mut());
+        *self.state.borrow_mut() =
+            std::mem::replace(&mut *channel.state.borrow_mut(), State::Detached);
+
+        self.queue_initial_commands();
+        for event in deferred {
+            self.process_notification(event);
+        }
+        Ok(())
+    }
+
+    /// Queues the commands that populate the domain once tmux has
+    /// sent its initial response
+    fn queue_initial_commands(&self) {
+        match &self.config.session {
+            // The panes are listed once we're in the right session
+            Some(session) => self.queue_command(Box::new(SwitchSession {
+                session: session.clone(),
+                create: true,
+            })),
+            None => self.queue_command(Box::new(ListAllPanes)),
+        }
+    }
+
+    /// Creates tabs for any remote windows that we are not yet mirroring,
+    /// and brings those that we are up to date with their layouts.
+    /// This is used both when we first attach and when re-attaching,
+    /// so it must not create more than one tab per remote window.
+    /// After `reattach`, the contents of the surviving panes are also
+    /// refreshed, and the panes that went away while we were
+    /// disconnected are removed.
+    pub(crate) fn sync_panes(&self, items: Vec<PaneItem>) -> anyhow::Result<()> {
+        let mux = Mux::get().expect("to be called on main thread");
+
+        let mut builder = None;
+        let window_id = match *self.gui_window.borrow() {
+            Some(window_id) if mux.get_window(window_id).is_some() => window_id,
+            _ => {
+                let b = mux.new_empty_window();
+                let window_id = *b;
+                builder = Some(b);
+                window_id
+            }
+        };
+        self.gui_window.borrow_mut().replace(window_id);
+
+        // list-panes -a reports the panes of every session; when we
+        // were asked for a specific session, only mirror that one
+        let session = match self.config.session {
+            Some(_) => *self.session.borrow(),
+            None => None,
+        };
+
+        let resyncing = std::mem::replace(&mut *self.resyncing.borrow_mut(), false);
+        let mut listed = HashSet::new();
+        // Each pane of a window reports the layout of the whole window
+        let mut layouts: Vec<(TmuxWindowId, String)> = vec![];
+        let mut history_sizes = vec![];
+
+        for item in items {
+            if session.map_or(false, |session| session != item.session_id) {
+                continue;
+            }
+            listed.insert(item.pane_id);
+            history_sizes.push((item.pane_id, item.history_size));
+            if let Some(name) = &item.window_name {
+                self.window_names
+                    .borrow_mut()
+                    .insert(item.window_id, name.clone());
+                if let Some(local_pane) = self.get_pane(item.pane_id) {
+                    if let Some(tmux_pane) = local_pane.downcast_ref::<TmuxPane>() {
+                        tmux_pane.set_window_name(Some(name.clone()));
+                    }
+                }
+            }
+            if !layouts.iter().any(|(window, _)| *window == item.window_id) {
+                layouts.push((item.window_id, item.window_layout));
+            }
+        }
+
+        if resyncing {
+            self.remove_unlisted_panes(&listed);
+        }
+
+        for (window, layout) in layouts {
+            let layout = match parse_layout(&layout) {
+                Ok(layout) => layout,
+                Err(err) => {
+                    log::error!("tmux: ignoring window @{}: {:#}", window, err);
+                    continue;
+                }
+            };
+            if resyncing && !self.resync_window(window, &layout) {
+                continue;
+            }
+            self.apply_layout(window, &layout, Some(window_id))?;
+        }
+
+        // Now that every pane has a local counterpart, let them know
+        // how much history tmux holds, so that it can be scrolled into
+        for (pane, size) in history_sizes {
+            self.history_size(pane, size);
+        }
+
+        if resyncing {
+            // Output was lost while we were disconnected.  This is
+            // queued after any resize requested by `resync_window` so
+            // that the panes are captured at their new size.
+            let mut panes: Vec<TmuxPaneId> = self.panes.borrow().keys().cloned().collect();
+            panes.sort();
+            for pane in panes {
+                self.queue_capture(pane);
+            }
+        }
+
+        // Dropping the builder announces the new window, now that
+        // it has some tabs in it
+        drop(builder);
+        Ok(())
+    }
+
+    /// Reconciles a remote window that we were mirroring before
+    /// `reattach` with its local tab.  Returns false if its `layout`
+    /// should not be applied, which is the case when the tab or
+    /// some of its panes were closed while we were disconnected,
+    /// which would have killed the remote panes had we been attached,
+    /// or when the tab was resized, in which case the remote window is
+    /// resized to match rather than resizing the tab.  Either way, the
+    /// `%layout-change` that follows arranges the tab as usual.
+    /// Killing the remote panes by id is only safe because `reattach`
+    /// requires that we are talking to the same server and session.
+    fn resync_window(&self, window: TmuxWindowId, layout: &LayoutCell) -> bool {
+        let tab_id = match self.tabs.borrow().get(&window) {
+            Some(tab_id) => *tab_id,
+            None => return true,
+        };
+        let tab = Mux::get().and_then(|mux| mux.get_tab(tab_id));
+        let mut closed = false;
+        for pane in layout.panes() {
+            let alive = match &tab {
+                Some(tab) => self.get_pane(pane).map_or(false, |local_pane| {
+                    !local_pane.is_dead() && tab.contains_pane(local_pane.pane_id())
+                }),
+                None => false,
+            };
+            if !alive && self.panes.borrow().contains_key(&pane) {
+                log::trace!("tmux: pane %{} was closed while disconnected", pane);
+                self.forget_pane(pane);
+                self.queue_command(Box::new(KillPane { pane }));
+                closed = true;
+            }
+        }
+        let tab = match tab {
+            Some(tab) => tab,
+            None => {
+                self.tabs.borrow_mut().remove(&window);
+                return false;
+            }
+        };
+        if closed {
+            return false;
+        }
+
+        let size = tab.get_size();
+        if (u64::from(size.cols), u64::from(size.rows)) != (layout.width, layout.height) {
+            log::trace!(
+                "tmux: window @{} is {}x{} but the local tab is {}x{}",
+                window,
+                layout.width,
+                layout.height,
+                size.cols,
+                size.rows
+            );
+            self.resize_client(size.cols, size.rows);
+            return false;
+        }
+        true
+    }
+
+    fn forget_pane(&self, pane: TmuxPaneId) {
+        self.panes.borrow_mut().remove(&pane);
+    }
+
+    /// Removes the local panes whose remote panes are not in `listed`,
+    /// because they went away while we were disconnected
+    fn remove_unlisted_panes(&self, listed: &HashSet<TmuxPaneId>) {
+        let gone: Vec<TmuxPaneId> = self
+            .panes
+            .borrow()
+            .keys()
+            .filter(|pane| !listed.contains(pane))
+            .cloned()
+            .collect();
+        let mut dead = vec![];
+        for pane in gone {
+            log::trace!("tmux: pane %{} went away while disconnected", pane);
+            if let Some(local_pane) = self.get_pane(pane) {
+                if let Some(tmux_pane) = local_pane.downcast_ref::<TmuxPane>() {
+                    tmux_pane.mark_dead();
+                }
+                dead.push(local_pane.pane_id());
+            }
+            self.forget_pane(pane);
+        }
+        remove_panes(dead);
+    }
+
+    /// Returns the local pane that mirrors the remote tmux `pane`
+    fn get_pane(&self, pane: TmuxPaneId) -> Option<Rc<dyn Pane>> {
+        let pane_id = *self.panes.borrow().get(&pane)?;
+        Mux::get()?.get_pane(pane_id)
+    }
+
+    fn pane_output(&self, pane: TmuxPaneId, bytes: &[u8]) {
+        match self.get_pane(pane) {
+            Some(local_pane) => {
+                let apply = self.flow.borrow_mut().output(pane, bytes.len());
+                self.schedule_catch_up();
+                if !apply {
+                    return;
+                }
+                if let Some(tmux_pane) = local_pane.downcast_ref::<TmuxPane>() {
+                    tmux_pane.advance_bytes(bytes);
+                    if let Some(mux) = Mux::get() {
+                        mux.notify(MuxNotification::PaneOutput(local_pane.pane_id()));
+                    }
+                }
+            }
+            None => {
+                log::trace!("tmux: buffering output for unknown pane %{}", pane);
+                self.pending_output
+                    .borrow_mut()
+                    .push(pane, bytes, Instant::now());
+            }
+        }
+    }
+
+    /// Arranges for `caught_up` to be called once the main thread has
+    /// processed the control mode output that is already queued up
+    fn schedule_catch_up(&self) {
+        if !self.flow.borrow_mut().schedule_catch_up() {
+            return;
+        }
+        let domain_id = self.domain_id;
+        promise::spawn::spawn(async move {
+            if let Some(tmux) = get_tmux_domain(domain_id) {
+                tmux.caught_up();
+            }
+        })
+        .detach();
+    }
+
+    /// Resumes the panes that were paused while we were behind
+    fn caught_up(&self) {
+        let panes = self.flow.borrow_mut().caught_up();
+        let supported = self.flow.borrow().supported;
+        for pane in panes {
+            if self.get_pane(pane).is_none() {
+                continue;
+            }
+            if supported {
+                self.queue_command(Box::new(ContinuePane { pane }));
+            } else {
+                self.queue_capture(pane);
+            }
+        }
+    }
+
+    pub(crate) fn set_flow_control_supported(&self, supported: bool) {
+        self.flow.borrow_mut().supported = supported;
+    }
+
+    /// Fetches the contents of the remote `pane` and the position of
+    /// its cursor, and replaces those of the local pane with them.
+    /// This is used to recover from discarding some of its output.
+    fn queue_capture(&self, pane: TmuxPaneId) {
+        let captured = Rc::new(RefCell::new(None));
+        self.queue_command(Box::new(CapturePane {
+            pane,
+            captured: Rc::clone(&captured),
+        }));
+        self.queue_command(Box::new(CaptureCursor { pane, captured }));
+    }
+
+    /// Replaces the contents of the local pane with `text`, the
+    /// output of `capture-pane`, and moves its cursor to `cursor`
+    pub(crate) fn pane_captured(&self, pane: TmuxPaneId, text: &[u8], cursor: (usize, usize)) {
+        let local_pane = match self.get_pane(pane) {
+            Some(local_pane) => local_pane,
+            None => return,
+        };
+        if let Some(tmux_pane) = local_pane.downcast_ref::<TmuxPane>() {
+            let mut text = text;
+            while text.last() == Some(&b'\n') {
+                text = &text[..text.len() - 1];
+            }
+            let mut bytes = b"\x1b[0m\x1b[H\x1b[2J".to_vec();
+            for (idx, line) in text.split(|&b| b == b'\n').enumerate() {
+                if idx > 0 {
+                    bytes.extend_from_slice(b"\r\n");
+                }
+                bytes.extend_from_slice(line);
+            }
+            // Put the cursor back where tmux has it, so that further
+            // output is applied in the right place
+            let (x, y) = cursor;
+            bytes.extend_from_slice(format!("\x1b[{};{}H", y + 1, x + 1).as_bytes());
+            tmux_pane.advance_bytes(&bytes);
+            if let Some(mux) = Mux::get() {
+                mux.notify(MuxNotification::PaneOutput(local_pane.pane_id()));
+            }
+        }
+    }
+
+    /// Records the number of rows of history that tmux holds for `pane`
+    pub(crate) fn history_size(&self, pane: TmuxPaneId, size: u64) {
+        if let Some(local_pane) = self.get_pane(pane) {
+            if let Some(tmux_pane) = local_pane.downcast_ref::<TmuxPane>() {
+                tmux_pane.set_history_size(size);
+            }
+        }
+    }
+
+    /// Returns the stable row index of the top of the screen of the
+    /// local pane that mirrors `pane`
+    pub(crate) fn physical_top(&self, pane: TmuxPaneId) -> Option<StableRowIndex> {
+        let local_pane = self.get_pane(pane)?;
+        let tmux_pane = local_pane.downcast_ref::<TmuxPane>()?;
+        Some(tmux_pane.physical_top())
+    }
+
+    /// Stores rows of history that were fetched for `pane`, the
+    /// output of `capture-pane -S -E`
+    pub(crate) fn history_captured(
+        &self,
+        pane: TmuxPaneId,
+        rows: Range<StableRowIndex>,
+        text: &[u8],
+    ) {
+        let local_pane = match self.get_pane(pane) {
+            Some(local_pane) => local_pane,
+            None => return,
+        };
+        if let Some(tmux_pane) = local_pane.downcast_ref::<TmuxPane>() {
+            tmux_pane.history_captured(rows, text);
+            if let Some(mux) = Mux::get() {
+                mux.notify(MuxNotification::PaneOutput(local_pane.pane_id()));
+            }
+        }
+    }
+
+    /// Creates a local pane to mirror the remote tmux `pane`.
+    /// The caller is responsible for adding it to a tab and the mux.
+    pub(crate) fn create_pane(
+        &self,
+        window: TmuxWindowId,
+        pane: TmuxPaneId,
+        size: PtySize,
+    ) -> anyhow::Result<Rc<dyn Pane>> {
+        let tmux_pane = TmuxPane::new(self.domain_id, window, pane, size)?;
+        tmux_pane.set_window_name(self.window_names.borrow().get(&window).cloned());
+        if let Some(pending) = self.pending_output.borrow_mut().take(pane) {
+            tmux_pane.advance_bytes(&pending);
+        }
+        let local_pane: Rc<dyn Pane> = Rc::new(tmux_pane);
+        self.panes.borrow_mut().insert(pane, local_pane.pane_id());
+        Ok(local_pane)
+    }
+
+    /// Sends a command to tmux, returning a future that resolves to
+    /// its response once tmux has processed it.  The future resolves
+    /// to an error if tmux reports that the command failed, or if we
+    /// detach before the response arrives.
+    /// Use `queue_command` if the response isn't needed.
+    pub(crate) fn send_command(&self, cmd: Box<dyn TmuxCommand>) -> Future<Guarded> {
+        let mut promise = Promise::new();
+        let future = promise.get_future().unwrap();
+        self.queue_command(Box::new(Awaited {
+            cmd,
+            promise: RefCell::new(Some(promise)),
+        }));
+        future
+    }
+
+    /// Adds a command to the queue, sending it to tmux as soon as
+    /// any outstanding command has completed
+    pub(crate) fn queue_command(&self, cmd: Box<dyn TmuxCommand>) {
+        if self.is_detached() {
+            cmd.cancel();
+            return;
+        }
+        self.cmd_queue.borrow_mut().push_back(cmd);
+        self.send_next_command();
     }
 
     fn send_next_command(&self) {
         if *self.state.borrow() != State::Idle {
             return;
         }
-        if let Some(first) = self.cmd_queue.borrow().front() {
-            let cmd = first.get_command();
-            log::error!("sending cmd {:?}", cmd);
-            let mux = Mux::get().expect("to be called on main thread");
-            if let Some(pane) = mux.get_pane(self.pane_id) {
+        let cmd = match self.cmd_queue.borrow().front() {
+            Some(first) => first.get_command(),
+            None => return,
+        };
+        self.write_command(&cmd);
+        *self.state.borrow_mut() = State::WaitingForResponse;
+    }
+
+    /// Writes `cmd` to the pane that is running tmux
+    fn write_command(&self, cmd: &str) {
+        log::trace!("tmux: sending {:?}", cmd);
+        let mux = Mux::get().expect("to be called on main thread");
+        let pane_id = *self.pane_id.borrow();
+        match mux.get_pane(pane_id) {
+            Some(pane) => {
                 let mut writer = pane.writer();
-                let _ = write!(writer, "{}", cmd);
+                if let Err(err) = write!(writer, "{}", cmd).and_then(|_| writer.flush()) {
+                    // The control channel has gone away; the caller
+                    // is responsible for cleaning up local state
+                    log::error!("tmux: failed to send {:?}: {}", cmd.trim_end(), err);
+                }
             }
-            *self.state.borrow_mut() = State::WaitingForResponse;
+            None => log::trace!("tmux: pane {} is gone; not sending {:?}", pane_id, cmd),
         }
     }
 }
 
 impl TmuxDomain {
     pub fn new(pane_id: PaneId) -> Self {
+        Self::with_config(pane_id, TmuxDomainConfig::default())
+    }
+
+    pub fn with_config(pane_id: PaneId, config: TmuxDomainConfig) -> Self {
         let domain_id = alloc_domain_id();
         let parser = RefCell::new(Parser::new());
-        let mut cmd_queue = VecDeque::<Box<dyn TmuxCommand>>::new();
-        cmd_queue.push_back(Box::new(ListAllPanes));
+        let name = match &config.session {
+            Some(session) => format!("tmux:{}", session),
+            None => "tmux".to_owned(),
+        };
         let inner = Arc::new(TmuxDomainState {
             domain_id,
-            pane_id,
+            pane_id: RefCell::new(pane_id),
             parser,
             state: RefCell::new(State::WaitForInitialGuard),
-            cmd_queue: RefCell::new(cmd_queue),
+            cmd_queue: RefCell::new(VecDeque::new()),
+            deferred: RefCell::new(vec![]),
+            panes: RefCell::new(HashMap::new()),
+            tabs: RefCell::new(HashMap::new()),
+            resyncing: RefCell::new(false),
+            identity: RefCell::new(None),
+            identifying: RefCell::new(true),
+            successor: RefCell::new(None),
+            pending_output: RefCell::new(PendingOutput::default()),
+            flow: RefCell::new(FlowControl::default()),
+            window_names: RefCell::new(HashMap::new()),
+            gui_window: RefCell::new(None),
+            client_size: RefCell::new(None),
+            resize_pending: RefCell::new(false),
+            preamble: RefCell::new(0),
+            initial_block_seen: RefCell::new(false),
+            session: RefCell::new(None),
+            config,
         });
-        Self { inner }
+        // The remote panes are listed once we know that we aren't
+        // reattaching a domain that was already mirroring them
+        inner.queue_command(Box::new(ServerIdentity));
+        Self { inner, name }
+    }
+
+    /// Returns true if the control channel was lost and the domain
+    /// is waiting to be reattached
+    pub fn is_disconnected(&self) -> bool {
+        *self.inner.state.borrow() == State::Disconnected
     }
 
     fn send_next_command(&self) {
@@ -206,23 +1334,117 @@ impl TmuxDomain {
 impl Domain for TmuxDomain {
     async fn spawn(
         &self,
-        _size: PtySize,
-        _command: Option<CommandBuilder>,
-        _command_dir: Option<String>,
-        _window: WindowId,
+        size: PtySize,
+        command: Option<CommandBuilder>,
+        command_dir: Option<String>,
+        window: WindowId,
     ) -> anyhow::Result<Rc<Tab>> {
-        anyhow::bail!("Spawn not yet implemented for TmuxDomain");
+        if self.state() == DomainState::Detached {
+            anyhow::bail!("tmux domain is detached");
+        }
+        let mut promise = Promise::new();
+        let future = promise.get_future().unwrap();
+        self.inner.queue_command(Box::new(NewWindow {
+            target: self.inner.spawn_target(),
+            cwd: command_dir,
+            command: remote_argv(command),
+            promise: RefCell::new(Some(promise)),
+        }));
+        let (tmux_window, tmux_pane) = future.await?;
+
+        // The new window takes on the size of the client; make it
+        // match the size of the local tab instead
+        let (cols, rows) = clamp_window_size(size.cols, size.rows);
+        self.inner.queue_command(Box::new(ResizeWindow {
+            window: tmux_window,
+            cols,
+            rows,
+        }));
+
+        let mux = Mux::get().unwrap();
+        // The listing that follows `%window-add` may have beaten us to it
+        let existing = self.inner.tabs.borrow().get(&tmux_window).cloned();
+        if let Some(tab) = existing.and_then(|tab_id| mux.get_tab(tab_id)) {
+            return Ok(tab);
+        }
+
+        let pane = self.inner.create_pane(tmux_window, tmux_pane, size)?;
+        let tab = Rc::new(Tab::new(&size));
+        tab.assign_pane(&pane);
+
+        mux.add_tab_and_active_pane(&tab)?;
+        mux.add_tab_to_window(&tab, window)?;
+        self.inner
+            .tabs
+            .borrow_mut()
+            .insert(tmux_window, tab.tab_id());
+
+        Ok(tab)
     }
 
     async fn split_pane(
         &self,
-        _command: Option<CommandBuilder>,
-        _command_dir: Option<String>,
-        _tab: TabId,
-        _pane_id: PaneId,
-        _direction: SplitDirection,
+        command: Option<CommandBuilder>,
+        command_dir: Option<String>,
+        tab: TabId,
+        pane_id: PaneId,
+        direction: SplitDirection,
     ) -> anyhow::Result<Rc<dyn Pane>> {
-        anyhow::bail!("split_pane not yet implemented for TmuxDomain");
+        if self.state() == DomainState::Detached {
+            anyhow::bail!("tmux domain is detached");
+        }
+        let mux = Mux::get().unwrap();
+        let tmux_pane = match mux
+            .get_pane(pane_id)
+            .as_ref()
+            .and_then(|pane| pane.downcast_ref::<TmuxPane>())
+        {
+            Some(tmux_pane) => tmux_pane.tmux_pane_id(),
+            None => anyhow::bail!("invalid pane id {}", pane_id),
+        };
+
+        let mut promise = Promise::new();
+        let future = promise.get_future().unwrap();
+        self.inner.queue_command(Box::new(SplitWindow {
+            pane: tmux_pane,
+            direction,
+            cwd: command_dir,
+            command: remote_argv(command),
+            promise: RefCell::new(Some(promise)),
+        }));
+        let (tmux_window, new_pane) = future.await?;
+
+        // tmux follows up with `%layout-change` for the window, which
+        // places the new pane in the tab; it may already have done so
+        if let Some(pane) = self.inner.get_pane(new_pane) {
+            return Ok(pane);
+        }
+
+        // Otherwise, put it where tmux put it; the layout sorts out
+        // the sizes once it arrives
+        let tab = match mux.get_tab(tab) {
+            Some(t) => t,
+            None => anyhow::bail!("Invalid tab id {}", tab),
+        };
+        let pane_index = match tab
+            .iter_panes()
+            .iter()
+            .find(|p| p.pane.pane_id() == pane_id)
+        {
+            Some(p) => p.index,
+            None => anyhow::bail!("invalid pane id {}", pane_id),
+        };
+        let split_size = match tab.compute_split_size(pane_index, direction) {
+            Some(s) => s,
+            None => anyhow::bail!("invalid pane index {}", pane_index),
+        };
+
+        let pane = self
+            .inner
+            .create_pane(tmux_window, new_pane, split_size.second)?;
+        tab.split_and_insert(pane_index, direction, Rc::clone(&pane))?;
+        mux.add_pane(&pane)?;
+        Ok(pane)
     }
 
     fn domain_id(&self) -> DomainId {
@@ -230,18 +1452,645 @@ impl Domain for TmuxDomain {
     }
 
     fn domain_name(&self) -> &str {
-        "tmux"
+        &self.name
     }
 
     async fn attach(&self) -> anyhow::Result<()> {
+        // Any panes that we already know about are skipped when
+        // the listing is processed, so this is safe to repeat.
+        // Wait for it so that the tabs exist when we return.
+        self.inner.send_command(Box::new(ListAllPanes)).await?;
         Ok(())
     }
 
     fn detach(&self) -> anyhow::Result<()> {
-        anyhow::bail!("detach not implemented for TmuxDomain");
+        match *self.inner.state.borrow() {
+            State::Detached => return Ok(()),
+            // There's no channel to tell tmux about it, but we no
+            // longer want to reattach
+            State::Disconnected => {}
+            // Send this immediately rather than queueing it behind
+            // any outstanding commands; tmux will respond with %exit
+            _ => self.inner.write_command(&DetachClient.get_command()),
+        }
+        self.inner.detached();
+        Ok(())
     }
 
     fn state(&self) -> DomainState {
-        DomainState::Attached
+        if self.inner.is_detached() {
+            DomainState::Detached
+        } else {
+            DomainState::Attached
+        }
+    }
+}
+
+/// Clamps a window size to the range accepted by tmux, which rejects
+/// sizes outside of WINDOW_MINIMUM..=WINDOW_MAXIMUM
+fn clamp_window_size(cols: u16, rows: u16) -> (u16, u16) {
+    const WINDOW_MINIMUM: u16 = 1;
+    const WINDOW_MAXIMUM: u16 = 10_000;
+    (
+        cols.max(WINDOW_MINIMUM).min(WINDOW_MAXIMUM),
+        rows.max(WINDOW_MINIMUM).min(WINDOW_MAXIMUM),
+    )
+}
+
+/// Returns true if `event` adds, removes or resizes windows
+fn is_topology_change(event: &Event) -> bool {
+    match event {
+        Event::WindowAdd { .. }
+        | Event::UnlinkedWindowAdd { .. }
+        | Event::WindowClose { .. }
+        | Event::WindowRenamed { .. }
+        | Event::LayoutChange { .. } => true,
+        _ => false,
+    }
+}
+
+/// The command runs on the remote host, so only its argv is
+/// meaningful; the default program is the shell that tmux uses
+fn remote_argv(command: Option<CommandBuilder>) -> Option<Vec<String>> {
+    command.filter(|cmd| !cmd.is_default_prog()).map(|cmd| {
+        cmd.get_argv()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    })
+}
+
+fn cell_size(width: u64, height: u64) -> PtySize {
+    PtySize {
+        rows: height as u16,
+        cols: width as u16,
+        pixel_width: 0,
+        pixel_height: 0,
+    }
+}
+
+/// Converts a tmux window layout into the tree of splits that a
+/// `Tab` is built from.  tmux places any number of cells side by side
+/// or one above the other, whereas our splits are binary, so a list
+/// of cells becomes a chain of splits that each divide the first cell
+/// from the rest.  The leaves hold the tmux pane ids rather than
+/// local pane ids; `active` is the pane that should be active.
+fn layout_to_pane_node(cell: &LayoutCell, active: Option<TmuxPaneId>) -> PaneNode {
+    let (direction, cells) = match &cell.content {
+        LayoutContent::Pane(pane) => {
+            return PaneNode::Leaf(PaneEntry {
+                window_id: 0,
+                tab_id: 0,
+                pane_id: *pane as PaneId,
+                title: String::new(),
+                size: cell_size(cell.width, cell.height),
+                working_dir: None,
+                is_active_pane: active == Some(*pane),
+                is_zoomed_pane: false,
+            })
+        }
+        LayoutContent::LeftRight(cells) => (SplitDirection::Horizontal, cells),
+        LayoutContent::TopBottom(cells) => (SplitDirection::Vertical, cells),
+    };
+    let (first, rest) = match cells.split_first() {
+        Some(split) => split,
+        None => return PaneNode::Empty,
+    };
+    if rest.is_empty() {
+        return layout_to_pane_node(first, active);
+    }
+
+    // The rest span from the start of the second cell to the end of
+    // the last; the gap before it is occupied by the split itself
+    let (second, last) = (&rest[0], &rest[rest.len() - 1]);
+    let remainder = match direction {
+        SplitDirection::Horizontal => LayoutCell {
+            width: last.x + last.width - second.x,
+            height: cell.height,
+            x: second.x,
+            y: cell.y,
+            content: LayoutContent::LeftRight(rest.to_vec()),
+        },
+        SplitDirection::Vertical => LayoutCell {
+            width: cell.width,
+            height: last.y + last.height - second.y,
+            x: cell.x,
+            y: second.y,
+            content: LayoutContent::TopBottom(rest.to_vec()),
+        },
+    };
+    PaneNode::Split {
+        left: Box::new(layout_to_pane_node(first, active)),
+        right: Box::new(layout_to_pane_node(&remainder, active)),
+        node: SplitDirectionAndSize {
+            direction,
+            first: cell_size(first.width, first.height),
+            second: cell_size(remainder.width, remainder.height),
+        },
+    }
+}
+
+/// Formats a message from tmux for display in a terminal; each line
+/// is prefixed so that it stands out from the regular output
+fn format_message(message: &str) -> String {
+    let mut text = String::new();
+    for line in message.trim_end().lines() {
+        text.push_str("[tmux] ");
+        text.push_str(line);
+        text.push_str("\r\n");
+    }
+    text
+}
+
+/// Applies `text` to the terminal model of `pane_id`.
+/// We may be called while the embedding pane is parsing its
+/// output, so this is deferred until that has completed.
+fn write_to_pane(pane_id: PaneId, text: String) {
+    promise::spawn::spawn_into_main_thread(async move {
+        let mux = Mux::get().expect("to be called on main thread");
+        if let Some(pane) = mux.get_pane(pane_id) {
+            let mut parser = termwiz::escape::parser::Parser::new();
+            pane.perform_actions(parser.parse_as_vec(text.as_bytes()));
+            mux.notify(MuxNotification::PaneOutput(pane_id));
+        }
+    })
+    .detach();
+}
+
+/// Removes local panes, and any tabs and windows left empty as a result,
+/// from the mux.  This is deferred because we may be called while the
+/// mux is iterating its panes.
+fn remove_panes(dead: Vec<PaneId>) {
+    if dead.is_empty() {
+        return;
+    }
+    promise::spawn::spawn(async move {
+        let mux = Mux::get().expect("to be called on main thread");
+        for pane_id in dead {
+            mux.remove_pane(pane_id);
+        }
+    })
+    .detach();
+}
+
+/// Returns a tmux domain that lost its control channel and was
+/// attached to the tmux server and session given by `identity`, now
+/// that tmux is running in control mode in `pane_id`.  A domain that
+/// was previously hosted by the same pane is preferred.
+fn get_disconnected_tmux_domain(pane_id: PaneId, identity: &str) -> Option<Arc<TmuxDomainState>> {
+    let mux = Mux::get()?;
+    let mut candidates: Vec<Arc<TmuxDomainState>> = mux
+        .iter_domains()
+        .into_iter()
+        .filter_map(|domain| {
+            let tmux_domain = domain.downcast_ref::<TmuxDomain>()?;
+            if tmux_domain.is_disconnected()
+                && tmux_domain.inner.identity.borrow().as_deref() == Some(identity)
+            {
+                Some(Arc::clone(&tmux_domain.inner))
+            } else {
+                None
+            }
+        })
+        .collect();
+    candidates.sort_by_key(|tmux| (*tmux.pane_id.borrow() != pane_id, tmux.domain_id));
+    candidates.into_iter().next()
+}
+
+/// Returns the state for the tmux domain with the specified id
+pub(crate) fn get_tmux_domain(domain_id: DomainId) -> Option<Arc<TmuxDomainState>> {
+    let mux = Mux::get()?;
+    let domain = mux.get_domain(domain_id)?;
+    let tmux_domain = domain.downcast_ref::<TmuxDomain>()?;
+    Some(Arc::clone(&tmux_domain.inner))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tmux_commands::HistorySize;
+
+    fn leaf(pane: PaneId, cols: u16, rows: u16, is_active_pane: bool) -> PaneNode {
+        PaneNode::Leaf(PaneEntry {
+            window_id: 0,
+            tab_id: 0,
+            pane_id: pane,
+            title: String::new(),
+            size: cell_size(cols.into(), rows.into()),
+            working_dir: None,
+            is_active_pane,
+            is_zoomed_pane: false,
+        })
+    }
+
+    #[test]
+    fn layout() {
+        let layout = parse_layout("b25d,80x24,0,0,0").unwrap();
+        assert_eq!(layout_to_pane_node(&layout, None), leaf(0, 80, 24, false));
+
+        // Three panes side by side, the last of which is split in two;
+        // the first split divides the first pane from the other two
+        let layout = parse_layout(
+            "08ac,80x24,0,0{26x24,0,0,1,26x24,27,0,2,26x24,54,0[26x12,54,0,3,26x11,54,13,4]}",
+        )
+        .unwrap();
+        assert_eq!(
+            layout_to_pane_node(&layout, Some(3)),
+            PaneNode::Split {
+                left: Box::new(leaf(1, 26, 24, false)),
+                right: Box::new(PaneNode::Split {
+                    left: Box::new(leaf(2, 26, 24, false)),
+                    right: Box::new(PaneNode::Split {
+                        left: Box::new(leaf(3, 26, 12, true)),
+                        right: Box::new(leaf(4, 26, 11, false)),
+                        node: SplitDirectionAndSize {
+                            direction: SplitDirection::Vertical,
+                            first: cell_size(26, 12),
+                            second: cell_size(26, 11),
+                        },
+                    }),
+                    node: SplitDirectionAndSize {
+                        direction: SplitDirection::Horizontal,
+                        first: cell_size(26, 24),
+                        second: cell_size(26, 24),
+                    },
+                }),
+                node: SplitDirectionAndSize {
+                    direction: SplitDirection::Horizontal,
+                    first: cell_size(26, 24),
+                    second: cell_size(53, 24),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn pending_output() {
+        let start = Instant::now();
+        let mut pending = PendingOutput::default();
+        pending.push(1, b"\x1b[1mhello\x00", start);
+        pending.push(1, b"\r\n", start);
+        pending.push(2, b"two", start);
+        assert_eq!(pending.take(1), Some(b"\x1b[1mhello\x00\r\n".to_vec()));
+        assert_eq!(pending.take(1), None);
+
+        // Output that would exceed the limit is discarded
+        pending.push(3, &vec![b'x'; MAX_PENDING_OUTPUT], start);
+        pending.push(3, b"y", start);
+        assert_eq!(pending.take(3).map(|b| b.len()), Some(MAX_PENDING_OUTPUT));
+
+        // Pane 2 never showed up
+        pending.push(4, b"four", start + PENDING_OUTPUT_TIMEOUT);
+        assert_eq!(pending.take(2), None);
+        assert_eq!(pending.take(4), Some(b"four".to_vec()));
+    }
+
+    #[test]
+    fn flow_control() {
+        // Without support from tmux, output over budget is discarded
+        // until we've caught up
+        let mut flow = FlowControl::default();
+        assert!(!flow.schedule_catch_up());
+        assert!(flow.output(1, MAX_OUTPUT_BACKLOG));
+        assert!(flow.output(2, 10));
+        assert!(!flow.output(1, 1));
+        assert!(!flow.output(1, 1));
+        assert!(flow.output(2, 10));
+        assert!(flow.schedule_catch_up());
+        assert!(!flow.schedule_catch_up());
+        assert_eq!(flow.caught_up(), vec![1]);
+        assert!(flow.output(1, 1));
+        assert!(flow.schedule_catch_up());
+        assert_eq!(flow.caught_up(), vec![]);
+        assert!(!flow.schedule_catch_up());
+
+        // With support, tmux tells us which panes to pause
+        let mut flow = FlowControl::default();
+        flow.supported = true;
+        assert!(flow.output(1, MAX_OUTPUT_BACKLOG * 2));
+        assert!(!flow.schedule_catch_up());
+        flow.pause(3);
+        assert!(!flow.output(3, 1));
+        assert!(flow.schedule_catch_up());
+        assert_eq!(flow.caught_up(), vec![3]);
+        assert!(flow.output(3, 1));
+    }
+
+    #[test]
+    fn control_mode_detection() {
+        let feed = |tmux: &TmuxDomainState, chunks: &[&[u8]]| -> bool {
+            chunks
+                .iter()
+                .all(|chunk| chunk.iter().all(|&b| tmux.advance(b)))
+        };
+
+        // Completing the initial response sends the next command
+        Mux::set_mux(&Rc::new(Mux::new(None)));
+
+        // tmux begins with the response to its initial command, which
+        // may be split across reads
+        let domain = TmuxDomain::new(0);
+        assert!(feed(
+            &domain.inner,
+            &[b"%beg", b"in 1611111111 ", b"27 0\n"]
+        ));
+        assert!(domain.inner.parser.borrow().is_in_block());
+        // The rest of the stream is accepted, including the newline
+        // that ends the initial response and the notifications after it
+        assert!(feed(
+            &domain.inner,
+            &[b"%end 1611111111 27 0\n", b"%session-changed $1 1\n"]
+        ));
+        assert_eq!(*domain.inner.state.borrow(), State::WaitingForResponse);
+        assert_eq!(*domain.inner.session.borrow(), Some(1));
+
+        // whereas a file that happened to contain the DCS introducer
+        // is followed by arbitrary text
+        let domain = TmuxDomain::new(0);
+        assert!(!feed(&domain.inner, &[b"hello", b" there\r\n"]));
+
+        let domain = TmuxDomain::new(0);
+        assert!(!feed(&domain.inner, &[&[b'x'; MAX_PREAMBLE * 2]]));
+
+        Mux::shutdown();
+    }
+
+    #[test]
+    fn resize_burst() {
+        let domain = TmuxDomain::new(0);
+        let tmux = &domain.inner;
+        let queued = || -> Vec<String> {
+            tmux.cmd_queue
+                .borrow()
+                .iter()
+                .map(|cmd| cmd.get_command())
+                .collect()
+        };
+
+        // tmux hasn't sent its initial response, so these are only queued
+        tmux.resize_client(80, 24);
+        tmux.resize_client(81, 24);
+        tmux.resize_client(90, 30);
+        assert_eq!(
+            queued()[1..].to_vec(),
+            vec!["refresh-client -C 80x24\n".to_owned()]
+        );
+
+        // Once the first completes, only the most recent size is sent
+        tmux.client_resized((80, 24));
+        assert_eq!(
+            queued()[1..].to_vec(),
+            vec![
+                "refresh-client -C 80x24\n".to_owned(),
+                "refresh-client -C 90x30\n".to_owned()
+            ]
+        );
+
+        // and nothing further once we've caught up
+        tmux.client_resized((90, 30));
+        assert_eq!(queued().len(), 3);
+
+        assert_eq!(clamp_window_size(0, 20_000), (1, 10_000));
+    }
+
+    #[test]
+    fn session_config() {
+        let domain = TmuxDomain::new(0);
+        assert_eq!(domain.domain_name(), "tmux");
+        assert_eq!(domain.inner.spawn_target(), None);
+        domain.inner.session.borrow_mut().replace(3);
+        assert_eq!(domain.inner.spawn_target(), Some("$3:".to_owned()));
+
+        let domain = TmuxDomain::with_config(
+            0,
+            TmuxDomainConfig {
+                session: Some("work".to_owned()),
+                window: None,
+            },
+        );
+        assert_eq!(domain.domain_name(), "tmux:work");
+        assert_eq!(domain.inner.spawn_target(), Some("=work:".to_owned()));
+        // Once we know its id, that is used instead, in case the
+        // session is renamed
+        domain.inner.session.borrow_mut().replace(4);
+        assert_eq!(domain.inner.spawn_target(), Some("$4:".to_owned()));
+        let queued: Vec<String> = domain
+            .inner
+            .cmd_queue
+            .borrow()
+            .iter()
+            .map(|cmd| cmd.get_command())
+            .collect();
+        assert_eq!(
+            queued,
+            vec!["display-message -p '#{pid} #{socket_path} #{session_id}'\n".to_owned()]
+        );
+
+        // The session is only selected once we know that we aren't
+        // picking up where another domain left off
+        domain.inner.identified(None);
+        assert_eq!(
+            domain.inner.cmd_queue.borrow()[1].get_command(),
+            "switch-client -t '=work'\n"
+        );
+    }
+
+    thread_local! {
+        static SPAWNED: RefCell<VecDeque<promise::spawn::Runnable>> =
+            RefCell::new(VecDeque::new());
+    }
+
+    /// Holds the futures that are spawned on each thread until
+    /// `run_spawned` is called
+    fn hold_spawned() {
+        let schedule = || -> promise::spawn::ScheduleFunc {
+            Box::new(|runnable| SPAWNED.with(|spawned| spawned.borrow_mut().push_back(runnable)))
+        };
+        promise::spawn::set_schedulers(schedule(), schedule());
+    }
+
+    /// Runs the futures that were spawned on this thread, as the gui
+    /// would, so that the responses from tmux are processed
+    fn run_spawned() {
+        while let Some(runnable) = SPAWNED.with(|spawned| spawned.borrow_mut().pop_front()) {
+            runnable.run();
+        }
+    }
+
+    #[test]
+    fn capture_responses() {
+        hold_spawned();
+        let mux = Rc::new(Mux::new(None));
+        Mux::set_mux(&mux);
+        let domain = TmuxDomain::new(0);
+        let tmux = Arc::clone(&domain.inner);
+        mux.add_domain(&(Arc::new(domain) as Arc<dyn Domain>));
+        let feed = |stream: &str| {
+            assert!(stream.bytes().all(|b| tmux.advance(b)));
+            run_spawned();
+        };
+
+        // Skip the handshake, which isn't what this is about
+        *tmux.state.borrow_mut() = State::Idle;
+        *tmux.identifying.borrow_mut() = false;
+        *tmux.initial_block_seen.borrow_mut() = true;
+        tmux.cmd_queue.borrow_mut().clear();
+        let pane = tmux.create_pane(0, 1, cell_size(10, 2)).unwrap();
+        mux.add_pane(&pane).unwrap();
+
+        // tmux responds to the capture and to the cursor query with
+        // a block each, and the command after them gets its own
+        tmux.queue_capture(1);
+        let future = tmux.send_command(Box::new(HistorySize { pane: 1 }));
+        feed("%begin 1 2 1\n$ ls\n%end 1 2 1\n");
+        feed("%begin 1 3 1\n2 1\n%end 1 3 1\n");
+        let (_, lines) = pane.get_lines(0..2);
+        assert_eq!(lines[0].as_str().trim_end(), "$ ls");
+        let cursor = pane.get_cursor_position();
+        assert_eq!((cursor.x, cursor.y), (2, 1));
+
+        feed("%begin 1 4 1\n1500\n%end 1 4 1\n");
+        let response = promise::spawn::block_on(future).unwrap();
+        assert_eq!(response.text(), "1500\n");
+        assert!(tmux.cmd_queue.borrow().is_empty());
+
+        Mux::shutdown();
+    }
+
+    #[test]
+    fn detach_once() {
+        hold_spawned();
+        let mux = Rc::new(Mux::new(None));
+        Mux::set_mux(&mux);
+        let domain: Arc<dyn Domain> = Arc::new(TmuxDomain::new(0));
+        mux.add_domain(&domain);
+        let tmux = get_tmux_domain(domain.domain_id()).unwrap();
+        let pane = tmux.create_pane(0, 1, cell_size(10, 2)).unwrap();
+        mux.add_pane(&pane).unwrap();
+
+        domain.detach().unwrap();
+        assert_eq!(domain.state(), DomainState::Detached);
+        assert!(mux.get_pane(pane.pane_id()).is_none());
+        run_spawned();
+
+        // tmux ends the control channel once it has detached us,
+        // which must not tear the domain down a second time
+        let pane = tmux.create_pane(0, 2, cell_size(10, 2)).unwrap();
+        mux.add_pane(&pane).unwrap();
+        tmux.detached();
+        assert!(mux.get_pane(pane.pane_id()).is_some());
+
+        Mux::shutdown();
+    }
+
+    #[test]
+    fn send_command() {
+        // The response is matched up with the command by its position
+        // in the queue, as tmux responds to commands in order
+        let domain = TmuxDomain::new(1);
+        let tmux = &domain.inner;
+        let future = tmux.send_command(Box::new(KillPane { pane: 1 }));
+        let queued: Vec<String> = tmux
+            .cmd_queue
+            .borrow()
+            .iter()
+            .map(|cmd| cmd.get_command())
+            .collect();
+        assert_eq!(queued.last().map(String::as_str), Some("kill-pane -t %1\n"));
+
+        // Losing the connection fails the commands that are outstanding
+        tmux.disconnected();
+        assert!(promise::spawn::block_on(future).is_err());
+    }
+
+    #[test]
+    fn reattach() {
+        let mux = Rc::new(Mux::new(None));
+        Mux::set_mux(&mux);
+        // Starts a domain via `pane_id`, which tmux tells is attached
+        // to `identity`.  There's no such pane, so the commands that
+        // the domain sends go nowhere, but they are still queued.
+        let attach = |pane_id: PaneId, identity: &str| -> Arc<TmuxDomainState> {
+            let domain = TmuxDomain::new(pane_id);
+            let tmux = Arc::clone(&domain.inner);
+            mux.add_domain(&(Arc::new(domain) as Arc<dyn Domain>));
+            let stream = format!(
+                "%begin 1 1 0\n%end 1 1 0\n%begin 1 2 1\n{}\n%end 1 2 1\n",
+                identity
+            );
+            assert!(stream.bytes().all(|b| tmux.advance(b)));
+            tmux
+        };
+        let queued = |tmux: &TmuxDomainState| -> Vec<String> {
+            tmux.cmd_queue
+                .borrow()
+                .iter()
+                .map(|cmd| cmd.get_command())
+                .collect()
+        };
+
+        let tmux = attach(1, "123 /tmp/tmux-0/default $1");
+        assert_eq!(
+            tmux.identity.borrow().as_deref(),
+            Some("123 /tmp/tmux-0/default $1")
+        );
+        assert!(queued(&tmux)[1].starts_with("list-panes -aF"));
+
+        // Losing the channel discards the commands that we had queued,
+        // and nothing more is queued until we reattach
+        tmux.disconnected();
+        let domain = mux.get_domain(tmux.domain_id).unwrap();
+        assert_eq!(domain.state(), DomainState::Detached);
+        assert!(queued(&tmux).is_empty());
+        tmux.resize_client(80, 24);
+        assert!(queued(&tmux).is_empty());
+
+        // A client of a different server is not merged with us, even
+        // though the ids of its panes may well be the same as ours
+        let other = attach(2, "456 /tmp/tmux-0/default $1");
+        assert!(other.take_successor().is_none());
+        assert_eq!(*tmux.state.borrow(), State::Disconnected);
+        assert_eq!(*tmux.pane_id.borrow(), 1);
+        assert!(queued(&other)[1].starts_with("list-panes -aF"));
+
+        // whereas a client of the same server and session picks up
+        // where we left off, and its own domain goes away
+        let channel = attach(3, "123 /tmp/tmux-0/default $1");
+        let successor = channel.take_successor().unwrap();
+        assert!(Arc::ptr_eq(&successor, &tmux));
+        assert!(channel.is_detached());
+        assert!(queued(&channel).is_empty());
+        assert!(mux.get_domain(channel.domain_id).is_none());
+        assert_eq!(
+            mux.get_domain_by_name("tmux")
+                .map(|domain| domain.domain_id()),
+            Some(tmux.domain_id)
+        );
+        assert_eq!(*tmux.pane_id.borrow(), 3);
+        assert!(*tmux.resyncing.borrow());
+        assert_eq!(domain.state(), DomainState::Attached);
+        assert!(queued(&tmux)[1].starts_with("list-panes -aF"));
+
+        // whereas after an explicit detach, there's no coming back
+        tmux.disconnected();
+        domain.detach().unwrap();
+        let channel = attach(4, "123 /tmp/tmux-0/default $1");
+        assert!(channel.take_successor().is_none());
+        assert_eq!(*tmux.state.borrow(), State::Detached);
+
+        Mux::shutdown();
+    }
+
+    #[test]
+    fn message_text() {
+        assert_eq!(
+            format_message("error: unknown command: foo\n"),
+            "[tmux] error: unknown command: foo\r\n"
+        );
+        assert_eq!(
+            format_message("one\r\ntwo\n"),
+            "[tmux] one\r\n[tmux] two\r\n"
+        );
+        assert_eq!(format_message(""), "");
     }
 }
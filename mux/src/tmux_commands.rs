@@ -0,0 +1,982 @@
+use crate::domain::DomainId;
+use crate::tab::SplitDirection;
+use crate::tmux::get_tmux_domain;
+use anyhow::anyhow;
+use promise::Promise;
+use std::cell::{Cell, RefCell};
+use std::fmt::Write;
+use std::ops::Range;
+use std::rc::Rc;
+use tmux_cc::*;
+use wezterm_term::StableRowIndex;
+
+pub(crate) trait TmuxCommand {
+    fn get_command(&self) -> String;
+    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()>;
+
+    /// The remote pane that this command acts upon, if any.
+    /// Errors reported by tmux are shown in the corresponding local pane.
+    fn pane(&self) -> Option<TmuxPaneId> {
+        None
+    }
+
+    /// Called when the command will never receive a response,
+    /// because we have detached from tmux.  Commands that report
+    /// their result via a promise must fail it here, so that nothing
+    /// waits on it forever.
+    fn cancel(&self) {}
+}
+
+/// Wraps another command so that its response can be awaited;
+/// see `TmuxDomainState::send_command`
+pub(crate) struct Awaited {
+    pub cmd: Box<dyn TmuxCommand>,
+    pub promise: RefCell<Option<Promise<Guarded>>>,
+}
+impl TmuxCommand for Awaited {
+    fn get_command(&self) -> String {
+        self.cmd.get_command()
+    }
+
+    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        let processed = self.cmd.process_result(domain_id, result);
+        if let Some(mut promise) = self.promise.borrow_mut().take() {
+            if result.error {
+                promise.err(anyhow!(
+                    "{} failed: {}",
+                    self.get_command().trim_end(),
+                    result.text().trim()
+                ));
+            } else {
+                promise.ok(result.clone());
+            }
+        }
+        processed
+    }
+
+    fn pane(&self) -> Option<TmuxPaneId> {
+        self.cmd.pane()
+    }
+
+    fn cancel(&self) {
+        self.cmd.cancel();
+        if let Some(mut promise) = self.promise.borrow_mut().take() {
+            promise.err(anyhow!("detached from tmux"));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PaneItem {
+    pub session_id: TmuxSessionId,
+    pub window_id: TmuxWindowId,
+    pub pane_id: TmuxPaneId,
+    pub pane_index: u64,
+    pub cursor_x: u64,
+    pub cursor_y: u64,
+    pub pane_width: u64,
+    pub pane_height: u64,
+    pub pane_left: u64,
+    pub pane_top: u64,
+    /// The number of rows of history that tmux holds for the pane
+    pub history_size: u64,
+    /// The layout of the window; see `tmux_cc::layout`
+    pub window_layout: String,
+    /// The name of the window, which is used as the title of the tab
+    pub window_name: Option<String>,
+}
+
+/// Asks which tmux server and session the control client is attached
+/// to.  This is the first command that a domain sends, and its response
+/// is handled by the domain as soon as it arrives rather than via
+/// `process_result`; see `TmuxDomainState::identified`.
+pub(crate) struct ServerIdentity;
+impl TmuxCommand for ServerIdentity {
+    fn get_command(&self) -> String {
+        "display-message -p '#{pid} #{socket_path} #{session_id}'\n".to_owned()
+    }
+
+    fn process_result(&self, _domain_id: DomainId, _result: &Guarded) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) struct ListAllPanes;
+impl TmuxCommand for ListAllPanes {
+    fn get_command(&self) -> String {
+        "list-panes -aF '#{session_id} #{window_id} #{pane_id} \
+            #{pane_index} #{cursor_x} #{cursor_y} #{pane_width} #{pane_height} \
+            #{pane_left} #{pane_top} #{history_size} #{window_layout} #{window_name}'\n"
+            .to_owned()
+    }
+
+    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        let mut items = vec![];
+
+        for line in result.text().split('\n') {
+            if line.is_empty() {
+                continue;
+            }
+            // Skip rows that we can't make sense of rather than failing
+            // to attach to any of the panes
+            match parse_pane_item(line) {
+                Ok(item) => items.push(item),
+                Err(err) => log::warn!("ignoring list-panes row {:?}: {:#}", line, err),
+            }
+        }
+
+        log::trace!("panes in domain_id {}: {:?}", domain_id, items);
+        if let Some(tmux) = get_tmux_domain(domain_id) {
+            tmux.sync_panes(items)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a row of the output produced by `ListAllPanes`
+fn parse_pane_item(line: &str) -> anyhow::Result<PaneItem> {
+    // The window name is last because it may contain spaces
+    let mut fields = line.splitn(13, ' ');
+    let mut next = |name: &str| fields.next().ok_or_else(|| anyhow!("missing {}", name));
+
+    // These ids all have various sigils such as `$`, `%`, `@`,
+    // so skip those prior to parsing them
+    let session_id = parse_id(next("session_id")?, '$')?;
+    let window_id = parse_id(next("window_id")?, '@')?;
+    let pane_id = parse_id(next("pane_id")?, '%')?;
+    let pane_index = next("pane_index")?.parse()?;
+    let cursor_x = next("cursor_x")?.parse()?;
+    let cursor_y = next("cursor_y")?.parse()?;
+    let pane_width = next("pane_width")?.parse()?;
+    let pane_height = next("pane_height")?.parse()?;
+    let pane_left = next("pane_left")?.parse()?;
+    let pane_top = next("pane_top")?.parse()?;
+    let history_size = next("history_size")?.parse()?;
+    let window_layout = next("window_layout")?.to_owned();
+    // An empty name is treated as no name, so that the tab falls
+    // back to the title set by the application
+    let window_name = fields
+        .next()
+        .filter(|name| !name.is_empty())
+        .map(unvis)
+        .transpose()?;
+
+    Ok(PaneItem {
+        session_id,
+        window_id,
+        pane_id,
+        pane_index,
+        cursor_x,
+        cursor_y,
+        pane_width,
+        pane_height,
+        pane_left,
+        pane_top,
+        history_size,
+        window_layout,
+        window_name,
+    })
+}
+
+/// Parses a tmux id such as `@1`, which is prefixed by `sigil`
+fn parse_id(field: &str, sigil: char) -> anyhow::Result<u64> {
+    if !field.starts_with(sigil) {
+        anyhow::bail!("expected {:?} to start with {:?}", field, sigil);
+    }
+    Ok(field[sigil.len_utf8()..].parse()?)
+}
+
+/// Sends the supplied bytes to a pane as though they were typed
+/// into it.  The bytes are hex encoded so that we don't need to
+/// worry about quoting or about tmux interpreting key names.
+pub(crate) struct SendKeys {
+    pub pane: TmuxPaneId,
+    pub keys: Vec<u8>,
+}
+impl TmuxCommand for SendKeys {
+    fn get_command(&self) -> String {
+        let mut cmd = format!("send-keys -H -t %{}", self.pane);
+        for b in &self.keys {
+            let _ = write!(&mut cmd, " {:02x}", b);
+        }
+        cmd.push('\n');
+        cmd
+    }
+
+    fn process_result(&self, _domain_id: DomainId, _result: &Guarded) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn pane(&self) -> Option<TmuxPaneId> {
+        Some(self.pane)
+    }
+}
+
+/// Creates a new window in the attached session and reports the
+/// ids of the new window and its pane via `promise`
+pub(crate) struct NewWindow {
+    /// The window to create, passed via `-t`; this is typically
+    /// `session:` to create the window in a particular session
+    pub target: Option<String>,
+    /// The working directory for the new window, passed via `-c`
+    pub cwd: Option<String>,
+    /// The command to run in the new window, rather than the
+    /// default shell configured in tmux
+    pub command: Option<Vec<String>>,
+    pub promise: RefCell<Option<Promise<(TmuxWindowId, TmuxPaneId)>>>,
+}
+impl TmuxCommand for NewWindow {
+    fn get_command(&self) -> String {
+        let mut cmd = "new-window -P -F '#{window_id} #{pane_id}'".to_owned();
+        if let Some(target) = &self.target {
+            cmd.push_str(" -t ");
+            cmd.push_str(&quote_arg(target));
+        }
+        if let Some(cwd) = &self.cwd {
+            cmd.push_str(" -c ");
+            cmd.push_str(&quote_arg(cwd));
+        }
+        if let Some(command) = &self.command {
+            for arg in command {
+                cmd.push(' ');
+                cmd.push_str(&quote_arg(arg));
+            }
+        }
+        cmd.push('\n');
+        cmd
+    }
+
+    fn process_result(&self, _domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        let parsed = if result.error {
+            Err(anyhow!("new-window failed: {}", result.text().trim()))
+        } else {
+            parse_window_and_pane(&result.text())
+        };
+        if let Some(mut promise) = self.promise.borrow_mut().take() {
+            promise.result(parsed);
+        }
+        Ok(())
+    }
+
+    fn cancel(&self) {
+        if let Some(mut promise) = self.promise.borrow_mut().take() {
+            promise.err(anyhow!("detached from tmux"));
+        }
+    }
+}
+
+/// Splits `pane`, placing the new pane to its right or below it,
+/// and reports the ids of the window and the new pane via `promise`.
+/// tmux follows up with `%layout-change` for the window.
+pub(crate) struct SplitWindow {
+    pub pane: TmuxPaneId,
+    pub direction: SplitDirection,
+    /// The working directory for the new pane, passed via `-c`
+    pub cwd: Option<String>,
+    /// The command to run in the new pane, rather than the
+    /// default shell configured in tmux
+    pub command: Option<Vec<String>>,
+    pub promise: RefCell<Option<Promise<(TmuxWindowId, TmuxPaneId)>>>,
+}
+impl TmuxCommand for SplitWindow {
+    fn get_command(&self) -> String {
+        let mut cmd = "split-window -P -F '#{window_id} #{pane_id}'".to_owned();
+        cmd.push_str(match self.direction {
+            SplitDirection::Horizontal => " -h",
+            SplitDirection::Vertical => " -v",
+        });
+        let _ = write!(&mut cmd, " -t %{}", self.pane);
+        if let Some(cwd) = &self.cwd {
+            cmd.push_str(" -c ");
+            cmd.push_str(&quote_arg(cwd));
+        }
+        if let Some(command) = &self.command {
+            for arg in command {
+                cmd.push(' ');
+                cmd.push_str(&quote_arg(arg));
+            }
+        }
+        cmd.push('\n');
+        cmd
+    }
+
+    fn process_result(&self, _domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        let parsed = if result.error {
+            Err(anyhow!("split-window failed: {}", result.text().trim()))
+        } else {
+            parse_window_and_pane(&result.text())
+        };
+        if let Some(mut promise) = self.promise.borrow_mut().take() {
+            promise.result(parsed);
+        }
+        Ok(())
+    }
+
+    fn pane(&self) -> Option<TmuxPaneId> {
+        Some(self.pane)
+    }
+
+    fn cancel(&self) {
+        if let Some(mut promise) = self.promise.borrow_mut().take() {
+            promise.err(anyhow!("detached from tmux"));
+        }
+    }
+}
+
+/// Switches our client to the session named `session` and then
+/// lists its panes.  If `create` is true and there is no such session,
+/// it is created and then switched to.
+pub(crate) struct SwitchSession {
+    pub session: String,
+    pub create: bool,
+}
+impl TmuxCommand for SwitchSession {
+    fn get_command(&self) -> String {
+        // `=` requires an exact match rather than a prefix
+        format!(
+            "switch-client -t {}\n",
+            quote_arg(&format!("={}", self.session))
+        )
+    }
+
+    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        let tmux = match get_tmux_domain(domain_id) {
+            Some(tmux) => tmux,
+            None => return Ok(()),
+        };
+        if !result.error {
+            tmux.queue_command(Box::new(ListAllPanes));
+            return Ok(());
+        }
+        if !self.create {
+            anyhow::bail!(
+                "switch-client to {} failed: {}",
+                self.session,
+                result.text().trim()
+            );
+        }
+        log::trace!(
+            "tmux: creating session {} after: {}",
+            self.session,
+            result.text().trim()
+        );
+        tmux.queue_command(Box::new(NewSession {
+            session: self.session.clone(),
+        }));
+        tmux.queue_command(Box::new(SwitchSession {
+            session: self.session.clone(),
+            create: false,
+        }));
+        Ok(())
+    }
+}
+
+/// Creates a detached session named `session`
+pub(crate) struct NewSession {
+    pub session: String,
+}
+impl TmuxCommand for NewSession {
+    fn get_command(&self) -> String {
+        format!("new-session -d -s {}\n", quote_arg(&self.session))
+    }
+
+    fn process_result(&self, _domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if result.error {
+            anyhow::bail!("new-session failed: {}", result.text().trim());
+        }
+        Ok(())
+    }
+}
+
+/// Quotes `arg` so that tmux's command parser treats it as a single word.
+/// There are no escapes inside single quotes, so an embedded quote is
+/// emitted by closing the quoted section and escaping it.
+fn quote_arg(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Parses the `@window %pane` output produced by the `-F` formats
+/// used by `new-window` and `split-window`
+fn parse_window_and_pane(output: &str) -> anyhow::Result<(TmuxWindowId, TmuxPaneId)> {
+    let line = output.trim();
+    let mut fields = line.split(' ');
+    let window_id = fields.next().ok_or_else(|| anyhow!("missing window_id"))?;
+    let pane_id = fields.next().ok_or_else(|| anyhow!("missing pane_id"))?;
+    if !window_id.starts_with('@') || !pane_id.starts_with('%') {
+        anyhow::bail!("unexpected new-window output {:?}", line);
+    }
+    Ok((window_id[1..].parse()?, pane_id[1..].parse()?))
+}
+
+/// Parses the output of `CaptureCursor`
+fn parse_cursor(output: &str) -> anyhow::Result<(usize, usize)> {
+    let mut fields = output.trim().split(' ');
+    let mut next = |name: &str| -> anyhow::Result<usize> {
+        let field = fields.next().ok_or_else(|| anyhow!("missing {}", name))?;
+        Ok(field.parse()?)
+    };
+    Ok((next("cursor_x")?, next("cursor_y")?))
+}
+
+pub(crate) struct KillPane {
+    pub pane: TmuxPaneId,
+}
+impl TmuxCommand for KillPane {
+    fn get_command(&self) -> String {
+        format!("kill-pane -t %{}\n", self.pane)
+    }
+
+    fn process_result(&self, _domain_id: DomainId, _result: &Guarded) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn pane(&self) -> Option<TmuxPaneId> {
+        Some(self.pane)
+    }
+}
+
+pub(crate) struct ResizeWindow {
+    pub window: TmuxWindowId,
+    pub cols: u16,
+    pub rows: u16,
+}
+impl TmuxCommand for ResizeWindow {
+    fn get_command(&self) -> String {
+        format!(
+            "resize-window -t @{} -x {} -y {}\n",
+            self.window, self.cols, self.rows
+        )
+    }
+
+    fn process_result(&self, _domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if result.error {
+            anyhow::bail!("resize-window failed: {}", result.text().trim());
+        }
+        Ok(())
+    }
+}
+
+/// Resizes a pane within its window, which moves the splits
+/// that separate it from its neighbors
+pub(crate) struct ResizePane {
+    pub pane: TmuxPaneId,
+    pub cols: u16,
+    pub rows: u16,
+}
+impl TmuxCommand for ResizePane {
+    fn get_command(&self) -> String {
+        format!(
+            "resize-pane -t %{} -x {} -y {}\n",
+            self.pane, self.cols, self.rows
+        )
+    }
+
+    fn process_result(&self, _domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if result.error {
+            anyhow::bail!("resize-pane failed: {}", result.text().trim());
+        }
+        Ok(())
+    }
+
+    fn pane(&self) -> Option<TmuxPaneId> {
+        Some(self.pane)
+    }
+}
+
+/// Informs tmux of the size of our client, which in turn determines
+/// the size of the windows in the session
+pub(crate) struct RefreshClient {
+    pub cols: u16,
+    pub rows: u16,
+}
+impl TmuxCommand for RefreshClient {
+    fn get_command(&self) -> String {
+        format!("refresh-client -C {}x{}\n", self.cols, self.rows)
+    }
+
+    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if let Some(tmux) = get_tmux_domain(domain_id) {
+            tmux.client_resized((self.cols, self.rows));
+        }
+        if result.error {
+            anyhow::bail!("refresh-client failed: {}", result.text().trim());
+        }
+        Ok(())
+    }
+}
+
+/// Asks tmux to pause panes whose output we haven't kept up with.
+/// This requires tmux 3.2 or later; if it fails, we fall back to
+/// limiting the output that we apply ourselves.
+pub(crate) struct EnableFlowControl {
+    pub pause_after: u64,
+}
+impl TmuxCommand for EnableFlowControl {
+    fn get_command(&self) -> String {
+        format!("refresh-client -f pause-after={}\n", self.pause_after)
+    }
+
+    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if let Some(tmux) = get_tmux_domain(domain_id) {
+            tmux.set_flow_control_supported(!result.error);
+        }
+        if result.error {
+            log::warn!(
+                "tmux: flow control is not available: {}",
+                result.text().trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Resumes output for a pane that tmux paused
+pub(crate) struct ContinuePane {
+    pub pane: TmuxPaneId,
+}
+impl TmuxCommand for ContinuePane {
+    fn get_command(&self) -> String {
+        format!("refresh-client -A '%{}:continue'\n", self.pane)
+    }
+
+    fn process_result(&self, _domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if result.error {
+            anyhow::bail!("refresh-client -A failed: {}", result.text().trim());
+        }
+        Ok(())
+    }
+}
+
+/// Fetches the visible contents of a pane, including its attributes,
+/// holding them in `captured` for the `CaptureCursor` that follows;
+/// see `TmuxDomainState::queue_capture`
+pub(crate) struct CapturePane {
+    pub pane: TmuxPaneId,
+    pub captured: Rc<RefCell<Option<Vec<u8>>>>,
+}
+impl TmuxCommand for CapturePane {
+    fn get_command(&self) -> String {
+        format!("capture-pane -p -e -t %{}\n", self.pane)
+    }
+
+    fn process_result(&self, _domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if result.error {
+            anyhow::bail!("capture-pane failed: {}", result.text().trim());
+        }
+        self.captured.borrow_mut().replace(result.output.clone());
+        Ok(())
+    }
+
+    fn pane(&self) -> Option<TmuxPaneId> {
+        Some(self.pane)
+    }
+}
+
+/// Fetches the position of the cursor in a pane, and then replaces
+/// the contents of the local pane with those that were `captured` by
+/// the `CapturePane` that preceded it.  tmux responds to each command
+/// of a command line separately, so this can't be part of the same
+/// command line as the capture, but nothing else is sent in between.
+pub(crate) struct CaptureCursor {
+    pub pane: TmuxPaneId,
+    pub captured: Rc<RefCell<Option<Vec<u8>>>>,
+}
+impl TmuxCommand for CaptureCursor {
+    fn get_command(&self) -> String {
+        format!(
+            "display-message -p -t %{} '#{{cursor_x}} #{{cursor_y}}'\n",
+            self.pane
+        )
+    }
+
+    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if result.error {
+            anyhow::bail!("display-message failed: {}", result.text().trim());
+        }
+        // If the capture failed, that has already been reported
+        let text = match self.captured.borrow_mut().take() {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let cursor = parse_cursor(&result.text())?;
+        if let Some(tmux) = get_tmux_domain(domain_id) {
+            tmux.pane_captured(self.pane, &text, cursor);
+        }
+        Ok(())
+    }
+
+    fn pane(&self) -> Option<TmuxPaneId> {
+        Some(self.pane)
+    }
+}
+
+/// Asks how many rows of history tmux holds for `pane`; see
+/// `TmuxPane::set_history_size`
+pub(crate) struct HistorySize {
+    pub pane: TmuxPaneId,
+}
+impl TmuxCommand for HistorySize {
+    fn get_command(&self) -> String {
+        format!("display-message -p -t %{} '#{{history_size}}'\n", self.pane)
+    }
+
+    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if result.error {
+            anyhow::bail!("display-message failed: {}", result.text().trim());
+        }
+        let size = result.text().trim().parse()?;
+        if let Some(tmux) = get_tmux_domain(domain_id) {
+            tmux.history_size(self.pane, size);
+        }
+        Ok(())
+    }
+
+    fn pane(&self) -> Option<TmuxPaneId> {
+        Some(self.pane)
+    }
+}
+
+/// Fetches `rows` of the history of `pane`, including their
+/// attributes.  tmux addresses the history relative to the top of
+/// the screen, so the rows are translated using `physical_top`,
+/// the stable row index of the top of the local screen when the
+/// command is sent.  Output that arrived before then has scrolled
+/// the remote screen too, whereas output that arrives afterwards
+/// was produced after tmux captured the rows.
+pub(crate) struct CaptureHistory {
+    pub domain_id: DomainId,
+    pub pane: TmuxPaneId,
+    pub rows: Range<StableRowIndex>,
+    /// Resolved by the first call to `get_command`, which is made
+    /// when the command is sent, and kept so that the command reads
+    /// the same when it is logged along with its response
+    pub physical_top: Cell<Option<StableRowIndex>>,
+}
+impl TmuxCommand for CaptureHistory {
+    fn get_command(&self) -> String {
+        let physical_top = match self.physical_top.get() {
+            Some(physical_top) => physical_top,
+            None => {
+                // If the pane has gone away, so will the response
+                let physical_top = get_tmux_domain(self.domain_id)
+                    .and_then(|tmux| tmux.physical_top(self.pane))
+                    .unwrap_or(0);
+                self.physical_top.set(Some(physical_top));
+                physical_top
+            }
+        };
+        format!(
+            "capture-pane -p -e -S {} -E {} -t %{}\n",
+            self.rows.start - physical_top,
+            self.rows.end - 1 - physical_top,
+            self.pane
+        )
+    }
+
+    fn process_result(&self, domain_id: DomainId, result: &Guarded) -> anyhow::Result<()> {
+        if let Some(tmux) = get_tmux_domain(domain_id) {
+            // This also lets the pane know that the rows are no
+            // longer being fetched, should tmux have failed
+            let text: &[u8] = if result.error { b"" } else { &result.output };
+            tmux.history_captured(self.pane, self.rows.clone(), text);
+        }
+        if result.error {
+            anyhow::bail!("capture-pane failed: {}", result.text().trim());
+        }
+        Ok(())
+    }
+
+    fn pane(&self) -> Option<TmuxPaneId> {
+        Some(self.pane)
+    }
+
+    fn cancel(&self) {
+        // Let the pane know that the rows are no longer being
+        // fetched, so that they are fetched again once we reattach
+        if let Some(tmux) = get_tmux_domain(self.domain_id) {
+            tmux.history_captured(self.pane, self.rows.clone(), b"");
+        }
+    }
+}
+
+pub(crate) struct DetachClient;
+impl TmuxCommand for DetachClient {
+    fn get_command(&self) -> String {
+        "detach-client\n".to_owned()
+    }
+
+    fn process_result(&self, _domain_id: DomainId, _result: &Guarded) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use promise::spawn::block_on;
+
+    /// Returns the response block in `text`, as tmux would send it
+    fn response(text: &str) -> Guarded {
+        let mut parser = Parser::new();
+        match parser.advance_string(text).pop() {
+            Some(Event::Guarded(guarded)) => guarded,
+            event => panic!("expected a response but got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn awaited() {
+        let awaited = || {
+            let mut promise = Promise::new();
+            let future = promise.get_future().unwrap();
+            let cmd = Awaited {
+                cmd: Box::new(KillPane { pane: 1 }),
+                promise: RefCell::new(Some(promise)),
+            };
+            (cmd, future)
+        };
+
+        let (cmd, future) = awaited();
+        let guarded = response("%begin 1600000000 12 1\nok\n%end 1600000000 12 1\n");
+        cmd.process_result(0, &guarded).unwrap();
+        assert_eq!(block_on(future).unwrap(), guarded);
+
+        let (cmd, future) = awaited();
+        let guarded =
+            response("%begin 1600000000 13 1\ncan't find pane: %1\n%error 1600000000 13 1\n");
+        cmd.process_result(0, &guarded).unwrap();
+        let err = block_on(future).unwrap_err().to_string();
+        assert!(err.contains("can't find pane"), "{}", err);
+
+        let (cmd, future) = awaited();
+        cmd.cancel();
+        assert!(block_on(future).is_err());
+    }
+
+    #[test]
+    fn captured_cursor() {
+        assert_eq!(parse_cursor("4 1\n").unwrap(), (4, 1));
+        assert_eq!(parse_cursor("0 0").unwrap(), (0, 0));
+        assert!(parse_cursor("$ ls\n").is_err());
+        assert!(parse_cursor("4\n").is_err());
+        assert!(parse_cursor("").is_err());
+    }
+
+    #[test]
+    fn commands() {
+        let new_window = NewWindow {
+            target: None,
+            cwd: None,
+            command: None,
+            promise: RefCell::new(None),
+        };
+        assert_eq!(
+            new_window.get_command(),
+            "new-window -P -F '#{window_id} #{pane_id}'\n"
+        );
+
+        let new_window = NewWindow {
+            target: None,
+            cwd: Some("/home/o'brien".to_owned()),
+            command: None,
+            promise: RefCell::new(None),
+        };
+        assert_eq!(
+            new_window.get_command(),
+            "new-window -P -F '#{window_id} #{pane_id}' -c '/home/o'\\''brien'\n"
+        );
+
+        let new_window = NewWindow {
+            target: None,
+            cwd: None,
+            command: Some(vec!["vim".to_owned(), "my file".to_owned()]),
+            promise: RefCell::new(None),
+        };
+        assert_eq!(
+            new_window.get_command(),
+            "new-window -P -F '#{window_id} #{pane_id}' 'vim' 'my file'\n"
+        );
+
+        let new_window = NewWindow {
+            target: Some("=work:".to_owned()),
+            cwd: None,
+            command: None,
+            promise: RefCell::new(None),
+        };
+        assert_eq!(
+            new_window.get_command(),
+            "new-window -P -F '#{window_id} #{pane_id}' -t '=work:'\n"
+        );
+
+        assert_eq!(
+            SwitchSession {
+                session: "work".to_owned(),
+                create: true
+            }
+            .get_command(),
+            "switch-client -t '=work'\n"
+        );
+        assert_eq!(
+            EnableFlowControl { pause_after: 2 }.get_command(),
+            "refresh-client -f pause-after=2\n"
+        );
+        assert_eq!(
+            ServerIdentity.get_command(),
+            "display-message -p '#{pid} #{socket_path} #{session_id}'\n"
+        );
+        assert_eq!(
+            ContinuePane { pane: 3 }.get_command(),
+            "refresh-client -A '%3:continue'\n"
+        );
+        assert_eq!(
+            CapturePane {
+                pane: 3,
+                captured: Rc::new(RefCell::new(None)),
+            }
+            .get_command(),
+            "capture-pane -p -e -t %3\n"
+        );
+        assert_eq!(
+            CaptureCursor {
+                pane: 3,
+                captured: Rc::new(RefCell::new(None)),
+            }
+            .get_command(),
+            "display-message -p -t %3 '#{cursor_x} #{cursor_y}'\n"
+        );
+        assert_eq!(
+            HistorySize { pane: 3 }.get_command(),
+            "display-message -p -t %3 '#{history_size}'\n"
+        );
+        assert_eq!(
+            CaptureHistory {
+                domain_id: 0,
+                pane: 3,
+                rows: -1200..-200,
+                physical_top: Cell::new(Some(50)),
+            }
+            .get_command(),
+            "capture-pane -p -e -S -1250 -E -251 -t %3\n"
+        );
+        // The top of the screen is resolved once, when the command is
+        // sent; here, the pane no longer exists
+        let capture = CaptureHistory {
+            domain_id: 0,
+            pane: 3,
+            rows: -1200..-200,
+            physical_top: Cell::new(None),
+        };
+        assert_eq!(
+            capture.get_command(),
+            "capture-pane -p -e -S -1200 -E -201 -t %3\n"
+        );
+        assert_eq!(capture.physical_top.get(), Some(0));
+        assert_eq!(
+            NewSession {
+                session: "work".to_owned()
+            }
+            .get_command(),
+            "new-session -d -s 'work'\n"
+        );
+
+        assert_eq!(
+            SplitWindow {
+                pane: 2,
+                direction: SplitDirection::Horizontal,
+                cwd: None,
+                command: None,
+                promise: RefCell::new(None),
+            }
+            .get_command(),
+            "split-window -P -F '#{window_id} #{pane_id}' -h -t %2\n"
+        );
+        assert_eq!(
+            SplitWindow {
+                pane: 2,
+                direction: SplitDirection::Vertical,
+                cwd: Some("/tmp".to_owned()),
+                command: Some(vec!["top".to_owned()]),
+                promise: RefCell::new(None),
+            }
+            .get_command(),
+            "split-window -P -F '#{window_id} #{pane_id}' -v -t %2 -c '/tmp' 'top'\n"
+        );
+        assert_eq!(
+            ResizePane {
+                pane: 2,
+                cols: 40,
+                rows: 24
+            }
+            .get_command(),
+            "resize-pane -t %2 -x 40 -y 24\n"
+        );
+
+        assert_eq!(
+            ResizeWindow {
+                window: 1,
+                cols: 80,
+                rows: 24
+            }
+            .get_command(),
+            "resize-window -t @1 -x 80 -y 24\n"
+        );
+
+        assert_eq!(
+            SendKeys {
+                pane: 2,
+                keys: b"ls\r".to_vec()
+            }
+            .get_command(),
+            "send-keys -H -t %2 6c 73 0d\n"
+        );
+
+        assert_eq!(
+            RefreshClient { cols: 80, rows: 24 }.get_command(),
+            "refresh-client -C 80x24\n"
+        );
+
+        assert_eq!(KillPane { pane: 3 }.get_command(), "kill-pane -t %3\n");
+        assert_eq!(DetachClient.get_command(), "detach-client\n");
+    }
+
+    #[test]
+    fn parse_new_window_output() {
+        assert_eq!(parse_window_and_pane("@1 %2\n").unwrap(), (1, 2));
+        assert!(parse_window_and_pane("1 2").is_err());
+    }
+
+    #[test]
+    fn parse_list_panes() {
+        let item = parse_pane_item("$1 @2 %3 0 4 5 80 24 0 0 250 b25d,80x24,0,0,3").unwrap();
+        assert_eq!(item.session_id, 1);
+        assert_eq!(item.window_id, 2);
+        assert_eq!(item.pane_id, 3);
+        assert_eq!((item.cursor_x, item.cursor_y), (4, 5));
+        assert_eq!((item.pane_width, item.pane_height), (80, 24));
+        assert_eq!(item.history_size, 250);
+        assert_eq!(item.window_layout, "b25d,80x24,0,0,3");
+        assert_eq!(item.window_name, None);
+
+        let item = parse_pane_item("$1 @2 %3 0 4 5 80 24 0 0 250 b25d,80x24,0,0,3 ").unwrap();
+        assert_eq!(item.window_name, None);
+
+        let item =
+            parse_pane_item("$1 @3 %4 0 0 0 120 40 0 0 0 1d2c,120x40,0,0,4 my editor").unwrap();
+        assert_eq!(item.window_id, 3);
+        assert_eq!((item.pane_width, item.pane_height), (120, 40));
+        assert_eq!(item.window_name.as_deref(), Some("my editor"));
+
+        // Names are reported in their escaped form
+        let item =
+            parse_pane_item("$1 @3 %4 0 0 0 120 40 0 0 0 1d2c,120x40,0,0,4 caf\u{e9}\\011\\\\")
+                .unwrap();
+        assert_eq!(item.window_name.as_deref(), Some("caf\u{e9}\t\\"));
+
+        // Truncated rows, missing sigils and empty fields are rejected
+        // rather than panicking
+        assert!(parse_pane_item("$1 @2 %3 0 4 5 80").is_err());
+        assert!(parse_pane_item("$1 @2 %3 0 4 5 80 24 0 0 250").is_err());
+        assert!(parse_pane_item("1 @2 %3 0 4 5 80 24 0 0 250 b25d,80x24,0,0,3").is_err());
+        assert!(parse_pane_item("$1  %3 0 4 5 80 24 0 0 250 b25d,80x24,0,0,3").is_err());
+    }
+}
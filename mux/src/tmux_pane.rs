@@ -0,0 +1,527 @@
+//! A pane that mirrors a pane in a remote tmux session.
+//! Output is fed to us by the TmuxDomain as it parses `%output`
+//! notifications from the control mode stream, and input is
+//! relayed back to tmux via `send-keys`.
+//! The history that tmux held for the pane before we attached is
+//! not part of that output; it is fetched in chunks via
+//! `capture-pane` as the user scrolls up into it.
+
+use crate::domain::DomainId;
+use crate::localpane::LocalPaneNotifHandler;
+use crate::pane::{alloc_pane_id, Pane, PaneId};
+use crate::renderable::*;
+use crate::tmux::get_tmux_domain;
+use crate::tmux_commands::{CaptureHistory, HistorySize, KillPane, SendKeys};
+use config::keyassignment::ScrollbackEraseMode;
+use filedescriptor::{FileDescriptor, Pipe};
+use portable_pty::PtySize;
+use rangeset::RangeSet;
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use termwiz::surface::Line;
+use tmux_cc::{TmuxPaneId, TmuxWindowId};
+use url::Url;
+use wezterm_term::color::ColorPalette;
+use wezterm_term::{KeyCode, KeyModifiers, MouseEvent, StableRowIndex, Terminal};
+
+/// The writer half of the terminal model; anything that the terminal
+/// wants to send to the "pty" is turned into a `send-keys` command
+/// for the corresponding remote pane.
+struct TmuxPaneWriter {
+    domain_id: DomainId,
+    tmux_pane: TmuxPaneId,
+}
+
+impl std::io::Write for TmuxPaneWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let domain_id = self.domain_id;
+        let pane = self.tmux_pane;
+        let keys = buf.to_vec();
+        // The terminal model may be driven from outside of the main
+        // thread, so bounce the command over to it
+        promise::spawn::spawn_into_main_thread(async move {
+            if let Some(tmux) = get_tmux_domain(domain_id) {
+                tmux.queue_command(Box::new(SendKeys { pane, keys }));
+            }
+        })
+        .detach();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How many rows of remote history to fetch at a time
+const HISTORY_CHUNK: StableRowIndex = 1000;
+
+/// The rows of remote history that lie above the scrollback of our
+/// terminal model.  They are addressed by stable row index, where
+/// the oldest row that tmux holds is `history_size` rows above the
+/// top of the screen.
+#[derive(Default)]
+struct RemoteHistory {
+    /// The oldest row that tmux holds, once we know its history size
+    oldest: Option<StableRowIndex>,
+    lines: HashMap<StableRowIndex, Line>,
+    /// The chunks that are being fetched, keyed by their first row
+    requested: HashSet<StableRowIndex>,
+    /// Rows that were fetched but have not yet been rendered
+    dirty: RangeSet<StableRowIndex>,
+}
+
+impl RemoteHistory {
+    fn chunk_start(row: StableRowIndex) -> StableRowIndex {
+        row - row.rem_euclid(HISTORY_CHUNK)
+    }
+
+    /// Records the history size reported by tmux.  The history
+    /// shrinks when it is cleared or trimmed to the history limit,
+    /// in which case the rows that tmux no longer holds are dropped.
+    fn set_size(&mut self, size: StableRowIndex, physical_top: StableRowIndex) {
+        let oldest = physical_top - size;
+        self.oldest.replace(oldest);
+        self.lines.retain(|&row, _| row >= oldest);
+        self.dirty.remove_range(StableRowIndex::MIN..oldest);
+    }
+
+    /// Returns the chunks of `rows` that need to be fetched; rows at
+    /// or below `local_top` are held by the terminal model instead.
+    /// The returned chunks are considered to have been requested.
+    fn chunks_to_fetch(
+        &mut self,
+        rows: Range<StableRowIndex>,
+        local_top: StableRowIndex,
+    ) -> Vec<Range<StableRowIndex>> {
+        let oldest = match self.oldest {
+            Some(oldest) => oldest,
+            None => return vec![],
+        };
+        let start = rows.start.max(oldest);
+        let end = rows.end.min(local_top);
+        let mut chunks = vec![];
+        let mut chunk = Self::chunk_start(start);
+        while chunk < end {
+            let range = chunk.max(oldest)..(chunk + HISTORY_CHUNK).min(local_top);
+            if !self.requested.contains(&chunk)
+                && range.clone().any(|row| !self.lines.contains_key(&row))
+            {
+                self.requested.insert(chunk);
+                chunks.push(range);
+            }
+            chunk += HISTORY_CHUNK;
+        }
+        chunks
+    }
+
+    /// Stores the lines that were fetched for `rows`
+    fn insert(&mut self, rows: Range<StableRowIndex>, lines: Vec<Line>, local_top: StableRowIndex) {
+        self.requested.remove(&Self::chunk_start(rows.start));
+        let oldest = self.oldest.unwrap_or(rows.start);
+        for (row, line) in rows.zip(lines) {
+            if row >= oldest && row < local_top {
+                self.lines.insert(row, line);
+                self.dirty.add(row);
+            }
+        }
+    }
+
+    /// Returns the lines for `rows`; rows that haven't been fetched
+    /// yet are blank
+    fn get(&mut self, rows: Range<StableRowIndex>, cols: usize) -> Vec<Line> {
+        self.dirty.remove_range(rows.clone());
+        rows.map(|row| {
+            self.lines
+                .get(&row)
+                .cloned()
+                .unwrap_or_else(|| Line::with_width(cols))
+        })
+        .collect()
+    }
+}
+
+/// Parses the output of `capture-pane -e` into lines of `cols` cells
+fn captured_lines(text: &[u8], cols: usize) -> Vec<Line> {
+    let mut text: Vec<&[u8]> = text.split(|&b| b == b'\n').collect();
+    // Each line is terminated by a newline, rather than separated by one
+    if text.last().map_or(false, |line| line.is_empty()) {
+        text.pop();
+    }
+    let size = PtySize {
+        rows: text.len().max(1) as u16,
+        cols: cols as u16,
+        pixel_width: 0,
+        pixel_height: 0,
+    };
+    let mut terminal = Terminal::new(
+        crate::pty_size_to_terminal_size(size),
+        std::sync::Arc::new(config::TermConfig {}),
+        "WezTerm",
+        config::wezterm_version(),
+        Box::new(std::io::sink()),
+    );
+    terminal.advance_bytes(text.join(&b"\r\n"[..]));
+    let dims = terminal_get_dimensions(&mut terminal);
+    let rows = dims.physical_top..dims.physical_top + text.len() as StableRowIndex;
+    terminal_get_lines(&mut terminal, rows).1
+}
+
+pub struct TmuxPane {
+    pane_id: PaneId,
+    domain_id: DomainId,
+    tmux_window: TmuxWindowId,
+    tmux_pane: TmuxPaneId,
+    terminal: RefCell<Terminal>,
+    writer: RefCell<TmuxPaneWriter>,
+    dead: RefCell<bool>,
+    /// The name of the remote window; when set, it is used as
+    /// the title in place of the title set by the application
+    window_name: RefCell<Option<String>>,
+    /// The mux wants a reader to pump output into the terminal, but
+    /// our output arrives via the control channel and is applied
+    /// directly in `advance_bytes`.  We hand out the read end of this
+    /// pipe and close the write end when the pane is killed, which
+    /// allows the reader thread to terminate.
+    output_read: FileDescriptor,
+    output_write: RefCell<Option<FileDescriptor>>,
+    history: RefCell<RemoteHistory>,
+}
+
+impl TmuxPane {
+    pub(crate) fn new(
+        domain_id: DomainId,
+        tmux_window: TmuxWindowId,
+        tmux_pane: TmuxPaneId,
+        size: PtySize,
+    ) -> anyhow::Result<Self> {
+        let pane_id = alloc_pane_id();
+        let pipe = Pipe::new()?;
+
+        let mut terminal = Terminal::new(
+            crate::pty_size_to_terminal_size(size),
+            std::sync::Arc::new(config::TermConfig {}),
+            "WezTerm",
+            config::wezterm_version(),
+            Box::new(TmuxPaneWriter {
+                domain_id,
+                tmux_pane,
+            }),
+        );
+        // tmux passes the bell through in the output of the pane, so
+        // it rings for us just as it would for a local pane
+        terminal.set_notification_handler(Box::new(LocalPaneNotifHandler { pane_id }));
+
+        Ok(Self {
+            pane_id,
+            domain_id,
+            tmux_window,
+            tmux_pane,
+            terminal: RefCell::new(terminal),
+            writer: RefCell::new(TmuxPaneWriter {
+                domain_id,
+                tmux_pane,
+            }),
+            dead: RefCell::new(false),
+            window_name: RefCell::new(None),
+            output_read: pipe.read,
+            output_write: RefCell::new(Some(pipe.write)),
+            history: RefCell::new(RemoteHistory::default()),
+        })
+    }
+
+    pub fn tmux_window_id(&self) -> TmuxWindowId {
+        self.tmux_window
+    }
+
+    pub fn tmux_pane_id(&self) -> TmuxPaneId {
+        self.tmux_pane
+    }
+
+    pub(crate) fn set_window_name(&self, name: Option<String>) {
+        *self.window_name.borrow_mut() = name;
+    }
+
+    /// Apply output from the remote pane to our terminal model
+    pub(crate) fn advance_bytes(&self, bytes: &[u8]) {
+        self.terminal.borrow_mut().advance_bytes(bytes);
+    }
+
+    /// Resize the terminal model without informing tmux; used when
+    /// tmux tells us about a change in the size of the remote pane
+    pub(crate) fn resize_terminal(&self, size: PtySize) {
+        self.terminal.borrow_mut().resize(
+            size.rows as usize,
+            size.cols as usize,
+            size.pixel_width as usize,
+            size.pixel_height as usize,
+        );
+    }
+
+    /// Records the number of rows of history that tmux holds for
+    /// the pane, which allows the user to scroll back into them
+    pub(crate) fn set_history_size(&self, size: u64) {
+        let physical_top = terminal_get_dimensions(&mut self.terminal.borrow_mut()).physical_top;
+        self.history
+            .borrow_mut()
+            .set_size(size as StableRowIndex, physical_top);
+    }
+
+    /// Returns the stable row index of the top of the screen
+    pub(crate) fn physical_top(&self) -> StableRowIndex {
+        terminal_get_dimensions(&mut self.terminal.borrow_mut()).physical_top
+    }
+
+    /// Stores the rows of history that were fetched by `CaptureHistory`
+    pub(crate) fn history_captured(&self, rows: Range<StableRowIndex>, text: &[u8]) {
+        let dims = terminal_get_dimensions(&mut self.terminal.borrow_mut());
+        let lines = captured_lines(text, dims.cols);
+        self.history
+            .borrow_mut()
+            .insert(rows, lines, dims.scrollback_top);
+    }
+
+    /// Asks tmux for the chunks of history that the user scrolled
+    /// into.  The history size is checked first, so that history that
+    /// was cleared in the meantime isn't shown.
+    fn fetch_history(&self, chunks: Vec<Range<StableRowIndex>>) {
+        let domain_id = self.domain_id;
+        let pane = self.tmux_pane;
+        // This is called while the pane is being rendered, so defer
+        // queueing the commands rather than borrowing the domain here
+        promise::spawn::spawn_into_main_thread(async move {
+            if let Some(tmux) = get_tmux_domain(domain_id) {
+                tmux.queue_command(Box::new(HistorySize { pane }));
+                for rows in chunks {
+                    tmux.queue_command(Box::new(CaptureHistory {
+                        domain_id,
+                        pane,
+                        rows,
+                        physical_top: Cell::new(None),
+                    }));
+                }
+            }
+        })
+        .detach();
+    }
+
+    /// Mark the pane as dead; this happens when the remote pane
+    /// goes away, or when we are no longer attached to tmux.
+    pub(crate) fn mark_dead(&self) {
+        *self.dead.borrow_mut() = true;
+        self.output_write.borrow_mut().take();
+    }
+}
+
+impl Pane for TmuxPane {
+    fn pane_id(&self) -> PaneId {
+        self.pane_id
+    }
+
+    fn get_cursor_position(&self) -> StableCursorPosition {
+        terminal_get_cursor_position(&mut self.terminal.borrow_mut())
+    }
+
+    fn get_dirty_lines(&self, lines: Range<StableRowIndex>) -> RangeSet<StableRowIndex> {
+        let mut dirty = terminal_get_dirty_lines(&mut self.terminal.borrow_mut(), lines.clone());
+        dirty.add_set(&self.history.borrow().dirty.intersection_with_range(lines));
+        dirty
+    }
+
+    fn get_lines(&self, lines: Range<StableRowIndex>) -> (StableRowIndex, Vec<Line>) {
+        let mut terminal = self.terminal.borrow_mut();
+        let dims = terminal_get_dimensions(&mut terminal);
+        if lines.start >= dims.scrollback_top {
+            return terminal_get_lines(&mut terminal, lines);
+        }
+
+        let mut history = self.history.borrow_mut();
+        let oldest = history.oldest.unwrap_or(dims.scrollback_top);
+        let start = lines.start.max(oldest).min(dims.scrollback_top);
+        let history_end = lines.end.min(dims.scrollback_top);
+        let chunks = history.chunks_to_fetch(start..history_end, dims.scrollback_top);
+        let mut result = history.get(start..history_end, dims.cols);
+        drop(history);
+        if lines.end > dims.scrollback_top {
+            let (_, mut local) = terminal_get_lines(&mut terminal, dims.scrollback_top..lines.end);
+            result.append(&mut local);
+        }
+
+        if !chunks.is_empty() {
+            self.fetch_history(chunks);
+        }
+        (start, result)
+    }
+
+    fn get_dimensions(&self) -> RenderableDimensions {
+        let mut dims = terminal_get_dimensions(&mut self.terminal.borrow_mut());
+        if let Some(oldest) = self.history.borrow().oldest {
+            if oldest < dims.scrollback_top {
+                dims.scrollback_rows += (dims.scrollback_top - oldest) as usize;
+                dims.scrollback_top = oldest;
+            }
+        }
+        dims
+    }
+
+    fn get_title(&self) -> String {
+        if let Some(name) = self.window_name.borrow().as_ref() {
+            return name.clone();
+        }
+        self.terminal.borrow_mut().get_title().to_string()
+    }
+
+    fn send_paste(&self, text: &str) -> anyhow::Result<()> {
+        self.terminal.borrow_mut().send_paste(text)
+    }
+
+    fn reader(&self) -> anyhow::Result<Box<dyn std::io::Read + Send>> {
+        Ok(Box::new(self.output_read.try_clone()?))
+    }
+
+    fn writer(&self) -> RefMut<dyn std::io::Write> {
+        self.writer.borrow_mut()
+    }
+
+    fn resize(&self, size: PtySize) -> anyhow::Result<()> {
+        self.resize_terminal(size);
+        if let Some(tmux) = get_tmux_domain(self.domain_id) {
+            tmux.pane_resized(self.tmux_window, self.tmux_pane, size);
+        }
+        Ok(())
+    }
+
+    fn key_down(&self, key: KeyCode, mods: KeyModifiers) -> anyhow::Result<()> {
+        self.terminal.borrow_mut().key_down(key, mods)
+    }
+
+    fn mouse_event(&self, event: MouseEvent) -> anyhow::Result<()> {
+        self.terminal.borrow_mut().mouse_event(event)
+    }
+
+    fn perform_actions(&self, actions: Vec<termwiz::escape::Action>) {
+        self.terminal.borrow_mut().perform_actions(actions)
+    }
+
+    fn is_dead(&self) -> bool {
+        *self.dead.borrow()
+    }
+
+    fn kill(&self) {
+        if self.is_dead() {
+            return;
+        }
+        self.mark_dead();
+        // Closing the local tab should also close the remote pane
+        if let Some(tmux) = get_tmux_domain(self.domain_id) {
+            tmux.queue_command(Box::new(KillPane {
+                pane: self.tmux_pane,
+            }));
+        }
+    }
+
+    fn palette(&self) -> ColorPalette {
+        self.terminal.borrow().palette()
+    }
+
+    fn domain_id(&self) -> DomainId {
+        self.domain_id
+    }
+
+    fn erase_scrollback(&self, erase_mode: ScrollbackEraseMode) {
+        match erase_mode {
+            ScrollbackEraseMode::ScrollbackOnly => {
+                self.terminal.borrow_mut().erase_scrollback();
+            }
+            ScrollbackEraseMode::ScrollbackAndViewport => {
+                self.terminal.borrow_mut().erase_scrollback_and_viewport();
+            }
+        }
+    }
+
+    fn focus_changed(&self, focused: bool) {
+        self.terminal.borrow_mut().focus_changed(focused);
+    }
+
+    fn is_mouse_grabbed(&self) -> bool {
+        self.terminal.borrow().is_mouse_grabbed()
+    }
+
+    fn is_alt_screen_active(&self) -> bool {
+        self.terminal.borrow().is_alt_screen_active()
+    }
+
+    fn get_current_working_dir(&self) -> Option<Url> {
+        self.terminal.borrow().get_current_dir().cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text_of(line: &Line) -> String {
+        line.as_str().trim_end().to_string()
+    }
+
+    #[test]
+    fn history_chunks() {
+        let mut history = RemoteHistory::default();
+        // Nothing is fetched until we know how much history there is
+        assert!(history.chunks_to_fetch(-50..0, 0).is_empty());
+
+        history.set_size(2500, 100);
+        assert_eq!(history.oldest, Some(-2400));
+        assert_eq!(
+            history.chunks_to_fetch(-1100..-900, 0),
+            vec![-2000..-1000, -1000..0]
+        );
+        // Chunks that are in flight are not requested again
+        assert!(history.chunks_to_fetch(-1100..-900, 0).is_empty());
+        // The oldest chunk is clipped to the start of the history
+        assert_eq!(history.chunks_to_fetch(-3000..-2300, 0), vec![-2400..-2000]);
+
+        history.insert(
+            -1000..0,
+            vec![Line::from_text("hello", &Default::default())],
+            0,
+        );
+        assert!(history.requested.contains(&-2000));
+        assert!(!history.requested.contains(&-1000));
+        assert_eq!(
+            history.dirty.iter().cloned().collect::<Vec<_>>(),
+            vec![-1000..-999]
+        );
+        let lines = history.get(-1001..-998, 10);
+        assert_eq!(
+            lines.iter().map(text_of).collect::<Vec<_>>(),
+            vec!["", "hello", ""]
+        );
+        assert!(history.dirty.is_empty());
+
+        // Clearing the history drops what we fetched
+        history.set_size(0, 200);
+        assert_eq!(history.oldest, Some(200));
+        assert!(history.lines.is_empty());
+        assert!(history.chunks_to_fetch(-1100..-900, 0).is_empty());
+
+        // A fetch that was cancelled stores nothing, but the chunk can
+        // be requested again
+        history.set_size(1500, 200);
+        history.insert(-2000..-1000, vec![], 0);
+        assert_eq!(
+            history.chunks_to_fetch(-1100..-900, 0),
+            vec![-1300..-1000, -1000..0]
+        );
+    }
+
+    #[test]
+    fn captured() {
+        let lines = captured_lines(b"one\n\x1b[1mtwo\x1b[0m\n\nfour\n", 10);
+        assert_eq!(
+            lines.iter().map(text_of).collect::<Vec<_>>(),
+            vec!["one", "two", "", "four"]
+        );
+    }
+}
@@ -59,6 +59,12 @@ impl CommandBuilder {
         self.args.is_empty()
     }
 
+    /// Returns the argument vector; this is empty for a builder
+    /// created via `new_default_prog`
+    pub fn get_argv(&self) -> &Vec<OsString> {
+        &self.args
+    }
+
     /// Append an argument to the current command line.
     /// Will panic if called on a builder created via `new_default_prog`.
     pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) {
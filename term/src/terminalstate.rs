@@ -553,6 +553,8 @@ impl TerminalState {
             MouseButton::Right => 2,
             MouseButton::WheelUp(_) => 64,
             MouseButton::WheelDown(_) => 65,
+            MouseButton::WheelLeft(_) => 66,
+            MouseButton::WheelRight(_) => 67,
         };
 
         if event.modifiers.contains(KeyModifiers::SHIFT) {
@@ -593,15 +595,15 @@ impl TerminalState {
             self.writer.flush()?;
         } else if self.screen.is_alt_screen_active() {
             // Send cursor keys instead (equivalent to xterm's alternateScroll mode)
+            let key = match event.button {
+                MouseButton::WheelDown(_) => KeyCode::DownArrow,
+                MouseButton::WheelUp(_) => KeyCode::UpArrow,
+                // alternateScroll only applies to the vertical wheel
+                MouseButton::WheelLeft(_) | MouseButton::WheelRight(_) => return Ok(()),
+                _ => bail!("unexpected mouse event"),
+            };
             for _ in 0..self.config.alternate_buffer_wheel_scroll_speed() {
-                self.key_down(
-                    match event.button {
-                        MouseButton::WheelDown(_) => KeyCode::DownArrow,
-                        MouseButton::WheelUp(_) => KeyCode::UpArrow,
-                        _ => bail!("unexpected mouse event"),
-                    },
-                    KeyModifiers::default(),
-                )?;
+                self.key_down(key, KeyModifiers::default())?;
             }
         }
         Ok(())
@@ -722,6 +724,16 @@ impl TerminalState {
                 kind: MouseEventKind::Press,
                 button: MouseButton::WheelDown(_),
                 ..
+            }
+            | MouseEvent {
+                kind: MouseEventKind::Press,
+                button: MouseButton::WheelLeft(_),
+                ..
+            }
+            | MouseEvent {
+                kind: MouseEventKind::Press,
+                button: MouseButton::WheelRight(_),
+                ..
             } => self.mouse_wheel(event),
             MouseEvent {
                 kind: MouseEventKind::Press,
@@ -0,0 +1,284 @@
+//! Parses the window layouts that tmux reports via `#{window_layout}`
+//! and `%layout-change`, such as `b25d,80x24,0,0{40x24,0,0,1,39x24,41,0,2}`.
+//! The layout begins with a checksum of the remainder, which describes
+//! a tree of cells.  Each cell has a size and a position, and is either
+//! a pane or a list of cells that are arranged left-to-right (`{}`) or
+//! top-to-bottom (`[]`).
+use crate::TmuxPaneId;
+use anyhow::{anyhow, bail};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutCell {
+    pub width: u64,
+    pub height: u64,
+    pub x: u64,
+    pub y: u64,
+    pub content: LayoutContent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutContent {
+    Pane(TmuxPaneId),
+    /// Cells that are side by side
+    LeftRight(Vec<LayoutCell>),
+    /// Cells that are stacked one above the other
+    TopBottom(Vec<LayoutCell>),
+}
+
+impl LayoutCell {
+    /// Returns the ids of the panes in the layout, in the order that
+    /// they appear in it
+    pub fn panes(&self) -> Vec<TmuxPaneId> {
+        let mut panes = vec![];
+        self.collect_panes(&mut panes);
+        panes
+    }
+
+    fn collect_panes(&self, panes: &mut Vec<TmuxPaneId>) {
+        match &self.content {
+            LayoutContent::Pane(pane) => panes.push(*pane),
+            LayoutContent::LeftRight(cells) | LayoutContent::TopBottom(cells) => {
+                for cell in cells {
+                    cell.collect_panes(panes);
+                }
+            }
+        }
+    }
+}
+
+/// Parses a layout, including its checksum prefix
+pub fn parse_layout(layout: &str) -> anyhow::Result<LayoutCell> {
+    let comma = layout
+        .find(',')
+        .ok_or_else(|| anyhow!("layout {:?} has no checksum", layout))?;
+    let checksum = u16::from_str_radix(&layout[..comma], 16)
+        .map_err(|err| anyhow!("invalid checksum in layout {:?}: {}", layout, err))?;
+    let body = &layout[comma + 1..];
+    let expected = layout_checksum(body);
+    if checksum != expected {
+        bail!(
+            "layout {:?} has checksum {:04x} but should be {:04x}",
+            layout,
+            checksum,
+            expected
+        );
+    }
+
+    let mut parser = LayoutParser {
+        bytes: body.as_bytes(),
+        pos: 0,
+    };
+    let cell = parser.cell()?;
+    if parser.pos != parser.bytes.len() {
+        bail!("unexpected {:?} at the end of layout", &body[parser.pos..]);
+    }
+    Ok(cell)
+}
+
+/// Computes the checksum that tmux prefixes to a layout;
+/// see `layout_checksum` in tmux's layout-custom.c
+fn layout_checksum(body: &str) -> u16 {
+    let mut csum: u16 = 0;
+    for b in body.bytes() {
+        csum = (csum >> 1) | ((csum & 1) << 15);
+        csum = csum.wrapping_add(u16::from(b));
+    }
+    csum
+}
+
+struct LayoutParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> LayoutParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, expected: u8) -> anyhow::Result<()> {
+        match self.peek() {
+            Some(b) if b == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(b) => bail!(
+                "expected {:?} but found {:?} at offset {}",
+                expected as char,
+                b as char,
+                self.pos
+            ),
+            None => bail!("expected {:?} but the layout ended", expected as char),
+        }
+    }
+
+    fn number(&mut self) -> anyhow::Result<u64> {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            bail!("expected a number at offset {}", start);
+        }
+        // The digits are ASCII, so this is valid UTF-8
+        Ok(std::str::from_utf8(&self.bytes[start..self.pos])?.parse()?)
+    }
+
+    /// `WxH,X,Y` followed by either `,ID`, `{cells}` or `[cells]`
+    fn cell(&mut self) -> anyhow::Result<LayoutCell> {
+        let width = self.number()?;
+        self.expect(b'x')?;
+        let height = self.number()?;
+        self.expect(b',')?;
+        let x = self.number()?;
+        self.expect(b',')?;
+        let y = self.number()?;
+
+        let content = match self.peek() {
+            Some(b',') => {
+                self.pos += 1;
+                LayoutContent::Pane(self.number()?)
+            }
+            Some(b'{') => LayoutContent::LeftRight(self.cells(b'{', b'}')?),
+            Some(b'[') => LayoutContent::TopBottom(self.cells(b'[', b']')?),
+            _ => bail!(
+                "expected a pane id or a list of cells at offset {}",
+                self.pos
+            ),
+        };
+
+        Ok(LayoutCell {
+            width,
+            height,
+            x,
+            y,
+            content,
+        })
+    }
+
+    fn cells(&mut self, open: u8, close: u8) -> anyhow::Result<Vec<LayoutCell>> {
+        self.expect(open)?;
+        let mut cells = vec![self.cell()?];
+        while self.peek() == Some(b',') {
+            self.pos += 1;
+            cells.push(self.cell()?);
+        }
+        self.expect(close)?;
+        Ok(cells)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn pane(width: u64, height: u64, x: u64, y: u64, pane: TmuxPaneId) -> LayoutCell {
+        LayoutCell {
+            width,
+            height,
+            x,
+            y,
+            content: LayoutContent::Pane(pane),
+        }
+    }
+
+    #[test]
+    fn single_pane() {
+        assert_eq!(
+            parse_layout("b25d,80x24,0,0,0").unwrap(),
+            pane(80, 24, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn left_right() {
+        assert_eq!(
+            parse_layout("020a,80x24,0,0{40x24,0,0,1,39x24,41,0,2}").unwrap(),
+            LayoutCell {
+                width: 80,
+                height: 24,
+                x: 0,
+                y: 0,
+                content: LayoutContent::LeftRight(vec![
+                    pane(40, 24, 0, 0, 1),
+                    pane(39, 24, 41, 0, 2)
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn top_bottom() {
+        assert_eq!(
+            parse_layout("c196,80x24,0,0[80x12,0,0,0,80x11,0,13,3]").unwrap(),
+            LayoutCell {
+                width: 80,
+                height: 24,
+                x: 0,
+                y: 0,
+                content: LayoutContent::TopBottom(vec![
+                    pane(80, 12, 0, 0, 0),
+                    pane(80, 11, 0, 13, 3)
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let body = "159x48,0,0{79x48,0,0,0,79x48,80,0[79x24,80,0,1,79x23,80,25{39x23,80,25,2,39x23,120,25,3}]}";
+        let layout = format!("{:04x},{}", layout_checksum(body), body);
+        let cell = parse_layout(&layout).unwrap();
+        assert_eq!(cell.panes(), vec![0, 1, 2, 3]);
+        assert_eq!(
+            cell.content,
+            LayoutContent::LeftRight(vec![
+                pane(79, 48, 0, 0, 0),
+                LayoutCell {
+                    width: 79,
+                    height: 48,
+                    x: 80,
+                    y: 0,
+                    content: LayoutContent::TopBottom(vec![
+                        pane(79, 24, 80, 0, 1),
+                        LayoutCell {
+                            width: 79,
+                            height: 23,
+                            x: 80,
+                            y: 25,
+                            content: LayoutContent::LeftRight(vec![
+                                pane(39, 23, 80, 25, 2),
+                                pane(39, 23, 120, 25, 3)
+                            ]),
+                        }
+                    ]),
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn checksum() {
+        // As reported by tmux for the first window of a new server
+        assert_eq!(layout_checksum("80x24,0,0,0"), 0xb25d);
+
+        assert!(parse_layout("b25e,80x24,0,0,0").is_err());
+        assert!(parse_layout("80x24,0,0,0").is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        for body in &[
+            "",
+            "80x24,0,0",
+            "80x24,0,0{40x24,0,0,1",
+            "80x24,0,0{}",
+            "80x24,0,0[40x24,0,0,1}",
+            "80x24,0,0,2,",
+        ] {
+            let layout = format!("{:04x},{}", layout_checksum(body), body);
+            assert!(parse_layout(&layout).is_err(), "{}", layout);
+        }
+    }
+}
@@ -7,6 +7,8 @@ pub type TmuxWindowId = u64;
 pub type TmuxPaneId = u64;
 pub type TmuxSessionId = u64;
 
+pub mod layout;
+
 mod parser {
     use pest_derive::Parser;
     #[derive(Parser)]
@@ -14,13 +16,29 @@ mod parser {
     pub struct TmuxParser;
 }
 
+/// The response to a command, which tmux brackets with `%begin`
+/// and either `%end` or `%error`
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Guarded {
     pub error: bool,
     pub timestamp: i64,
     pub number: u64,
     pub flags: i64,
-    pub output: String,
+    /// The lines of the response, each terminated by `\n`.  This is
+    /// kept as bytes because it may include the contents of a pane,
+    /// which need not be UTF-8.
+    pub output: Vec<u8>,
+}
+
+impl Guarded {
+    /// Returns the output as text, replacing any invalid UTF-8
+    pub fn text(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.output)
+    }
+
+    fn is_closed_by(&self, timestamp: i64, number: u64, flags: i64) -> bool {
+        self.timestamp == timestamp && self.number == number && self.flags == flags
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -43,17 +61,35 @@ pub enum Event {
     Guarded(Guarded),
     Output {
         pane: TmuxPaneId,
-        text: String,
+        /// The raw bytes output by the pane; this may contain control
+        /// characters and need not be valid UTF-8
+        text: Vec<u8>,
+    },
+    /// The pane has been paused because we haven't kept up with
+    /// its output; tmux discards output until we ask it to continue
+    Pause {
+        pane: TmuxPaneId,
+    },
+    Continue {
+        pane: TmuxPaneId,
     },
     Exit {
         reason: Option<String>,
     },
+    LayoutChange {
+        window: TmuxWindowId,
+        layout: String,
+        visible_layout: Option<String>,
+        raw_flags: Option<String>,
+    },
     SessionsChanged,
     SessionChanged {
         session: TmuxSessionId,
         name: String,
     },
     SessionRenamed {
+        /// Only reported by tmux 2.3 and later
+        session: Option<TmuxSessionId>,
         name: String,
     },
     SessionWindowChanged {
@@ -74,6 +110,9 @@ pub enum Event {
     WindowClose {
         window: TmuxWindowId,
     },
+    UnlinkedWindowAdd {
+        window: TmuxWindowId,
+    },
     WindowPaneChanged {
         window: TmuxWindowId,
         pane: TmuxPaneId,
@@ -176,6 +215,19 @@ fn parse_line(line: &str) -> anyhow::Result<Event> {
             let reason = pairs.next().map(|pair| pair.as_str().to_owned());
             Ok(Event::Exit { reason })
         }
+        Rule::layout_change => {
+            let mut pairs = pair.into_inner();
+            let window = parse_window_id(pairs.next().unwrap())?;
+            let layout = pairs.next().unwrap().as_str().to_owned();
+            let visible_layout = pairs.next().map(|pair| pair.as_str().to_owned());
+            let raw_flags = pairs.next().map(|pair| pair.as_str().to_owned());
+            Ok(Event::LayoutChange {
+                window,
+                layout,
+                visible_layout,
+                raw_flags,
+            })
+        }
         Rule::sessions_changed => Ok(Event::SessionsChanged),
         Rule::pane_mode_changed => {
             let mut pairs = pair.into_inner();
@@ -192,6 +244,11 @@ fn parse_line(line: &str) -> anyhow::Result<Event> {
             let window = parse_window_id(pairs.next().unwrap())?;
             Ok(Event::WindowClose { window })
         }
+        Rule::unlinked_window_add => {
+            let mut pairs = pair.into_inner();
+            let window = parse_window_id(pairs.next().unwrap())?;
+            Ok(Event::UnlinkedWindowAdd { window })
+        }
         Rule::window_pane_changed => {
             let mut pairs = pair.into_inner();
             let window = parse_window_id(pairs.next().unwrap())?;
@@ -207,9 +264,28 @@ fn parse_line(line: &str) -> anyhow::Result<Event> {
         Rule::output => {
             let mut pairs = pair.into_inner();
             let pane = parse_pane_id(pairs.next().unwrap())?;
-            let text = unvis(pairs.next().unwrap().as_str())?;
+            let text = unvis_bytes(pairs.next().unwrap().as_str().as_bytes());
+            Ok(Event::Output { pane, text })
+        }
+        Rule::extended_output => {
+            // This is sent in place of %output when pause-after is
+            // enabled; we have no use for the age of the output
+            let mut pairs = pair.into_inner();
+            let pane = parse_pane_id(pairs.next().unwrap())?;
+            let _age = pairs.next().unwrap();
+            let text = unvis_bytes(pairs.next().unwrap().as_str().as_bytes());
             Ok(Event::Output { pane, text })
         }
+        Rule::pause_pane => {
+            let mut pairs = pair.into_inner();
+            let pane = parse_pane_id(pairs.next().unwrap())?;
+            Ok(Event::Pause { pane })
+        }
+        Rule::continue_pane => {
+            let mut pairs = pair.into_inner();
+            let pane = parse_pane_id(pairs.next().unwrap())?;
+            Ok(Event::Continue { pane })
+        }
         Rule::session_changed => {
             let mut pairs = pair.into_inner();
             let session = parse_session_id(pairs.next().unwrap())?;
@@ -229,8 +305,16 @@ fn parse_line(line: &str) -> anyhow::Result<Event> {
         }
         Rule::session_renamed => {
             let mut pairs = pair.into_inner();
-            let name = unvis(pairs.next().unwrap().as_str())?;
-            Ok(Event::SessionRenamed { name })
+            let mut next = pairs.next().unwrap();
+            let session = if next.as_rule() == Rule::session_id {
+                let session = parse_session_id(next)?;
+                next = pairs.next().unwrap();
+                Some(session)
+            } else {
+                None
+            };
+            let name = unvis(next.as_str())?;
+            Ok(Event::SessionRenamed { session, name })
         }
         Rule::session_window_changed => {
             let mut pairs = pair.into_inner();
@@ -241,6 +325,7 @@ fn parse_line(line: &str) -> anyhow::Result<Event> {
         Rule::pane_id
         | Rule::word
         | Rule::client_name
+        | Rule::layout
         | Rule::window_id
         | Rule::session_id
         | Rule::any_text
@@ -251,9 +336,20 @@ fn parse_line(line: &str) -> anyhow::Result<Event> {
     }
 }
 
-/// Decode OpenBSD `vis` encoded strings
+/// Decode OpenBSD `vis` encoded strings.
+/// tmux stores the names of windows and sessions in this form, so
+/// it applies to names obtained via formats such as `#{window_name}`
+/// as well as to those in notifications.
+pub fn unvis(s: &str) -> anyhow::Result<String> {
+    String::from_utf8(unvis_bytes(s.as_bytes()))
+        .map_err(|err| anyhow::anyhow!("Unescaped string is not valid UTF8: {}", err))
+}
+
+/// Decode OpenBSD `vis` encoded strings into the original bytes.
 /// See: https://github.com/tmux/tmux/blob/486ce9b09855ae30a2bf5e576cb6f7ad37792699/compat/unvis.c
-fn unvis(s: &str) -> anyhow::Result<String> {
+/// Unlike unvis(3), invalid or truncated escapes are passed through
+/// literally rather than being treated as an error.
+fn unvis_bytes(s: &[u8]) -> Vec<u8> {
     enum State {
         Ground,
         Start,
@@ -266,13 +362,13 @@ fn unvis(s: &str) -> anyhow::Result<String> {
 
     let mut state = State::Ground;
     let mut result: Vec<u8> = vec![];
-    let mut bytes = s.as_bytes().iter();
+    let mut bytes = s.iter();
 
     fn is_octal(b: u8) -> bool {
         b >= b'0' && b <= b'7'
     }
 
-    fn unvis_byte(b: u8, state: &mut State, result: &mut Vec<u8>) -> anyhow::Result<bool> {
+    fn unvis_byte(b: u8, state: &mut State, result: &mut Vec<u8>) -> bool {
         match state {
             State::Ground => {
                 if b == b'\\' {
@@ -345,7 +441,9 @@ fn unvis(s: &str) -> anyhow::Result<String> {
                     }
                     _ => {
                         // Invalid syntax
-                        anyhow::bail!("Invalid \\ escape: {}", b);
+                        result.push(b'\\');
+                        *state = State::Ground;
+                        return true;
                     }
                 }
             }
@@ -356,7 +454,9 @@ fn unvis(s: &str) -> anyhow::Result<String> {
                 } else if b == b'^' {
                     *state = State::Ctrl(0200);
                 } else {
-                    anyhow::bail!("invalid \\M escape: {}", b);
+                    result.extend_from_slice(b"\\M");
+                    *state = State::Ground;
+                    return true;
                 }
             }
 
@@ -384,7 +484,7 @@ fn unvis(s: &str) -> anyhow::Result<String> {
                     result.push(*prior);
                     *state = State::Ground;
                     // re-process the current byte
-                    return Ok(true);
+                    return true;
                 }
             }
 
@@ -399,23 +499,97 @@ fn unvis(s: &str) -> anyhow::Result<String> {
                     result.push(*prior);
                     *state = State::Ground;
                     // re-process the current byte
-                    return Ok(true);
+                    return true;
                 }
             }
         }
         // Don't process this byte again
-        Ok(false)
+        false
     }
 
     while let Some(&b) = bytes.next() {
-        let again = unvis_byte(b, &mut state, &mut result)?;
+        let again = unvis_byte(b, &mut state, &mut result);
         if again {
-            unvis_byte(b, &mut state, &mut result)?;
+            unvis_byte(b, &mut state, &mut result);
         }
     }
 
-    String::from_utf8(result)
-        .map_err(|err| anyhow::anyhow!("Unescaped string is not valid UTF8: {}", err))
+    // Flush an escape that was cut short by the end of the input
+    match state {
+        State::Ground => {}
+        State::Start => result.push(b'\\'),
+        State::Meta => result.extend_from_slice(b"\\M"),
+        State::Meta1 => result.extend_from_slice(b"\\M-"),
+        State::Ctrl(0) => result.extend_from_slice(b"\\^"),
+        State::Ctrl(_) => result.extend_from_slice(b"\\M^"),
+        State::Octal2(value) | State::Octal3(value) => result.push(value),
+    }
+
+    result
+}
+
+/// Parses `%output` and `%extended-output` notifications.
+/// These are handled as bytes rather than via the grammar, because the
+/// payload is the raw output of the pane rather than UTF-8; tmux only
+/// escapes control characters and backslashes, and a multi-byte
+/// sequence can be split across two notifications.
+/// Returns None if `line` is some other kind of line.
+fn parse_output_line(line: &[u8]) -> Option<anyhow::Result<Event>> {
+    const OUTPUT: &[u8] = b"%output ";
+    const EXTENDED_OUTPUT: &[u8] = b"%extended-output ";
+    if line.starts_with(OUTPUT) {
+        Some(parse_output(&line[OUTPUT.len()..], false))
+    } else if line.starts_with(EXTENDED_OUTPUT) {
+        Some(parse_output(&line[EXTENDED_OUTPUT.len()..], true))
+    } else {
+        None
+    }
+}
+
+fn parse_output(line: &[u8], extended: bool) -> anyhow::Result<Event> {
+    let space = line
+        .iter()
+        .position(|&b| b == b' ')
+        .ok_or_else(|| anyhow::anyhow!("missing output"))?;
+    let pane = std::str::from_utf8(&line[..space])?;
+    if !pane.starts_with('%') {
+        anyhow::bail!("invalid pane id {}", pane);
+    }
+    let pane = pane[1..].parse().context("pane_id is somehow not digits")?;
+    let mut payload = &line[space + 1..];
+    if extended {
+        // Skip the age of the output, and any fields that later
+        // versions of tmux may add, up to the separator
+        let sep = payload
+            .windows(3)
+            .position(|w| w == b" : ")
+            .ok_or_else(|| anyhow::anyhow!("missing separator"))?;
+        payload = &payload[sep + 3..];
+    }
+    Ok(Event::Output {
+        pane,
+        text: unvis_bytes(payload),
+    })
+}
+
+/// Returns `Some(error)` if `line` is the `%end` or `%error` that
+/// closes the block opened by `begun`, where `error` is true for
+/// `%error`
+fn closing_guard(line: &[u8], begun: &Guarded) -> Option<bool> {
+    let line = std::str::from_utf8(line).ok()?;
+    match parse_line(line) {
+        Ok(Event::End {
+            timestamp,
+            number,
+            flags,
+        }) if begun.is_closed_by(timestamp, number, flags) => Some(false),
+        Ok(Event::Error {
+            timestamp,
+            number,
+            flags,
+        }) if begun.is_closed_by(timestamp, number, flags) => Some(true),
+        _ => None,
+    }
 }
 
 pub struct Parser {
@@ -440,6 +614,11 @@ impl Parser {
         }
     }
 
+    /// Returns true if we are part way through a `%begin` block
+    pub fn is_in_block(&self) -> bool {
+        self.begun.is_some()
+    }
+
     pub fn advance_string(&mut self, s: &str) -> Vec<Event> {
         self.advance_bytes(s.as_bytes())
     }
@@ -454,95 +633,71 @@ impl Parser {
         events
     }
 
-    fn process_guarded_line(&mut self, line: String) -> Option<Event> {
-        let result = match parse_line(&line) {
-            Ok(Event::End {
-                timestamp,
-                number,
-                flags,
-            }) => {
-                if let Some(begun) = self.begun.take() {
-                    if begun.timestamp == timestamp
-                        && begun.number == number
-                        && begun.flags == flags
-                    {
-                        Some(Event::Guarded(begun))
-                    } else {
-                        log::error!("mismatched %end; expected {:?} but got {}", begun, line);
-                        None
-                    }
-                } else {
-                    log::error!("unexpected %end with no %begin ({})", line);
-                    None
-                }
-            }
-            Ok(Event::Error {
-                timestamp,
-                number,
-                flags,
-            }) => {
-                if let Some(mut begun) = self.begun.take() {
-                    if begun.timestamp == timestamp
-                        && begun.number == number
-                        && begun.flags == flags
-                    {
-                        begun.error = true;
-                        Some(Event::Guarded(begun))
-                    } else {
-                        log::error!("mismatched %error; expected {:?} but got {}", begun, line);
-                        None
-                    }
-                } else {
-                    log::error!("unexpected %error with no %begin ({})", line);
-                    None
-                }
-            }
-            _ => {
-                let begun = self.begun.as_mut().unwrap();
-                begun.output.push_str(&line);
-                begun.output.push('\n');
+    /// Handles a line that arrived while a `%begin` block is open.
+    /// tmux doesn't interleave notifications with the body of a
+    /// response, and the body can be arbitrary text, such as the
+    /// contents of a pane, so only the `%end` or `%error` that matches
+    /// the `%begin` closes the block; every other line is kept, as is,
+    /// in the body.
+    fn process_guarded_line(&mut self) -> Option<Event> {
+        let begun = self.begun.as_ref().expect("a block is open");
+        let closed = closing_guard(&self.buffer, begun);
+
+        let result = match closed {
+            Some(error) => self.begun.take().map(|mut begun| {
+                begun.error = error;
+                Event::Guarded(begun)
+            }),
+            None => {
+                let begun = self.begun.as_mut().expect("a block is open");
+                begun.output.extend_from_slice(&self.buffer);
+                begun.output.push(b'\n');
                 None
             }
         };
         self.buffer.clear();
-        return result;
+        result
     }
 
     fn process_line(&mut self) -> Option<Event> {
         if self.buffer.last() == Some(&b'\r') {
             self.buffer.pop();
         }
-        let result = match std::str::from_utf8(&self.buffer) {
-            Ok(line) => {
-                if self.begun.is_some() {
-                    let line = line.to_owned();
-                    return self.process_guarded_line(line);
+        if self.begun.is_some() {
+            return self.process_guarded_line();
+        }
+        if let Some(result) = parse_output_line(&self.buffer) {
+            self.buffer.clear();
+            return match result {
+                Ok(event) => Some(event),
+                Err(err) => {
+                    log::error!("Unrecognized tmux cc line: {}", err);
+                    None
                 }
-                match parse_line(line) {
-                    Ok(Event::Begin {
+            };
+        }
+        let result = match std::str::from_utf8(&self.buffer) {
+            Ok(line) => match parse_line(line) {
+                Ok(Event::Begin {
+                    timestamp,
+                    number,
+                    flags,
+                }) => {
+                    self.begun.replace(Guarded {
                         timestamp,
                         number,
                         flags,
-                    }) => {
-                        if self.begun.is_some() {
-                            log::error!("expected %end or %error before %begin ({})", line);
-                        }
-                        self.begun.replace(Guarded {
-                            timestamp,
-                            number,
-                            flags,
-                            error: false,
-                            output: String::new(),
-                        });
-                        None
-                    }
-                    Ok(event) => Some(event),
-                    Err(err) => {
-                        log::error!("Unrecognized tmux cc line: {}", err);
-                        None
-                    }
+                        error: false,
+                        output: vec![],
+                    });
+                    None
                 }
-            }
+                Ok(event) => Some(event),
+                Err(err) => {
+                    log::error!("Unrecognized tmux cc line: {}", err);
+                    None
+                }
+            },
             Err(err) => {
                 log::error!("Failed to parse line from tmux: {}", err);
                 None
@@ -582,6 +737,74 @@ mod tests {
             },
             parse_line("%end 12345 321 0").unwrap()
         );
+
+        assert_eq!(
+            Event::LayoutChange {
+                window: 1,
+                layout: "b25d,80x24,0,0,2".to_owned(),
+                visible_layout: Some("b25d,80x24,0,0,2".to_owned()),
+                raw_flags: Some("*".to_owned()),
+            },
+            parse_line("%layout-change @1 b25d,80x24,0,0,2 b25d,80x24,0,0,2 *").unwrap()
+        );
+
+        assert_eq!(
+            Event::LayoutChange {
+                window: 1,
+                layout: "cafd,120x29,0,0,0".to_owned(),
+                visible_layout: None,
+                raw_flags: None,
+            },
+            parse_line("%layout-change @1 cafd,120x29,0,0,0").unwrap()
+        );
+
+        assert_eq!(
+            Event::UnlinkedWindowAdd { window: 3 },
+            parse_line("%unlinked-window-add @3").unwrap()
+        );
+
+        assert_eq!(
+            Event::WindowRenamed {
+                window: 1,
+                name: "vim foo".to_owned(),
+            },
+            parse_line("%window-renamed @1 vim foo").unwrap()
+        );
+        assert_eq!(
+            Event::WindowRenamed {
+                window: 1,
+                name: "caf\u{e9}\tnotes\\".to_owned(),
+            },
+            parse_line("%window-renamed @1 caf\u{e9}\\011notes\\\\").unwrap()
+        );
+
+        assert_eq!(
+            Event::SessionRenamed {
+                session: Some(2),
+                name: "my work".to_owned(),
+            },
+            parse_line("%session-renamed $2 my work").unwrap()
+        );
+        assert_eq!(
+            Event::SessionRenamed {
+                session: None,
+                name: "work".to_owned(),
+            },
+            parse_line("%session-renamed work").unwrap()
+        );
+
+        assert_eq!(Event::Pause { pane: 2 }, parse_line("%pause %2").unwrap());
+        assert_eq!(
+            Event::Continue { pane: 2 },
+            parse_line("%continue %2").unwrap()
+        );
+        assert_eq!(
+            Event::Output {
+                pane: 2,
+                text: b"a : b\r\n".to_vec(),
+            },
+            parse_line("%extended-output %2 1500 : a : b\\015\\012").unwrap()
+        );
     }
 
     #[test]
@@ -615,7 +838,7 @@ here
                     number: 310,
                     flags: 0,
                     error: false,
-                    output: "stuff\nin\nhere\n".to_owned()
+                    output: b"stuff\nin\nhere\n".to_vec()
                 }),
                 Event::WindowAdd { window: 1 },
                 Event::SessionsChanged,
@@ -625,20 +848,20 @@ here
                 },
                 Event::Output {
                     pane: 1,
-                    text: "\x1b[1m\x1b[7m%\x1b[27m\x1b[1m\x1b[0m    \r \r".to_owned()
+                    text: b"\x1b[1m\x1b[7m%\x1b[27m\x1b[1m\x1b[0m    \r \r".to_vec()
                 },
                 Event::Output {
                     pane: 1,
-                    text: "\x1bkwez@cube-localdomain:~\x1b\\\x1b]2;wez@cube-localdomain:~\x1b\\"
-                        .to_owned()
+                    text: b"\x1bkwez@cube-localdomain:~\x1b\\\x1b]2;wez@cube-localdomain:~\x1b\\"
+                        .to_vec()
                 },
                 Event::Output {
                     pane: 1,
-                    text: "\x1b]7;file://cube-localdomain/home/wez\x1b\\".to_owned(),
+                    text: b"\x1b]7;file://cube-localdomain/home/wez\x1b\\".to_vec(),
                 },
                 Event::Output {
                     pane: 1,
-                    text: "\x1b[K\x1b[?2004h".to_owned(),
+                    text: b"\x1b[K\x1b[?2004h".to_vec(),
                 },
                 Event::Exit { reason: None },
                 Event::Exit {
@@ -648,4 +871,152 @@ here
             events
         );
     }
+
+    #[test]
+    fn test_protocol_lines_in_body() {
+        // The body of a response, such as the output of capture-pane,
+        // can contain anything at all, including lines that look like
+        // the guards of other blocks and notifications
+        let input = b"%begin 1604279270 311 1
+%begin 1600000000 5 1
+%end 1600000000 5 1
+%error 1604279270 311 0
+%exit
+%window-close @1
+%output %1 hello\\015\\012
+caf\xe9 \xff
+%end 1604279270 311 1
+%begin 1604279270 312 1
+can't find pane: %5
+%error 1604279270 312 1
+%window-close @1
+";
+
+        let mut p = Parser::new();
+        let events = p.advance_bytes(input);
+        assert_eq!(
+            vec![
+                Event::Guarded(Guarded {
+                    timestamp: 1604279270,
+                    number: 311,
+                    flags: 1,
+                    error: false,
+                    output: b"%begin 1600000000 5 1
+%end 1600000000 5 1
+%error 1604279270 311 0
+%exit
+%window-close @1
+%output %1 hello\\015\\012
+caf\xe9 \xff
+"
+                    .to_vec()
+                }),
+                Event::Guarded(Guarded {
+                    timestamp: 1604279270,
+                    number: 312,
+                    flags: 1,
+                    error: true,
+                    output: b"can't find pane: %5\n".to_vec()
+                }),
+                Event::WindowClose { window: 1 },
+            ],
+            events
+        );
+        assert!(!p.is_in_block());
+    }
+
+    #[test]
+    fn test_byte_at_a_time() {
+        let input = b"%begin 1604279270 313 1\r
+0: bash* (1 panes) [80x24] [layout b25d,80x24,0,0,1] @1 (active)\r
+1: vim (1 panes) [80x24] [layout b25e,80x24,0,0,2] @2\r
+%end 1604279270 313 1\r
+";
+
+        let mut p = Parser::new();
+        let mut events = vec![];
+        for &b in input.iter() {
+            if let Some(event) = p.advance_byte(b) {
+                events.push(event);
+            }
+        }
+        assert_eq!(
+            vec![Event::Guarded(Guarded {
+                timestamp: 1604279270,
+                number: 313,
+                flags: 1,
+                error: false,
+                output: b"0: bash* (1 panes) [80x24] [layout b25d,80x24,0,0,1] @1 (active)\n\
+                         1: vim (1 panes) [80x24] [layout b25e,80x24,0,0,2] @2\n"
+                    .to_vec()
+            })],
+            events
+        );
+    }
+
+    #[test]
+    fn test_output_unescape() {
+        assert_eq!(
+            Event::Output {
+                pane: 2,
+                text: b"a\x00b\r\n\x1b[1m\xe2\x82\xac \xff\\".to_vec(),
+            },
+            parse_line("%output %2 a\\000b\\015\\012\\033[1m\\342\\202\\254 \\377\\134").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_output_bytes() {
+        // A multi-byte sequence split across two notifications, and
+        // escapes that are invalid or cut short
+        let mut p = Parser::new();
+        let events = p.advance_bytes(
+            b"%output %1 \\033[1m\xe4\xb8\r\n\
+%output %1 \xad\\134 \\q \\M\\\r\n\
+%extended-output %1 12 : \xf0\x9f\r\n",
+        );
+        assert_eq!(
+            vec![
+                Event::Output {
+                    pane: 1,
+                    text: b"\x1b[1m\xe4\xb8".to_vec(),
+                },
+                Event::Output {
+                    pane: 1,
+                    text: b"\xad\\ \\q \\M\\".to_vec(),
+                },
+                Event::Output {
+                    pane: 1,
+                    text: b"\xf0\x9f".to_vec(),
+                },
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn test_window_notifications() {
+        let input = b"%window-add @1
+%unlinked-window-add @2
+%window-renamed @1 htop
+%window-close @7
+%window-close @1
+";
+
+        let mut p = Parser::new();
+        let events = p.advance_bytes(input);
+        assert_eq!(
+            vec![
+                Event::WindowAdd { window: 1 },
+                Event::UnlinkedWindowAdd { window: 2 },
+                Event::WindowRenamed {
+                    window: 1,
+                    name: "htop".to_owned(),
+                },
+                Event::WindowClose { window: 7 },
+                Event::WindowClose { window: 1 },
+            ],
+            events
+        );
+    }
 }
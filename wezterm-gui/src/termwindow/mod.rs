@@ -165,18 +165,18 @@ pub struct TermWindow {
 }
 
 impl WindowCallbacks for TermWindow {
-    fn can_close(&mut self) -> bool {
+    fn close_requested(&mut self) -> CloseAction {
         let mux = Mux::get().unwrap();
         match self.config.window_close_confirmation {
             WindowCloseConfirmation::NeverPrompt => {
                 // Immediately kill the tabs and allow the window to close
                 mux.kill_window(self.mux_window_id);
-                true
+                CloseAction::Close
             }
             WindowCloseConfirmation::AlwaysPrompt => {
                 let tab = match mux.get_active_tab_for_window(self.mux_window_id) {
                     Some(tab) => tab,
-                    None => return true,
+                    None => return CloseAction::Close,
                 };
 
                 let mux_window_id = self.mux_window_id;
@@ -186,7 +186,7 @@ impl WindowCallbacks for TermWindow {
                     .map_or(false, |w| w.can_close_without_prompting());
                 if can_close {
                     mux.kill_window(self.mux_window_id);
-                    return true;
+                    return CloseAction::Close;
                 }
                 let window = self.window.clone().unwrap();
                 let (overlay, future) = start_overlay(self, &tab, move |tab_id, term| {
@@ -197,7 +197,7 @@ impl WindowCallbacks for TermWindow {
 
                 // Don't close right now; let the close happen from
                 // the confirmation overlay
-                false
+                CloseAction::Defer
             }
         }
     }
@@ -226,6 +226,20 @@ impl WindowCallbacks for TermWindow {
         }
     }
 
+    fn dropped_files(&mut self, paths: Vec<std::path::PathBuf>) {
+        // Paste the paths as shell words, as other terminals do
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        let text = shell_words::join(paths);
+        if let Some(pane) = self.get_active_pane_or_overlay() {
+            if let Err(err) = pane.send_paste(&text) {
+                log::error!("failed to paste dropped files: {:#}", err);
+            }
+        }
+    }
+
     fn mouse_event(&mut self, event: &MouseEvent, context: &dyn WindowOps) {
         self.mouse_event_impl(event, context)
     }
@@ -374,6 +388,12 @@ impl WindowCallbacks for TermWindow {
     fn paint(&mut self, frame: &mut glium::Frame) {
         self.paint_impl(frame)
     }
+
+    fn paint_metrics(&mut self, metrics: &PaintMetrics) {
+        log::trace!("{:?}", metrics);
+        metrics::histogram!("gui.paint.present", metrics.present);
+        metrics::histogram!("gui.paint.damage.size", metrics.pixels as f64);
+    }
 }
 
 fn load_background_image(config: &ConfigHandle) -> Option<Arc<ImageData>> {
@@ -186,7 +186,11 @@ pub struct KeyEvent {
     pub raw_modifiers: Modifiers,
     pub raw_code: Option<u32>,
 
-    /// How many times this key repeats
+    /// How many times this key repeats.
+    /// The initial press of a key reports 1.  On X11, each auto-repeat
+    /// of a held key is delivered as a further key down event with an
+    /// incremented count, and the synthetic releases that the server
+    /// generates between repeats are not delivered.
     pub repeat_count: u16,
 
     /// If true, this is a key down rather than a key up event
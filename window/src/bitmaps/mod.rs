@@ -165,20 +165,34 @@ pub trait BitmapImage {
     }
 
     fn clear_rect(&mut self, rect: Rect, color: Color) {
-        let (dim_width, dim_height) = self.image_dimensions();
-        let max_x = rect.max_x().min(dim_width as isize) as usize;
-        let max_y = rect.max_y().min(dim_height as isize) as usize;
+        self.fill_rect(rect, color, Operator::Source);
+    }
 
-        let dest_x = rect.origin.x.max(0) as usize;
-        if dest_x >= dim_width {
+    /// Fill the portion of `rect` that lies within the image with
+    /// `color`, applied to the surface using the specified Operator.
+    fn fill_rect(&mut self, rect: Rect, color: Color, operator: Operator) {
+        let (dim_width, dim_height) = self.image_dimensions();
+        let min_x = rect.min_x().max(0);
+        let min_y = rect.min_y().max(0);
+        let max_x = rect.max_x().min(dim_width as isize);
+        let max_y = rect.max_y().min(dim_height as isize);
+        if min_x >= max_x || min_y >= max_y {
             return;
         }
-        let dest_y = rect.origin.y.max(0) as usize;
 
-        for y in dest_y..max_y {
-            let range = self.horizontal_pixel_range_mut(dest_x, max_x, y);
-            for c in range {
-                *c = color.0;
+        for y in min_y as usize..max_y as usize {
+            let range = self.horizontal_pixel_range_mut(min_x as usize, max_x as usize, y);
+            match operator {
+                Operator::Source => {
+                    for c in range {
+                        *c = color.0;
+                    }
+                }
+                _ => {
+                    for c in range {
+                        *c = color.composite(Color(*c), operator).0;
+                    }
+                }
             }
         }
     }
@@ -186,8 +200,46 @@ pub trait BitmapImage {
     /// Draw a line starting at `start` and ending at `end`.
     /// The line will be anti-aliased and applied to the surface using the
     /// specified Operator.
+    /// Horizontal and vertical lines, such as underlines and cursor
+    /// outlines, don't need anti-aliasing and take a faster path that
+    /// covers every pixel from `start` to `end` inclusive.
     fn draw_line(&mut self, start: Point, end: Point, color: Color, operator: Operator) {
         let (dim_width, dim_height) = self.image_dimensions();
+
+        if start.y == end.y {
+            let y = start.y;
+            if y < 0 || y >= dim_height as isize {
+                return;
+            }
+            let x1 = start.x.min(end.x).max(0);
+            let x2 = start.x.max(end.x).saturating_add(1).min(dim_width as isize);
+            if x1 >= x2 {
+                return;
+            }
+            for pix in self.horizontal_pixel_range_mut(x1 as usize, x2 as usize, y as usize) {
+                *pix = color.composite(Color(*pix), operator).0;
+            }
+            return;
+        }
+
+        if start.x == end.x {
+            let x = start.x;
+            if x < 0 || x >= dim_width as isize {
+                return;
+            }
+            let y1 = start.y.min(end.y).max(0);
+            let y2 = start
+                .y
+                .max(end.y)
+                .saturating_add(1)
+                .min(dim_height as isize);
+            for y in y1..y2 {
+                let pix = self.pixel_mut(x as usize, y as usize);
+                *pix = color.composite(Color(*pix), operator).0;
+            }
+            return;
+        }
+
         let linear: LinSrgba = color.into();
         let (red, green, blue, alpha) = linear.into_components();
 
@@ -460,3 +512,53 @@ impl Texture2d for ImageTexture {
         height
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fill_rect() {
+        let black = Color::rgba(0, 0, 0, 0xff);
+        let white = Color::rgba(0xff, 0xff, 0xff, 0xff);
+        let clear = Color::rgba(0, 0, 0, 0);
+
+        let mut image = Image::new(4, 4);
+        image.clear(black);
+        image.fill_rect(
+            Rect::new(Point::new(1, 1), Size::new(2, 2)),
+            white,
+            Operator::Source,
+        );
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = if (1..3).contains(&x) && (1..3).contains(&y) {
+                    white
+                } else {
+                    black
+                };
+                assert_eq!(*image.pixel(x, y), expected.0, "at {},{}", x, y);
+            }
+        }
+
+        // Blending a transparent color leaves the image alone,
+        // whereas replacing with it does not
+        let top = Rect::new(Point::new(0, 0), Size::new(2, 1));
+        image.fill_rect(top, clear, Operator::Over);
+        assert_eq!(*image.pixel(0, 0), black.0);
+        image.fill_rect(top, clear, Operator::Source);
+        assert_eq!(*image.pixel(0, 0), clear.0);
+        assert_eq!(*image.pixel(2, 0), black.0);
+
+        // Rects that are partially or entirely outside are clipped
+        image.fill_rect(
+            Rect::new(Point::new(-10, 3), Size::new(12, 5)),
+            white,
+            Operator::Over,
+        );
+        assert_eq!(*image.pixel(1, 3), white.0);
+        assert_eq!(*image.pixel(2, 3), black.0);
+        image.clear_rect(Rect::new(Point::new(-10, 0), Size::new(5, 4)), white);
+        assert_eq!(*image.pixel(0, 1), black.0);
+    }
+}
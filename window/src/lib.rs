@@ -29,6 +29,8 @@ pub use color::Color;
 pub use connection::*;
 pub use glium;
 pub use os::*;
+#[cfg(all(unix, not(target_os = "macos")))]
+pub use timerlist::TimerHandle;
 pub use wezterm_input_types::*;
 
 /// Compositing operator.
@@ -85,15 +87,95 @@ pub enum MouseCursor {
     SizeLeftRight,
 }
 
+/// Describes a monitor that is attached to the display
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorInfo {
+    /// The name of the output, such as `DP-2`
+    pub name: String,
+    /// The position of the top left of the monitor, in pixels,
+    /// relative to the top left of the screen
+    pub x: isize,
+    pub y: isize,
+    pub width: usize,
+    pub height: usize,
+    /// The physical size of the monitor.  Some displays, such as
+    /// projectors, don't report their size, in which case these are 0.
+    pub width_mm: usize,
+    pub height_mm: usize,
+    /// Whether this is the primary monitor
+    pub primary: bool,
+}
+
+impl MonitorInfo {
+    /// Returns the horizontal DPI, computed from the physical size
+    /// of the monitor, if it is known
+    pub fn dpi(&self) -> Option<f64> {
+        if self.width_mm == 0 {
+            None
+        } else {
+            Some(self.width as f64 * 25.4 / self.width_mm as f64)
+        }
+    }
+
+    pub fn contains(&self, x: isize, y: isize) -> bool {
+        x >= self.x
+            && x < self.x + self.width as isize
+            && y >= self.y
+            && y < self.y + self.height as isize
+    }
+}
+
+/// Statistics about the painting of a single frame.
+/// These are only collected when the `WEZTERM_PAINT_METRICS`
+/// environment variable is set, and are reported to
+/// `WindowCallbacks::paint_metrics`.
+#[derive(Debug, Clone, Default)]
+pub struct PaintMetrics {
+    /// The number of damaged regions that prompted the paint
+    pub rects: usize,
+    /// The number of pixels covered by those regions
+    pub pixels: usize,
+    /// Time spent in `WindowCallbacks::paint`
+    pub paint: std::time::Duration,
+    /// Time spent presenting the painted frame
+    pub present: std::time::Duration,
+}
+
+/// How to respond to a request to close a window, such as from the
+/// close button in the title bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseAction {
+    /// Close the window now
+    Close,
+    /// Keep the window open for now.  The application is responsible
+    /// for calling `WindowOps::close` once it is ready, perhaps after
+    /// asking the user to confirm.
+    Defer,
+    /// Keep the window open
+    Cancel,
+}
+
 #[allow(unused_variables)]
 pub trait WindowCallbacks: Any {
     /// Called when the window close button is clicked.
     /// Return true to allow the close to continue, false to
     /// prevent it from closing.
+    /// This is a convenience for implementing `close_requested`.
     fn can_close(&mut self) -> bool {
         true
     }
 
+    /// Called when the window close button is clicked, to decide
+    /// whether the window should be closed.
+    /// The default maps the result of `can_close`.
+    fn close_requested(&mut self) -> CloseAction {
+        if self.can_close() {
+            CloseAction::Close
+        } else {
+            CloseAction::Cancel
+        }
+    }
+
     /// Called when the window is being destroyed by the gui system
     fn destroy(&mut self) {}
 
@@ -103,6 +185,16 @@ pub trait WindowCallbacks: Any {
     /// Called when window gains/loses focus
     fn focus_change(&mut self, focused: bool) {}
 
+    /// Called when files are dropped onto the window
+    fn dropped_files(&mut self, paths: Vec<std::path::PathBuf>) {}
+
+    /// Called when the bell is rung for the window; the
+    /// implementation decides whether it is audible or visual
+    fn bell(&mut self) {}
+
+    /// Called when the mouse pointer enters or leaves the window
+    fn mouse_enter_leave(&mut self, entered: bool) {}
+
     /// Called when the window has opengl mode enabled and the window
     /// contents need painting.
     fn paint(&mut self, frame: &mut glium::Frame) {
@@ -110,6 +202,11 @@ pub trait WindowCallbacks: Any {
         frame.clear_color_srgb(0.25, 0.125, 0.375, 1.0);
     }
 
+    /// Called after each frame has been painted with statistics
+    /// about it.  This is only called when paint metrics have been
+    /// enabled; see `PaintMetrics`.
+    fn paint_metrics(&mut self, metrics: &PaintMetrics) {}
+
     /// Called if the opengl context is lost
     fn opengl_context_lost(&mut self, _window: &dyn WindowOps) -> anyhow::Result<()> {
         Ok(())
@@ -155,7 +252,7 @@ pub trait WindowOps {
     /// Schedule the window to be closed
     fn close(&self) -> Future<()>;
 
-    /// Change the cursor
+    /// Change the cursor; `None` hides the cursor
     fn set_cursor(&self, cursor: Option<MouseCursor>) -> Future<()>;
 
     /// Invalidate the window so that the entire client area will
@@ -168,6 +265,13 @@ pub trait WindowOps {
     /// Resize the inner or client area of the window
     fn set_inner_size(&self, width: usize, height: usize) -> Future<()>;
 
+    /// Returns the current size of the inner or client area of the window.
+    /// This reflects the most recent size that was passed to the
+    /// `resize` callback.
+    fn get_inner_size(&self) -> Future<Dimensions> {
+        Future::err(anyhow::anyhow!("get_inner_size is not implemented"))
+    }
+
     /// Changes the location of the window on the screen.
     /// The coordinates are of the top left pixel of the
     /// client area.
@@ -175,6 +279,30 @@ pub trait WindowOps {
         Future::ok(())
     }
 
+    /// Returns the location of the top left pixel of the client
+    /// area on the screen.
+    fn get_window_position(&self) -> Future<ScreenPoint> {
+        Future::err(anyhow::anyhow!("get_window_position is not implemented"))
+    }
+
+    /// Ask the window manager to size the window in steps of `x` by `y`
+    /// pixels on top of a base size of `base_width` by `base_height`,
+    /// such as the cell size and padding of a terminal.
+    fn set_resize_increments(
+        &self,
+        _x: u16,
+        _y: u16,
+        _base_width: u16,
+        _base_height: u16,
+    ) -> Future<()> {
+        Future::ok(())
+    }
+
+    /// Ask the window manager not to make the window smaller than this
+    fn set_min_size(&self, _width: u16, _height: u16) -> Future<()> {
+        Future::ok(())
+    }
+
     /// inform the windowing system of the current textual
     /// cursor input location.  This is used primarily for
     /// the platform specific input method editor
@@ -207,10 +335,58 @@ pub trait WindowOps {
         Future::ok(())
     }
 
+    /// Set the icon for the window, providing it at multiple sizes
+    /// so that the system can pick the most appropriate one.
+    /// Systems that accept only a single image use the largest.
+    fn set_icons(&self, images: Vec<Image>) -> Future<()> {
+        match images.into_iter().max_by_key(|image| {
+            let (width, height) = image.image_dimensions();
+            width * height
+        }) {
+            Some(image) => self.set_icon(image),
+            None => Future::ok(()),
+        }
+    }
+
     fn toggle_fullscreen(&self) -> Future<()> {
         Future::ok(())
     }
 
+    /// Ask the window manager to enter or leave fullscreen mode.
+    /// The window is resized via the usual `resize` callback once
+    /// the window manager has acted on the request.
+    fn set_fullscreen(&self, _enable: bool) -> Future<()> {
+        Future::ok(())
+    }
+
+    /// Returns whether the window is currently fullscreen
+    fn is_fullscreen(&self) -> Future<bool> {
+        Future::ok(false)
+    }
+
+    /// Ask the window manager to maximize or restore the window
+    fn set_maximized(&self, _enable: bool) -> Future<()> {
+        Future::ok(())
+    }
+
+    /// Ask the window manager to minimize (iconify) the window
+    fn minimize(&self) -> Future<()> {
+        Future::ok(())
+    }
+
+    /// Returns whether the window is currently maximized
+    fn is_maximized(&self) -> Future<bool> {
+        Future::ok(false)
+    }
+
+    /// Ask the compositor to apply `opacity`, in the range 0.0 (fully
+    /// transparent) to 1.0 (fully opaque), to the whole window.
+    /// Values outside of that range are clamped.  Without a compositing
+    /// manager this has no visible effect.
+    fn set_opacity(&self, _opacity: f64) -> Future<()> {
+        Future::ok(())
+    }
+
     fn config_did_change(&self, _config: &WindowConfigHandle) -> Future<()> {
         Future::ok(())
     }
@@ -226,7 +402,7 @@ pub trait WindowOpsMut {
     /// Schedule the window to be closed
     fn close(&mut self);
 
-    /// Change the cursor
+    /// Change the cursor; `None` hides the cursor
     fn set_cursor(&mut self, cursor: Option<MouseCursor>);
 
     /// Invalidate the window so that the entire client area will
@@ -249,12 +425,88 @@ pub trait WindowOpsMut {
     /// client area.
     fn set_window_position(&self, _coords: ScreenPoint) {}
 
+    /// Ask the window manager to size the window in steps of `x` by `y`
+    /// pixels on top of a base size of `base_width` by `base_height`
+    fn set_resize_increments(&mut self, _x: u16, _y: u16, _base_width: u16, _base_height: u16) {}
+
+    /// Ask the window manager not to make the window smaller than this
+    fn set_min_size(&mut self, _width: u16, _height: u16) {}
+
     /// Set the icon for the window.
     /// Depending on the system this may be shown in its titlebar
     /// and/or in the task manager/task switcher
     fn set_icon(&mut self, _image: &dyn BitmapImage) {}
 
+    /// Set the icon for the window, providing it at multiple sizes
+    fn set_icons(&mut self, images: &[&dyn BitmapImage]) {
+        if let Some(image) = images.iter().max_by_key(|image| {
+            let (width, height) = image.image_dimensions();
+            width * height
+        }) {
+            self.set_icon(*image);
+        }
+    }
+
     fn toggle_fullscreen(&mut self) {}
+    fn set_fullscreen(&mut self, _enable: bool) {}
+    fn set_maximized(&mut self, _enable: bool) {}
+    fn minimize(&mut self) {}
+    fn set_opacity(&mut self, _opacity: f64) {}
 
     fn config_did_change(&mut self, _config: &WindowConfigHandle) {}
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn close_requested() {
+        struct Accepting;
+        impl WindowCallbacks for Accepting {
+            fn as_any(&mut self) -> &mut dyn Any {
+                self
+            }
+        }
+
+        struct Refusing;
+        impl WindowCallbacks for Refusing {
+            fn can_close(&mut self) -> bool {
+                false
+            }
+            fn as_any(&mut self) -> &mut dyn Any {
+                self
+            }
+        }
+
+        // The default is derived from can_close
+        assert_eq!(Accepting.close_requested(), CloseAction::Close);
+        assert_eq!(Refusing.close_requested(), CloseAction::Cancel);
+    }
+
+    #[test]
+    fn monitor_info() {
+        let monitor = MonitorInfo {
+            name: "DP-2".to_owned(),
+            x: 1920,
+            y: 0,
+            width: 3840,
+            height: 2160,
+            width_mm: 600,
+            height_mm: 340,
+            primary: false,
+        };
+        assert_eq!(monitor.dpi().map(f64::round), Some(163.0));
+        assert!(monitor.contains(1920, 0));
+        assert!(monitor.contains(5759, 2159));
+        assert!(!monitor.contains(1919, 0));
+        assert!(!monitor.contains(5760, 0));
+
+        let projector = MonitorInfo {
+            width_mm: 0,
+            height_mm: 0,
+            ..monitor
+        };
+        assert_eq!(projector.dpi(), None);
+    }
+}
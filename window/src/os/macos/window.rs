@@ -4,9 +4,9 @@
 use super::{nsstring, nsstring_to_str};
 use crate::connection::ConnectionOps;
 use crate::{
-    config, Clipboard, Connection, Dimensions, KeyCode, KeyEvent, Modifiers, MouseButtons,
-    MouseCursor, MouseEvent, MouseEventKind, MousePress, Point, Rect, ScreenPoint, Size,
-    WindowCallbacks, WindowConfigHandle, WindowDecorations, WindowOps, WindowOpsMut,
+    config, Clipboard, CloseAction, Connection, Dimensions, KeyCode, KeyEvent, Modifiers,
+    MouseButtons, MouseCursor, MouseEvent, MouseEventKind, MousePress, Point, Rect, ScreenPoint,
+    Size, WindowCallbacks, WindowConfigHandle, WindowDecorations, WindowOps, WindowOpsMut,
 };
 use anyhow::{anyhow, bail, ensure};
 use cocoa::appkit::{
@@ -1249,7 +1249,7 @@ impl WindowView {
             "scrollPageUp:" => KeyCode::PageUp,
             "scrollPageDown:" => KeyCode::PageDown,
             _ => {
-                eprintln!("UNHANDLED: do_command_by_selector: {:?}", selector);
+                log::trace!("unhandled: do_command_by_selector: {:?}", selector);
                 return;
             }
         };
@@ -1320,14 +1320,16 @@ impl WindowView {
         replacement_range: NSRange,
     ) {
         let s = unsafe { nsstring_to_str(_astring) };
-        eprintln!(
+        log::trace!(
             "set_marked_text_selected_range_replacement_range {} {:?} {:?}",
-            s, selected_range, replacement_range
+            s,
+            selected_range,
+            replacement_range
         );
     }
 
     extern "C" fn unmark_text(_this: &mut Object, _sel: Sel) {
-        eprintln!("unmarkText");
+        log::trace!("unmarkText");
     }
 
     extern "C" fn valid_attributes_for_marked_text(_this: &mut Object, _sel: Sel) -> id {
@@ -1343,9 +1345,10 @@ impl WindowView {
         _proposed_range: NSRange,
         _actual_range: NSRangePointer,
     ) -> id {
-        eprintln!(
+        log::trace!(
             "attributedSubstringForProposedRange {:?} {:?}",
-            _proposed_range, _actual_range
+            _proposed_range,
+            _actual_range
         );
         nil
     }
@@ -1366,9 +1369,10 @@ impl WindowView {
     ) -> NSRect {
         // Returns a rect in screen coordinates; this is used to place
         // the input method editor
-        eprintln!(
+        log::trace!(
             "firstRectForCharacterRange: range:{:?} actual:{:?}",
-            range, actual
+            range,
+            actual
         );
         let frame = unsafe {
             let window: id = msg_send![this, window];
@@ -1407,7 +1411,7 @@ impl WindowView {
         }
 
         if let Some(this) = Self::get_this(this) {
-            if this.inner.borrow_mut().callbacks.can_close() {
+            if this.inner.borrow_mut().callbacks.close_requested() == CloseAction::Close {
                 YES
             } else {
                 NO
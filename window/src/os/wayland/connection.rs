@@ -4,7 +4,7 @@ use super::pointer::*;
 use super::window::*;
 use crate::connection::ConnectionOps;
 use crate::spawn::*;
-use crate::timerlist::{TimerEntry, TimerList};
+use crate::timerlist::{TimerEntry, TimerHandle, TimerList};
 use crate::Connection;
 use anyhow::{anyhow, bail, Context};
 use smithay_client_toolkit as toolkit;
@@ -221,7 +221,7 @@ impl ConnectionOps for WaylandConnection {
             .map_err(|e| anyhow!("failed to insert SpawnQueueSource: {:?}", e))?;
 
         while !*self.should_terminate.borrow() {
-            self.timers.borrow_mut().run_ready();
+            TimerList::run_ready(&self.timers);
 
             // Check the spawn queue before we try to sleep; there may
             // be work pending and we don't guarantee that there is a
@@ -255,10 +255,26 @@ impl ConnectionOps for WaylandConnection {
     }
 
     fn schedule_timer<F: FnMut() + 'static>(&self, interval: std::time::Duration, callback: F) {
+        self.schedule_cancellable_timer(interval, callback);
+    }
+}
+
+impl WaylandConnection {
+    /// Like `schedule_timer`, but returns a handle that can be
+    /// passed to `cancel_timer` to stop the timer
+    pub fn schedule_cancellable_timer<F: FnMut() + 'static>(
+        &self,
+        interval: std::time::Duration,
+        callback: F,
+    ) -> TimerHandle {
         self.timers.borrow_mut().insert(TimerEntry {
             callback: Box::new(callback),
             due: Instant::now(),
             interval,
-        });
+        })
+    }
+
+    pub fn cancel_timer(&self, handle: TimerHandle) {
+        self.timers.borrow_mut().cancel(handle);
     }
 }
@@ -255,6 +255,14 @@ impl PointerDispatcher {
         let serial = serial.unwrap_or(inner.serial);
         self.auto_pointer.set_cursor(name, Some(serial)).ok();
     }
+
+    /// Hides the cursor while it is over our surfaces; setting a
+    /// null cursor surface is how wayland spells this
+    pub fn hide_cursor(&self, serial: Option<u32>) {
+        let inner = self.inner.lock().unwrap();
+        let serial = serial.unwrap_or(inner.serial);
+        wl_pointer::WlPointer::set_cursor(&self.auto_pointer, serial, None, 0, 0);
+    }
 }
 
 #[derive(Clone, Debug, Copy, PartialEq, Eq)]
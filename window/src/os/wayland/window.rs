@@ -6,8 +6,8 @@ use crate::os::wayland::connection::WaylandConnection;
 use crate::os::xkeysyms::keysym_to_keycode;
 use crate::WindowConfigHandle;
 use crate::{
-    Clipboard, Connection, Dimensions, MouseCursor, Point, ScreenPoint, Window, WindowCallbacks,
-    WindowOps, WindowOpsMut,
+    Clipboard, CloseAction, Connection, Dimensions, MouseCursor, Point, ScreenPoint, Window,
+    WindowCallbacks, WindowOps, WindowOpsMut,
 };
 use anyhow::{anyhow, bail, Context};
 use filedescriptor::FileDescriptor;
@@ -453,7 +453,7 @@ impl WaylandWindowInner {
             pending = pending_events.clone();
             *pending_events = PendingEvent::default();
         }
-        if pending.close && self.callbacks.can_close() {
+        if pending.close && self.callbacks.close_requested() == CloseAction::Close {
             self.callbacks.destroy();
             self.window.take();
         }
@@ -842,7 +842,11 @@ impl WindowOpsMut for WaylandWindowInner {
             Some(MouseCursor::SizeUpDown) => "ns-resize",
             Some(MouseCursor::SizeLeftRight) => "ew-resize",
             Some(MouseCursor::Text) => "text",
-            None => return,
+            None => {
+                let conn = Connection::get().unwrap().wayland();
+                conn.pointer.hide_cursor(None);
+                return;
+            }
         };
         let conn = Connection::get().unwrap().wayland();
         conn.pointer.set_cursor(cursor, None);
@@ -2,9 +2,9 @@ use super::*;
 use crate::connection::ConnectionOps;
 use crate::WindowConfigHandle;
 use crate::{
-    Clipboard, Dimensions, KeyCode, KeyEvent, Modifiers, MouseButtons, MouseCursor, MouseEvent,
-    MouseEventKind, MousePress, Point, Rect, ScreenPoint, WindowCallbacks, WindowDecorations,
-    WindowOps, WindowOpsMut,
+    Clipboard, CloseAction, Dimensions, KeyCode, KeyEvent, Modifiers, MouseButtons, MouseCursor,
+    MouseEvent, MouseEventKind, MousePress, Point, Rect, ScreenPoint, WindowCallbacks,
+    WindowDecorations, WindowOps, WindowOpsMut,
 };
 use anyhow::{bail, Context};
 use lazy_static::lazy_static;
@@ -1204,7 +1204,7 @@ unsafe fn ime_composition(
 
                     return Some(1);
                 }
-                Err(_) => eprintln!("cannot represent IME as unicode string!?"),
+                Err(_) => log::error!("cannot represent IME as unicode string!?"),
             };
         }
     }
@@ -1907,7 +1907,7 @@ unsafe fn do_wnd_proc(hwnd: HWND, msg: UINT, wparam: WPARAM, lparam: LPARAM) ->
         WM_CLOSE => {
             if let Some(inner) = rc_from_hwnd(hwnd) {
                 let inner = inner.borrow();
-                if !inner.callbacks.borrow_mut().can_close() {
+                if inner.callbacks.borrow_mut().close_requested() != CloseAction::Close {
                     // Don't let it close
                     return Some(0);
                 }
@@ -1,9 +1,12 @@
-use super::keyboard::Keyboard;
+use super::cursor::XcbCursor;
+use super::dnd::XdndAtoms;
+use super::keyboard::{as_bell_notify, is_auto_repeat_release, Keyboard};
 use crate::connection::ConnectionOps;
-use crate::os::x11::window::XWindowInner;
+use crate::os::x11::window::{is_wheel_button, XWindowInner};
 use crate::os::Connection;
 use crate::spawn::*;
-use crate::timerlist::{TimerEntry, TimerList};
+use crate::timerlist::{TimerEntry, TimerHandle, TimerList};
+use crate::{MonitorInfo, MouseCursor};
 use anyhow::{anyhow, bail, Context as _};
 use mio::unix::EventedFd;
 use mio::{Evented, Events, Poll, PollOpt, Ready, Token};
@@ -17,7 +20,15 @@ use xcb_util::ffi::keysyms::{xcb_key_symbols_alloc, xcb_key_symbols_free, xcb_ke
 
 pub struct XConnection {
     pub conn: xcb_util::ewmh::Connection,
+    /// The DPI derived from the monitor or screen geometry
     pub default_dpi: f64,
+    /// The DPI explicitly configured via the Xft.dpi resource.
+    /// This is updated when the RESOURCE_MANAGER property changes.
+    xft_dpi: RefCell<Option<f64>>,
+    /// Updated when RandR tells us that the monitors have changed
+    monitors: RefCell<Vec<MonitorInfo>>,
+    /// The first event number of the RandR extension, if it is present
+    randr_ev: Option<u8>,
     pub screen_num: i32,
     pub root: xcb::xproto::Window,
     pub keyboard: Keyboard,
@@ -28,6 +39,14 @@ pub struct XConnection {
     pub atom_utf8_string: xcb::Atom,
     pub atom_xsel_data: xcb::Atom,
     pub atom_targets: xcb::Atom,
+    pub atom_incr: xcb::Atom,
+    pub atom_net_wm_state: xcb::Atom,
+    pub atom_net_wm_state_fullscreen: xcb::Atom,
+    pub atom_net_wm_state_maximized_horz: xcb::Atom,
+    pub atom_net_wm_state_maximized_vert: xcb::Atom,
+    pub atom_net_wm_window_opacity: xcb::Atom,
+    pub atom_wm_change_state: xcb::Atom,
+    pub(crate) xdnd: XdndAtoms,
     pub atom_clipboard: xcb::Atom,
     keysyms: *mut xcb_key_symbols_t,
     pub(crate) xrm: HashMap<String, String>,
@@ -37,6 +56,10 @@ pub struct XConnection {
     pub(crate) visual: xcb::xproto::Visualtype,
     pub(crate) depth: u8,
     pub(crate) gl_connection: RefCell<Option<Rc<crate::egl::GlConnection>>>,
+    /// Cursors that have been loaded for any window on this
+    /// connection; shared so that we don't allocate a fresh
+    /// server side cursor each time the pointer shape changes.
+    pub(crate) cursors: RefCell<HashMap<Option<MouseCursor>, XcbCursor>>,
 }
 
 impl std::ops::Deref for XConnection {
@@ -127,6 +150,10 @@ fn window_id_from_event(event: &xcb::GenericEvent) -> Option<xcb::xproto::Window
             let msg: &xcb::FocusOutEvent = unsafe { xcb::cast_event(event) };
             Some(msg.event())
         }
+        xcb::ENTER_NOTIFY | xcb::LEAVE_NOTIFY => {
+            let msg: &xcb::EnterNotifyEvent = unsafe { xcb::cast_event(event) };
+            Some(msg.event())
+        }
         _ => None,
     }
 }
@@ -149,7 +176,7 @@ impl ConnectionOps for XConnection {
     }
 
     fn default_dpi(&self) -> f64 {
-        self.default_dpi
+        self.xft_dpi.borrow().unwrap_or(self.default_dpi)
     }
 
     fn run_message_loop(&self) -> anyhow::Result<()> {
@@ -174,7 +201,7 @@ impl ConnectionOps for XConnection {
         let mut last_interval = Instant::now();
 
         while !*self.should_terminate.borrow() {
-            self.timers.borrow_mut().run_ready();
+            TimerList::run_ready(&self.timers);
 
             let now = Instant::now();
             let diff = now - last_interval;
@@ -226,15 +253,38 @@ impl ConnectionOps for XConnection {
     }
 
     fn schedule_timer<F: FnMut() + 'static>(&self, interval: std::time::Duration, callback: F) {
+        self.schedule_cancellable_timer(interval, callback);
+    }
+}
+
+impl XConnection {
+    /// Like `schedule_timer`, but returns a handle that can be
+    /// passed to `cancel_timer` to stop the timer
+    pub fn schedule_cancellable_timer<F: FnMut() + 'static>(
+        &self,
+        interval: std::time::Duration,
+        callback: F,
+    ) -> TimerHandle {
         self.timers.borrow_mut().insert(TimerEntry {
             callback: Box::new(callback),
             due: Instant::now(),
             interval,
-        });
+        })
+    }
+
+    pub fn cancel_timer(&self, handle: TimerHandle) {
+        self.timers.borrow_mut().cancel(handle);
     }
 }
 
 impl XConnection {
+    /// Read and dispatch everything that the X server has sent us.
+    /// Events are dispatched in the order that they were received and
+    /// each window only accumulates state (pending resize, exposed
+    /// regions) while doing so; painting happens separately in
+    /// `do_paint` on the paint interval, so a burst of motion or key
+    /// events is fully applied before the next paint and results
+    /// in at most one paint per window.
     fn process_queued_xcb(&self) -> anyhow::Result<()> {
         match self.conn.poll_for_event() {
             None => match self.conn.has_error() {
@@ -261,11 +311,91 @@ impl XConnection {
     }
 
     fn process_xcb_event(&self, event: &xcb::GenericEvent) -> anyhow::Result<()> {
+        if (event.response_type() & 0x7f) == xcb::BUTTON_PRESS {
+            let press: &xcb::ButtonPressEvent = unsafe { xcb::cast_event(event) };
+            if is_wheel_button(press.detail()) {
+                return self.process_wheel_event(press);
+            }
+        }
+        if !self.keyboard.detectable_auto_repeat()
+            && (event.response_type() & 0x7f) == xcb::KEY_RELEASE
+        {
+            // The server generates a release immediately prior to each
+            // auto-repeated press; look ahead so that we can drop it
+            if let Some(next) = self.conn.poll_for_queued_event() {
+                if (next.response_type() & 0x7f) == xcb::KEY_PRESS {
+                    let release: &xcb::KeyReleaseEvent = unsafe { xcb::cast_event(event) };
+                    let press: &xcb::KeyPressEvent = unsafe { xcb::cast_event(&next) };
+                    if is_auto_repeat_release(
+                        (release.detail(), release.time()),
+                        (press.detail(), press.time()),
+                    ) {
+                        return self.dispatch_xcb_event(&next);
+                    }
+                }
+                self.dispatch_xcb_event(event)?;
+                return self.process_xcb_event(&next);
+            }
+        }
+        self.dispatch_xcb_event(event)
+    }
+
+    /// A fast flick of the wheel produces a burst of press/release
+    /// pairs.  Fold those that are already queued for the same button
+    /// into a single event, so that we don't run the callbacks and
+    /// repaint for each one of them.
+    fn process_wheel_event(&self, press: &xcb::ButtonPressEvent) -> anyhow::Result<()> {
+        let mut ticks: i16 = 1;
+        let mut following = None;
+        while let Some(next) = self.conn.poll_for_queued_event() {
+            let r = next.response_type() & 0x7f;
+            if r == xcb::BUTTON_PRESS || r == xcb::BUTTON_RELEASE {
+                let next_press: &xcb::ButtonPressEvent = unsafe { xcb::cast_event(&next) };
+                // The state of a release includes the button itself,
+                // so only compare the state of presses
+                if next_press.event() == press.event()
+                    && next_press.detail() == press.detail()
+                    && (r == xcb::BUTTON_RELEASE || next_press.state() == press.state())
+                {
+                    if r == xcb::BUTTON_PRESS {
+                        ticks = ticks.saturating_add(1);
+                    }
+                    continue;
+                }
+            }
+            following = Some(next);
+            break;
+        }
+
+        if let Some(window) = self.window_by_id(press.event()) {
+            let mut inner = window.lock().unwrap();
+            inner.dispatch_wheel(press, ticks)?;
+        }
+
+        match following {
+            Some(next) => self.process_xcb_event(&next),
+            None => Ok(()),
+        }
+    }
+
+    fn dispatch_xcb_event(&self, event: &xcb::GenericEvent) -> anyhow::Result<()> {
         if let Some(window_id) = window_id_from_event(event) {
-            self.process_window_event(window_id, event)?;
+            if window_id == self.root {
+                self.process_root_event(event);
+            } else {
+                self.process_window_event(window_id, event)?;
+            }
         } else {
             let r = event.response_type() & 0x7f;
+            if is_monitor_event(r, self.randr_ev) {
+                self.monitors_changed();
+                return Ok(());
+            }
             if r == self.kbd_ev {
+                if let Some(bell) = as_bell_notify(event) {
+                    self.bell_rung(bell.window());
+                    return Ok(());
+                }
                 // key press/release are not processed here.
                 // xkbcommon depends on those events in order to:
                 //    - update modifiers state
@@ -276,6 +406,68 @@ impl XConnection {
         Ok(())
     }
 
+    fn process_root_event(&self, event: &xcb::GenericEvent) {
+        if event.response_type() & 0x7f == xcb::PROPERTY_NOTIFY {
+            let prop: &xcb::PropertyNotifyEvent = unsafe { xcb::cast_event(event) };
+            if prop.atom() == xcb::ffi::XCB_ATOM_RESOURCE_MANAGER {
+                self.resource_manager_changed();
+            }
+        }
+    }
+
+    /// The resources were changed, perhaps by `xrdb`, or by the
+    /// desktop environment in response to a change in the scaling
+    /// settings; pick up any change to Xft.dpi and let the windows
+    /// know so that they can re-render at the new DPI.
+    fn resource_manager_changed(&self) {
+        let xrm = match crate::x11::xrm::parse_root_resource_manager(&self.conn, self.root) {
+            Ok(xrm) => xrm,
+            Err(err) => {
+                log::error!("failed to read RESOURCE_MANAGER: {:#}", err);
+                return;
+            }
+        };
+        let xft_dpi = crate::x11::xrm::xft_dpi(&xrm);
+        if xft_dpi == *self.xft_dpi.borrow() {
+            return;
+        }
+        log::debug!("Xft.dpi changed to {:?}", xft_dpi);
+        *self.xft_dpi.borrow_mut() = xft_dpi;
+
+        let windows: Vec<_> = self.windows.borrow().values().map(Arc::clone).collect();
+        for window in windows {
+            window.lock().unwrap().dpi_changed();
+        }
+    }
+
+    /// Monitors were plugged in, removed or rearranged; look them up
+    /// again and let the windows know, as they may now be on a monitor
+    /// with a different DPI.
+    fn monitors_changed(&self) {
+        let monitors = match query_monitors(&self.conn, self.root) {
+            Ok(monitors) => monitors,
+            Err(err) => {
+                log::error!("failed to query monitors via RandR: {:#}", err);
+                return;
+            }
+        };
+        log::debug!("monitors changed: {:?}", monitors);
+        *self.monitors.borrow_mut() = monitors;
+
+        let windows: Vec<_> = self.windows.borrow().values().map(Arc::clone).collect();
+        for window in windows {
+            window.lock().unwrap().dpi_changed();
+        }
+    }
+
+    /// Lets our windows know that the bell was rung for `window`
+    fn bell_rung(&self, window: xcb::xproto::Window) {
+        let windows: Vec<_> = self.windows.borrow().values().map(Arc::clone).collect();
+        for w in windows {
+            w.lock().unwrap().bell_rung(window);
+        }
+    }
+
     fn window_by_id(&self, window_id: xcb::xproto::Window) -> Option<Arc<Mutex<XWindowInner>>> {
         self.windows.borrow().get(&window_id).map(Arc::clone)
     }
@@ -312,6 +504,29 @@ impl XConnection {
         let atom_targets = xcb::intern_atom(&conn, false, "TARGETS")
             .get_reply()?
             .atom();
+        let atom_incr = xcb::intern_atom(&conn, false, "INCR").get_reply()?.atom();
+        let atom_net_wm_state = xcb::intern_atom(&conn, false, "_NET_WM_STATE")
+            .get_reply()?
+            .atom();
+        let atom_net_wm_state_fullscreen =
+            xcb::intern_atom(&conn, false, "_NET_WM_STATE_FULLSCREEN")
+                .get_reply()?
+                .atom();
+        let atom_net_wm_state_maximized_horz =
+            xcb::intern_atom(&conn, false, "_NET_WM_STATE_MAXIMIZED_HORZ")
+                .get_reply()?
+                .atom();
+        let atom_net_wm_state_maximized_vert =
+            xcb::intern_atom(&conn, false, "_NET_WM_STATE_MAXIMIZED_VERT")
+                .get_reply()?
+                .atom();
+        let atom_net_wm_window_opacity = xcb::intern_atom(&conn, false, "_NET_WM_WINDOW_OPACITY")
+            .get_reply()?
+            .atom();
+        let atom_wm_change_state = xcb::intern_atom(&conn, false, "WM_CHANGE_STATE")
+            .get_reply()?
+            .atom();
+        let xdnd = XdndAtoms::new(&conn)?;
         let atom_clipboard = xcb::intern_atom(&conn, false, "CLIPBOARD")
             .get_reply()?
             .atom();
@@ -326,22 +541,14 @@ impl XConnection {
 
         let mut visuals = vec![];
         for depth in screen.allowed_depths() {
-            let depth_bpp = depth.depth();
-            if depth_bpp == 24 || depth_bpp == 32 {
-                for vis in depth.visuals() {
-                    if vis.class() == xcb::xproto::VISUAL_CLASS_TRUE_COLOR as _
-                        && vis.bits_per_rgb_value() == 8
-                    {
-                        visuals.push((depth_bpp, vis));
-                    }
-                }
+            for vis in depth.visuals() {
+                visuals.push((depth.depth(), vis.class(), vis.bits_per_rgb_value(), vis));
             }
         }
-        if visuals.is_empty() {
-            bail!("no suitable visuals of depth 24 or 32 are available");
-        }
-        visuals.sort_by(|(a_depth, _), (b_depth, _)| b_depth.cmp(&a_depth));
-        let (depth, visual) = visuals[0];
+        let (depth, visual) = match pick_visual(&visuals) {
+            Some(picked) => picked,
+            None => bail!("no suitable visuals of depth 24 or 32 are available"),
+        };
 
         log::trace!(
             "picked depth {} visual id:0x{:x}, class:{}, bits_per_rgb_value:{}, \
@@ -365,19 +572,57 @@ impl XConnection {
 
         let root = screen.root();
 
+        // We want to learn about changes to RESOURCE_MANAGER
+        xcb::change_window_attributes(
+            &conn,
+            root,
+            &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_PROPERTY_CHANGE)],
+        );
+
+        // and about changes to the monitors
+        conn.prefetch_extension_data(xcb::randr::id());
+        let randr_ev = conn
+            .get_extension_data(xcb::randr::id())
+            .filter(|data| data.present())
+            .map(|data| data.first_event());
+        if randr_ev.is_some() {
+            xcb::randr::select_input(
+                &conn,
+                root,
+                (xcb::randr::NOTIFY_MASK_SCREEN_CHANGE
+                    | xcb::randr::NOTIFY_MASK_CRTC_CHANGE
+                    | xcb::randr::NOTIFY_MASK_OUTPUT_CHANGE) as u16,
+            );
+        }
+
         let xrm =
             crate::x11::xrm::parse_root_resource_manager(&conn, root).unwrap_or(HashMap::new());
-        let default_dpi = xrm
-            .get("Xft.dpi")
-            .as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("96")
-            .parse::<f64>()
+        let xft_dpi = crate::x11::xrm::xft_dpi(&xrm);
+        let monitors = query_monitors(&conn, root).unwrap_or_else(|err| {
+            log::debug!("unable to query monitors via RandR: {:#}", err);
+            vec![]
+        });
+        let default_dpi = monitors
+            .iter()
+            .find_map(MonitorInfo::dpi)
+            .or_else(|| {
+                // Without RandR, the best we can do is the size of
+                // the screen as a whole
+                let width_mm = screen.width_in_millimeters();
+                if width_mm == 0 {
+                    None
+                } else {
+                    Some(screen.width_in_pixels() as f64 * 25.4 / width_mm as f64)
+                }
+            })
             .unwrap_or(crate::DEFAULT_DPI);
 
         let conn = XConnection {
             conn,
             default_dpi,
+            xft_dpi: RefCell::new(xft_dpi),
+            monitors: RefCell::new(monitors),
+            randr_ev,
             cursor_font_id,
             screen_num,
             root,
@@ -391,12 +636,21 @@ impl XConnection {
             atom_utf8_string,
             atom_xsel_data,
             atom_targets,
+            atom_incr,
+            atom_net_wm_state,
+            atom_net_wm_state_fullscreen,
+            atom_net_wm_state_maximized_horz,
+            atom_net_wm_state_maximized_vert,
+            atom_net_wm_window_opacity,
+            atom_wm_change_state,
+            xdnd,
             windows: RefCell::new(HashMap::new()),
             should_terminate: RefCell::new(false),
             timers: RefCell::new(TimerList::new()),
             depth,
             visual,
             gl_connection: RefCell::new(None),
+            cursors: RefCell::new(HashMap::new()),
         };
 
         Ok(conn)
@@ -458,3 +712,126 @@ impl Drop for XConnection {
         }
     }
 }
+
+/// Query the active monitors via RandR
+fn query_monitors(
+    conn: &xcb::Connection,
+    root: xcb::xproto::Window,
+) -> anyhow::Result<Vec<MonitorInfo>> {
+    let reply = xcb::randr::get_monitors(conn, root, true).get_reply()?;
+    let mut monitors = vec![];
+    for info in reply.monitors() {
+        let name = match xcb::get_atom_name(conn, info.name()).get_reply() {
+            Ok(reply) => reply.name().to_owned(),
+            Err(err) => {
+                log::debug!("unable to resolve monitor name {}: {:?}", info.name(), err);
+                String::new()
+            }
+        };
+        monitors.push(MonitorInfo {
+            name,
+            x: info.x().into(),
+            y: info.y().into(),
+            width: info.width().into(),
+            height: info.height().into(),
+            width_mm: info.width_in_millimeters() as usize,
+            height_mm: info.height_in_millimeters() as usize,
+            primary: info.primary(),
+        });
+    }
+    Ok(monitors)
+}
+
+impl XConnection {
+    /// Returns the DPI to use for content at the specified root
+    /// coordinates.  An explicitly configured Xft.dpi takes precedence
+    /// over the DPI of the monitor at that location.
+    pub(crate) fn dpi_at(&self, x: i32, y: i32) -> f64 {
+        if let Some(dpi) = *self.xft_dpi.borrow() {
+            return dpi;
+        }
+        self.monitors
+            .borrow()
+            .iter()
+            .find(|m| m.contains(x as isize, y as isize))
+            .and_then(MonitorInfo::dpi)
+            .unwrap_or(self.default_dpi)
+    }
+
+    /// Returns the monitors that are currently attached to the display
+    pub fn monitors(&self) -> anyhow::Result<Vec<MonitorInfo>> {
+        let screen = self
+            .conn()
+            .get_setup()
+            .roots()
+            .nth(self.screen_num as usize)
+            .ok_or_else(|| anyhow!("no screen?"))?;
+        query_monitors(self.conn(), screen.root())
+    }
+}
+
+/// Returns true if the event type `r` is one of the RandR events that
+/// we select in order to learn that the monitors changed, given the
+/// first event number of RandR, if it is present
+fn is_monitor_event(r: u8, randr_ev: Option<u8>) -> bool {
+    match randr_ev {
+        Some(first) => {
+            r == first + xcb::randr::SCREEN_CHANGE_NOTIFY || r == first + xcb::randr::NOTIFY
+        }
+        None => false,
+    }
+}
+
+/// Chooses the visual for our windows from the (depth, class,
+/// bits_per_rgb_value, visual) tuples offered by the screen.
+/// A 32-bit TrueColor visual has an alpha channel, which allows the
+/// window background to be translucent when a compositor is running,
+/// so it is preferred over a 24-bit one; we fall back to 24-bit on
+/// servers that don't offer it.
+fn pick_visual<T: Copy>(candidates: &[(u8, u8, u8, T)]) -> Option<(u8, T)> {
+    let suitable = |wanted: u8| {
+        candidates
+            .iter()
+            .find(|(depth, class, bits_per_rgb_value, _)| {
+                *depth == wanted
+                    && *class == xcb::xproto::VISUAL_CLASS_TRUE_COLOR as u8
+                    && *bits_per_rgb_value == 8
+            })
+            .map(|(depth, _, _, visual)| (*depth, *visual))
+    };
+    suitable(32).or_else(|| suitable(24))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn visual_selection() {
+        const TRUE_COLOR: u8 = xcb::xproto::VISUAL_CLASS_TRUE_COLOR as u8;
+        const DIRECT_COLOR: u8 = xcb::xproto::VISUAL_CLASS_DIRECT_COLOR as u8;
+
+        let argb = [
+            (24, TRUE_COLOR, 8, "rgb"),
+            (24, DIRECT_COLOR, 8, "direct"),
+            (32, TRUE_COLOR, 8, "argb"),
+        ];
+        assert_eq!(pick_visual(&argb), Some((32, "argb")));
+
+        // No 32-bit visual; use the 24-bit one rather than failing
+        let rgb_only = [(24, DIRECT_COLOR, 8, "direct"), (24, TRUE_COLOR, 8, "rgb")];
+        assert_eq!(pick_visual(&rgb_only), Some((24, "rgb")));
+
+        let unsuitable = [(16, TRUE_COLOR, 6, "565"), (32, TRUE_COLOR, 10, "deep")];
+        assert_eq!(pick_visual(&unsuitable), None);
+    }
+
+    #[test]
+    fn monitor_events() {
+        assert!(is_monitor_event(89, Some(89)));
+        assert!(is_monitor_event(90, Some(89)));
+        assert!(!is_monitor_event(91, Some(89)));
+        assert!(!is_monitor_event(xcb::CONFIGURE_NOTIFY, Some(89)));
+        assert!(!is_monitor_event(89, None));
+    }
+}
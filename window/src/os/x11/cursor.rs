@@ -24,8 +24,9 @@ impl Drop for XcbCursor {
 }
 
 pub struct CursorInfo {
-    cursors: HashMap<Option<MouseCursor>, XcbCursor>,
-    cursor: Option<MouseCursor>,
+    /// The cursor most recently applied to the window, or None if
+    /// we haven't yet set one
+    cursor: Option<Option<MouseCursor>>,
     conn: Weak<XConnection>,
     size: Option<u32>,
     theme: Option<String>,
@@ -127,7 +128,6 @@ impl CursorInfo {
         let icon_path = icon_path();
 
         Self {
-            cursors: HashMap::new(),
             cursor: None,
             conn: Rc::downgrade(conn),
             size,
@@ -146,65 +146,77 @@ impl CursorInfo {
         window_id: xcb::xproto::Window,
         cursor: Option<MouseCursor>,
     ) -> anyhow::Result<()> {
-        if cursor == self.cursor {
+        if self.cursor == Some(cursor) {
             return Ok(());
         }
 
         let conn = self.conn();
 
-        let cursor_id = match self.cursors.get(&cursor) {
-            Some(cursor) => cursor.id,
-            None => match self.load_themed(&conn, cursor) {
+        let cached = conn.cursors.borrow().get(&cursor).map(|c| c.id);
+        let cursor_id = match (cached, cursor) {
+            (Some(id), _) => id,
+            (None, Some(cursor)) => match self.load_themed(&conn, cursor) {
                 Some(c) => c,
                 None => self.load_basic(&conn, cursor),
             },
+            (None, None) => self.load_blank(&conn),
         };
 
         xcb::change_window_attributes(&conn, window_id, &[(xcb::ffi::XCB_CW_CURSOR, cursor_id)]);
 
-        self.cursor = cursor;
+        self.cursor = Some(cursor);
 
         Ok(())
     }
 
-    fn load_themed(&mut self, conn: &Rc<XConnection>, cursor: Option<MouseCursor>) -> Option<u32> {
+    fn load_themed(&mut self, conn: &Rc<XConnection>, cursor: MouseCursor) -> Option<u32> {
         let theme = self.theme.as_ref()?;
         if self.pict_format_id.is_none() {
             return None;
         }
 
-        let name = match cursor.unwrap_or(MouseCursor::Arrow) {
-            MouseCursor::Arrow => "top_left_arrow",
-            MouseCursor::Hand => "hand2",
-            MouseCursor::Text => "xterm",
-            MouseCursor::SizeUpDown => "sb_v_double_arrow",
-            MouseCursor::SizeLeftRight => "sb_h_double_arrow",
+        // Themes don't agree on naming, so try the traditional X
+        // name followed by the CSS name used by newer themes
+        let names: &[&str] = match cursor {
+            MouseCursor::Arrow => &["top_left_arrow", "left_ptr", "default"],
+            MouseCursor::Hand => &["hand2", "pointer"],
+            MouseCursor::Text => &["xterm", "text"],
+            MouseCursor::SizeUpDown => &["sb_v_double_arrow", "ns-resize"],
+            MouseCursor::SizeLeftRight => &["sb_h_double_arrow", "ew-resize"],
         };
 
-        for dir in &self.icon_path {
-            let candidate = dir.join(theme).join("cursors").join(name);
-            if let Ok(file) = std::fs::File::open(&candidate) {
-                match self.parse_cursor_file(conn, file) {
-                    Ok(cursor_id) => {
-                        self.cursors.insert(
-                            cursor,
-                            XcbCursor {
-                                id: cursor_id,
-                                conn: Rc::downgrade(&conn),
-                            },
-                        );
-
-                        return Some(cursor_id);
+        for name in names {
+            for dir in &self.icon_path {
+                let candidate = dir.join(theme).join("cursors").join(name);
+                if let Ok(file) = std::fs::File::open(&candidate) {
+                    match self.parse_cursor_file(conn, file) {
+                        Ok(cursor_id) => {
+                            conn.cursors.borrow_mut().insert(
+                                Some(cursor),
+                                XcbCursor {
+                                    id: cursor_id,
+                                    conn: Rc::downgrade(&conn),
+                                },
+                            );
+
+                            return Some(cursor_id);
+                        }
+                        Err(err) => log::error!("{:#}", err),
                     }
-                    Err(err) => log::error!("{:#}", err),
                 }
             }
         }
+
+        log::debug!(
+            "theme {} has no cursor for {:?}, using the core cursor font",
+            theme,
+            cursor
+        );
         None
     }
 
-    fn load_basic(&mut self, conn: &Rc<XConnection>, cursor: Option<MouseCursor>) -> u32 {
-        let id_no = match cursor.unwrap_or(MouseCursor::Arrow) {
+    fn load_basic(&mut self, conn: &Rc<XConnection>, cursor: MouseCursor) -> u32 {
+        let id_no = match cursor {
             // `/usr/include/X11/cursorfont.h`
             // <https://docs.rs/xcb-util/0.3.0/src/xcb_util/cursor.rs.html>
             MouseCursor::Arrow => xcb_util::cursor::TOP_LEFT_ARROW,
@@ -230,8 +242,36 @@ impl CursorInfo {
             0,
         );
 
-        self.cursors.insert(
-            cursor,
+        conn.cursors.borrow_mut().insert(
+            Some(cursor),
+            XcbCursor {
+                id: cursor_id,
+                conn: Rc::downgrade(&conn),
+            },
+        );
+
+        cursor_id
+    }
+
+    /// Create an invisible cursor; X has no notion of hiding the
+    /// pointer, so we build one from a fully transparent 1x1 bitmap.
+    fn load_blank(&mut self, conn: &Rc<XConnection>) -> u32 {
+        let pixmap = conn.generate_id();
+        xcb::create_pixmap(&conn, 1, pixmap, conn.root, 1, 1);
+
+        // The initial contents of a pixmap are undefined, so explicitly
+        // clear it to ensure that the mask is empty
+        let gc = conn.generate_id();
+        xcb::create_gc(&conn, gc, pixmap, &[(xcb::ffi::XCB_GC_FOREGROUND, 0)]);
+        xcb::poly_fill_rectangle(&conn, pixmap, gc, &[xcb::Rectangle::new(0, 0, 1, 1)]);
+        xcb::free_gc(&conn, gc);
+
+        let cursor_id: xcb::ffi::xcb_cursor_t = conn.generate_id();
+        xcb::create_cursor(&conn, cursor_id, pixmap, pixmap, 0, 0, 0, 0, 0, 0, 0, 0);
+        xcb::free_pixmap(&conn, pixmap);
+
+        conn.cursors.borrow_mut().insert(
+            None,
             XcbCursor {
                 id: cursor_id,
                 conn: Rc::downgrade(&conn),
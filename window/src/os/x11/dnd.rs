@@ -0,0 +1,131 @@
+//! Support for receiving files that are dropped onto our windows
+//! using the XDND protocol.
+//! See <https://www.freedesktop.org/wiki/Specifications/XDND/>
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+/// The version of the protocol that we implement
+pub const XDND_VERSION: u32 = 5;
+
+/// The atoms used by the XDND protocol
+pub struct XdndAtoms {
+    pub aware: xcb::Atom,
+    pub enter: xcb::Atom,
+    pub position: xcb::Atom,
+    pub status: xcb::Atom,
+    pub leave: xcb::Atom,
+    pub drop: xcb::Atom,
+    pub finished: xcb::Atom,
+    pub selection: xcb::Atom,
+    pub type_list: xcb::Atom,
+    pub action_copy: xcb::Atom,
+    pub text_uri_list: xcb::Atom,
+}
+
+impl XdndAtoms {
+    pub fn new(conn: &xcb::Connection) -> anyhow::Result<Self> {
+        let intern = |name: &str| -> anyhow::Result<xcb::Atom> {
+            Ok(xcb::intern_atom(conn, false, name).get_reply()?.atom())
+        };
+        Ok(Self {
+            aware: intern("XdndAware")?,
+            enter: intern("XdndEnter")?,
+            position: intern("XdndPosition")?,
+            status: intern("XdndStatus")?,
+            leave: intern("XdndLeave")?,
+            drop: intern("XdndDrop")?,
+            finished: intern("XdndFinished")?,
+            selection: intern("XdndSelection")?,
+            type_list: intern("XdndTypeList")?,
+            action_copy: intern("XdndActionCopy")?,
+            text_uri_list: intern("text/uri-list")?,
+        })
+    }
+
+    pub fn is_xdnd_message(&self, atom: xcb::Atom) -> bool {
+        atom == self.enter || atom == self.position || atom == self.leave || atom == self.drop
+    }
+}
+
+/// Tracks a drag that is in progress over one of our windows
+#[derive(Default)]
+pub struct DragAndDrop {
+    /// The window that is the source of the drag
+    pub source: Option<xcb::xproto::Window>,
+    /// The protocol version that the source is using
+    pub version: u32,
+    /// Whether the source is able to provide a text/uri-list
+    pub acceptable: bool,
+}
+
+/// Parses a text/uri-list, as described by RFC 2483, returning the
+/// paths of the local files that it lists.  URIs that don't refer to
+/// files are skipped.
+pub fn parse_uri_list(text: &str) -> Vec<PathBuf> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|uri| {
+            let path = file_uri_path(uri);
+            if path.is_none() {
+                log::debug!("ignoring dropped uri {:?}", uri);
+            }
+            path
+        })
+        .collect()
+}
+
+/// Returns the path portion of a `file:` URI.  The host, if any,
+/// is ignored; it is typically either empty or our own hostname.
+fn file_uri_path(uri: &str) -> Option<PathBuf> {
+    if !uri.starts_with("file://") {
+        return None;
+    }
+    let rest = &uri["file://".len()..];
+    let path = &rest[rest.find('/')?..];
+    let bytes = percent_decode(path)?;
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    fn hex(b: u8) -> Option<u8> {
+        (b as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let mut result = vec![];
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hi = hex(bytes.next()?)?;
+            let lo = hex(bytes.next()?)?;
+            result.push((hi << 4) | lo);
+        } else {
+            result.push(b);
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uri_list() {
+        let list = "# dropped by a file manager\r\n\
+                    file:///home/wez/some%20file.txt\r\n\
+                    file://localhost/tmp/%C3%A9t%C3%A9\r\n\
+                    https://wezfurlong.org/\r\n\
+                    file:///bad%2\r\n\
+                    file:///home/wez/100%25\r\n";
+        assert_eq!(
+            parse_uri_list(list),
+            vec![
+                PathBuf::from("/home/wez/some file.txt"),
+                PathBuf::from("/tmp/été"),
+                PathBuf::from("/home/wez/100%"),
+            ]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::os::xkeysyms::keysym_to_keycode;
+use crate::os::xkeysyms::{keypad_keysym_to_keycode, keysym_to_keycode};
 use crate::{KeyEvent, Modifiers};
 use anyhow::{anyhow, ensure};
 use libc;
@@ -14,6 +14,80 @@ pub struct Keyboard {
 
     state: RefCell<xkb::State>,
     compose_state: RefCell<xkb::compose::State>,
+
+    /// Whether the server has agreed to suppress the synthetic
+    /// key release events that it would otherwise generate
+    /// for auto-repeating keys
+    detectable_auto_repeat: bool,
+    held_key: RefCell<HeldKey>,
+}
+
+/// Tracks the most recently pressed key and the number of times
+/// that it has been reported as pressed without being released,
+/// so that auto-repeated presses are reported with an increasing
+/// repeat count.
+#[derive(Default)]
+struct HeldKey {
+    key: Option<(xkb::Keycode, u16)>,
+}
+
+impl HeldKey {
+    /// Returns the repeat count for this event
+    fn update(&mut self, xcode: xkb::Keycode, pressed: bool) -> u16 {
+        if pressed {
+            let count = match self.key {
+                Some((code, count)) if code == xcode => count.saturating_add(1),
+                _ => 1,
+            };
+            self.key.replace((xcode, count));
+            count
+        } else {
+            if let Some((code, _)) = self.key {
+                if code == xcode {
+                    self.key.take();
+                }
+            }
+            1
+        }
+    }
+}
+
+/// Resolves the keysym produced by `xcode` given the `state` field of
+/// the core key event that reported it.  The state holds the effective
+/// modifiers in its low byte and the effective group in bits 13-14, so
+/// this takes into account the layout that was active and the shift
+/// level (including level 3, eg: AltGr, and NumLock for the keypad)
+/// at the time of the event, even if our own xkb state has yet to
+/// catch up with the server.
+fn keysym_for_event(keymap: &xkb::Keymap, xcode: xkb::Keycode, state: u16) -> xkb::Keysym {
+    let mods = xkb::ModMask::from(state & 0xff);
+    let group = xkb::LayoutIndex::from((state >> 13) & 0x3);
+    let mut xkb_state = xkb::State::new(keymap);
+    xkb_state.update_mask(mods, 0, 0, 0, 0, group);
+    xkb_state.key_get_one_sym(xcode)
+}
+
+/// Returns true if the key release described by `release` is the
+/// synthetic release that the X server emits immediately before
+/// an auto-repeated press, `next_press`, of the same key.
+/// The two events share the same keycode and timestamp.
+pub fn is_auto_repeat_release(
+    release: (xcb::xproto::Keycode, xcb::xproto::Timestamp),
+    next_press: (xcb::xproto::Keycode, xcb::xproto::Timestamp),
+) -> bool {
+    release == next_press
+}
+
+/// Returns the event as an XKB bell notification, if it is one.
+/// The bell isn't specific to our keyboard device, so this is
+/// not handled by `process_xkb_event`.
+pub fn as_bell_notify(event: &xcb::GenericEvent) -> Option<&xcb::xkb::BellNotifyEvent> {
+    let xkb_ev: &XkbGenericEvent = unsafe { xcb::cast_event(event) };
+    if xkb_ev.xkb_type() == xcb::xkb::BELL_NOTIFY {
+        Some(unsafe { xcb::cast_event(event) })
+    } else {
+        None
+    }
 }
 
 impl Keyboard {
@@ -45,6 +119,22 @@ impl Keyboard {
         let device_id = xkb::x11::get_core_keyboard_device_id(&connection);
         ensure!(device_id != -1, "Couldn't find core keyboard device");
 
+        // Ask the server not to generate a key release prior to each
+        // auto-repeated key press, so that we can tell a held key apart
+        // from a series of discrete presses
+        let detectable_auto_repeat = {
+            let flag = xcb::xkb::PER_CLIENT_FLAG_DETECTABLE_AUTO_REPEAT;
+            match xcb::xkb::per_client_flags(&connection, device_id as u16, flag, flag, 0, 0, 0)
+                .get_reply()
+            {
+                Ok(reply) => reply.supported() & reply.value() & flag != 0,
+                Err(err) => {
+                    log::debug!("detectable auto repeat is not available: {:?}", err);
+                    false
+                }
+            }
+        };
+
         let keymap = xkb::x11::keymap_new_from_device(
             &context,
             &connection,
@@ -91,12 +181,33 @@ impl Keyboard {
             cookie.request_check()?;
         }
 
+        // Ask to be told when the bell rings so that the windows can
+        // show it; we can do without it if the server won't oblige
+        {
+            let events = xcb::xkb::EVENT_TYPE_BELL_NOTIFY;
+            let cookie = xcb::xkb::select_events_checked(
+                &connection,
+                device_id as u16,
+                events as u16,
+                0,
+                events as u16,
+                0,
+                0,
+                None,
+            );
+            if let Err(err) = cookie.request_check() {
+                log::debug!("bell notifications are not available: {:?}", err);
+            }
+        }
+
         let kbd = Keyboard {
             context,
             device_id,
             keymap: RefCell::new(keymap),
             state: RefCell::new(state),
             compose_state: RefCell::new(compose_state),
+            detectable_auto_repeat,
+            held_key: RefCell::new(HeldKey::default()),
         };
 
         Ok((kbd, first_ev))
@@ -106,7 +217,12 @@ impl Keyboard {
         let pressed = (xcb_ev.response_type() & !0x80) == xcb::KEY_PRESS;
 
         let xcode = xkb::Keycode::from(xcb_ev.detail());
-        let xsym = self.state.borrow().key_get_one_sym(xcode);
+        let repeat_count = self.held_key.borrow_mut().update(xcode, pressed);
+        let xsym = keysym_for_event(&self.keymap.borrow(), xcode, xcb_ev.state());
+
+        // Text produced by a compose sequence that has no single
+        // keysym equivalent, such as one producing multiple codepoints
+        let mut composed_text = None;
 
         let ksym = if pressed {
             self.compose_state.borrow_mut().feed(xsym);
@@ -118,8 +234,14 @@ impl Keyboard {
                     return None;
                 }
                 ComposeStatus::Composed => {
-                    let res = self.compose_state.borrow().keysym();
+                    let (res, text) = {
+                        let compose_state = self.compose_state.borrow();
+                        (compose_state.keysym(), compose_state.utf8())
+                    };
                     self.compose_state.borrow_mut().reset();
+                    if res.is_none() {
+                        composed_text = text.filter(|text| !text.is_empty());
+                    }
                     res.unwrap_or(xsym)
                 }
                 ComposeStatus::Nothing => xsym,
@@ -132,7 +254,16 @@ impl Keyboard {
             xsym
         };
 
-        let kc = keysym_to_keycode(ksym).or_else(|| keysym_to_keycode(xsym))?;
+        // Let keypad keys be distinguished from the main row, while
+        // still sending the text that they produce
+        let raw_key = match composed_text {
+            Some(_) => None,
+            None => keypad_keysym_to_keycode(ksym),
+        };
+        let kc = match composed_text {
+            Some(text) => crate::KeyCode::Composed(text),
+            None => keysym_to_keycode(ksym).or_else(|| keysym_to_keycode(xsym))?,
+        };
         let raw_modifiers = self.get_key_modifiers();
         // X11 keysyms that map to KeyCode::Char already factor in the SHIFT
         // modifier state.  eg: SHIFT-c in an US layout produces `Char('C')`.
@@ -152,14 +283,18 @@ impl Keyboard {
         Some(KeyEvent {
             key: kc,
             modifiers,
-            raw_key: None,
+            raw_key,
             raw_modifiers,
             raw_code: Some(xcode),
-            repeat_count: 1,
+            repeat_count,
             key_is_down: pressed,
         })
     }
 
+    pub fn detectable_auto_repeat(&self) -> bool {
+        self.detectable_auto_repeat
+    }
+
     fn mod_is_active(&self, modifier: &str) -> bool {
         // [TODO] consider state  Depressed & consumed mods
         self.state
@@ -278,3 +413,145 @@ impl XkbGenericEvent {
         unsafe { (*self.base.ptr).device_id }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A keymap with a key that has level 3 symbols and a second
+    /// group, along with a keypad key, so that we don't depend on
+    /// the xkeyboard-config data installed on the system
+    const TEST_KEYMAP: &str = r#"xkb_keymap {
+        xkb_keycodes {
+            minimum = 8;
+            maximum = 255;
+            <AD03> = 26;
+            <NMLK> = 77;
+            <KP1> = 87;
+            <LVL3> = 92;
+        };
+        xkb_types {
+            virtual_modifiers NumLock,LevelThree;
+            type "ONE_LEVEL" {
+                modifiers = none;
+                level_name[Level1] = "Any";
+            };
+            type "FOUR_LEVEL" {
+                modifiers = Shift+LevelThree;
+                map[Shift] = Level2;
+                map[LevelThree] = Level3;
+                map[Shift+LevelThree] = Level4;
+                level_name[Level1] = "Base";
+                level_name[Level2] = "Shift";
+                level_name[Level3] = "Alt Base";
+                level_name[Level4] = "Shift Alt";
+            };
+            type "KEYPAD" {
+                modifiers = Shift+NumLock;
+                map[Shift] = Level2;
+                map[NumLock] = Level2;
+                level_name[Level1] = "Base";
+                level_name[Level2] = "Number";
+            };
+        };
+        xkb_compatibility {
+            virtual_modifiers NumLock,LevelThree;
+            interpret ISO_Level3_Shift {
+                virtualModifier = LevelThree;
+                action = SetMods(modifiers=LevelThree);
+            };
+            interpret Num_Lock {
+                virtualModifier = NumLock;
+                action = LockMods(modifiers=NumLock);
+            };
+        };
+        xkb_symbols {
+            key <AD03> {
+                type[Group1] = "FOUR_LEVEL",
+                type[Group2] = "FOUR_LEVEL",
+                symbols[Group1] = [ e, E, EuroSign, cent ],
+                symbols[Group2] = [ Cyrillic_ie, Cyrillic_IE ]
+            };
+            key <NMLK> { type = "ONE_LEVEL", [ Num_Lock ] };
+            key <KP1> { type = "KEYPAD", [ KP_End, KP_1 ] };
+            key <LVL3> { type = "ONE_LEVEL", [ ISO_Level3_Shift ] };
+            modifier_map Mod2 { <NMLK> };
+            modifier_map Mod5 { <LVL3> };
+        };
+    };"#;
+
+    #[test]
+    fn keysym_levels_and_groups() {
+        use xcb::xproto::{MOD_MASK_2, MOD_MASK_5, MOD_MASK_SHIFT};
+        use xkb::keysyms::*;
+
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            TEST_KEYMAP.to_string(),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .expect("test keymap to compile");
+
+        let shift = MOD_MASK_SHIFT as u16;
+        let level3 = MOD_MASK_5 as u16;
+        let numlock = MOD_MASK_2 as u16;
+        let group2 = 1 << 13;
+
+        assert_eq!(keysym_for_event(&keymap, 26, 0), KEY_e);
+        assert_eq!(keysym_for_event(&keymap, 26, shift), KEY_E);
+        // eg: AltGr+e
+        assert_eq!(keysym_for_event(&keymap, 26, level3), KEY_EuroSign);
+        assert_eq!(keysym_for_event(&keymap, 26, shift | level3), KEY_cent);
+        assert_eq!(keysym_for_event(&keymap, 26, group2), KEY_Cyrillic_ie);
+        assert_eq!(
+            keysym_for_event(&keymap, 26, group2 | shift),
+            KEY_Cyrillic_IE
+        );
+
+        // The keypad produces digits only when NumLock is on, and they
+        // are distinguishable from those in the main row
+        let kp_end = keysym_for_event(&keymap, 87, 0);
+        assert_eq!(kp_end, KEY_KP_End);
+        assert_eq!(keysym_to_keycode(kp_end), Some(crate::KeyCode::End));
+        assert_eq!(keypad_keysym_to_keycode(kp_end), None);
+        let kp_1 = keysym_for_event(&keymap, 87, numlock);
+        assert_eq!(kp_1, KEY_KP_1);
+        assert_eq!(keysym_to_keycode(kp_1), Some(crate::KeyCode::Char('1')));
+        assert_eq!(
+            keypad_keysym_to_keycode(kp_1),
+            Some(crate::KeyCode::Numpad(1))
+        );
+        assert_eq!(keypad_keysym_to_keycode(KEY_1), None);
+    }
+
+    #[test]
+    fn auto_repeat_release() {
+        // A release followed by a press of the same key at the same time
+        assert!(is_auto_repeat_release((38, 1000), (38, 1000)));
+        // The user released the key and pressed it again
+        assert!(!is_auto_repeat_release((38, 1000), (38, 1042)));
+        // The user rolled onto a different key
+        assert!(!is_auto_repeat_release((38, 1000), (39, 1000)));
+    }
+
+    #[test]
+    fn repeat_count() {
+        let mut held = HeldKey::default();
+        // The initial press, followed by auto-repeated presses; the
+        // synthetic releases between them are suppressed before we
+        // get here, or are never sent with detectable auto-repeat
+        assert_eq!(held.update(38, true), 1);
+        assert_eq!(held.update(38, true), 2);
+        assert_eq!(held.update(38, true), 3);
+        // Another key takes over the repeat
+        assert_eq!(held.update(39, true), 1);
+        // Releasing a key that isn't repeating doesn't reset the count
+        assert_eq!(held.update(38, false), 1);
+        assert_eq!(held.update(39, true), 2);
+        // The real release ends the sequence
+        assert_eq!(held.update(39, false), 1);
+        assert_eq!(held.update(39, true), 1);
+    }
+}
@@ -1,6 +1,7 @@
 #![cfg(all(unix, not(target_os = "macos")))]
 pub mod connection;
 pub mod cursor;
+pub mod dnd;
 pub mod keyboard;
 pub mod window;
 pub mod xrm;
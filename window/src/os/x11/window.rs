@@ -1,3 +1,4 @@
+use super::dnd::{parse_uri_list, DragAndDrop, XDND_VERSION};
 use super::*;
 use crate::bitmaps::*;
 use crate::connection::ConnectionOps;
@@ -5,8 +6,9 @@ use crate::os::xkeysyms;
 use crate::os::{Connection, Window};
 use crate::WindowConfigHandle;
 use crate::{
-    Clipboard, Dimensions, MouseButtons, MouseCursor, MouseEvent, MouseEventKind, MousePress,
-    Point, Rect, ScreenPoint, Size, WindowCallbacks, WindowDecorations, WindowOps, WindowOpsMut,
+    Clipboard, CloseAction, Dimensions, MouseButtons, MouseCursor, MouseEvent, MouseEventKind,
+    MousePress, PaintMetrics, Point, Rect, ScreenPoint, Size, WindowCallbacks, WindowDecorations,
+    WindowOps, WindowOpsMut,
 };
 use anyhow::{anyhow, Context as _};
 use promise::{Future, Promise};
@@ -15,6 +17,28 @@ use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::rc::{Rc, Weak};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long to wait for the selection owner to send us the
+/// clipboard contents before giving up
+const CLIPBOARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The actions and source indication for _NET_WM_STATE client messages;
+/// see <https://specifications.freedesktop.org/wm-spec/latest/ar01s05.html>
+const NET_WM_STATE_REMOVE: u32 = 0;
+const NET_WM_STATE_ADD: u32 = 1;
+const NET_WM_SOURCE_APPLICATION: u32 = 1;
+/// The WM_STATE value that WM_CHANGE_STATE uses to request that a
+/// window be iconified; see ICCCM section 4.1.4
+const ICONIC_STATE: u32 = 3;
+
+/// The sizing constraints that we advertise via WM_NORMAL_HINTS
+#[derive(Default, Clone, Copy)]
+struct SizeHints {
+    /// (x, y, base_width, base_height)
+    increments: Option<(u16, u16, u16, u16)>,
+    min_size: Option<(u16, u16)>,
+}
 
 #[derive(Default)]
 struct CopyAndPaste {
@@ -22,6 +46,11 @@ struct CopyAndPaste {
     primary_selection_owned: Option<String>,
     clipboard_request: Option<Promise<String>>,
     selection_request: Option<Promise<String>>,
+    /// Data accumulated so far while receiving a large selection
+    /// using the INCR protocol
+    incr: Option<(Clipboard, Vec<u8>)>,
+    /// When we last asked the selection owner for its data
+    request_started: Option<Instant>,
     time: u32,
 }
 
@@ -46,6 +75,22 @@ impl CopyAndPaste {
             Clipboard::Clipboard => &mut self.clipboard_request,
         }
     }
+
+    /// Fail any requests that the selection owner has failed
+    /// to respond to in a timely fashion
+    fn expire_requests(&mut self) {
+        match self.request_started {
+            Some(started) if started.elapsed() >= CLIPBOARD_TIMEOUT => {}
+            _ => return,
+        }
+        self.request_started.take();
+        self.incr.take();
+        for clipboard in &[Clipboard::Clipboard, Clipboard::PrimarySelection] {
+            if let Some(mut promise) = self.request_mut(*clipboard).take() {
+                promise.err(anyhow!("timed out waiting for {:?} contents", clipboard));
+            }
+        }
+    }
 }
 
 pub(crate) struct XWindowInner {
@@ -56,20 +101,29 @@ pub(crate) struct XWindowInner {
     height: u16,
     expose: VecDeque<Rect>,
     paint_all: bool,
+    /// The most recent size reported by CONFIGURE_NOTIFY that has
+    /// not yet been passed on to the resize callback
+    pending_resize: Option<(u16, u16)>,
+    /// Whether the window manager has made us fullscreen; kept up to
+    /// date by watching for changes to _NET_WM_STATE
+    fullscreen: bool,
+    /// Whether the window manager has maximized us, tracked in the
+    /// same way as `fullscreen`
+    maximized: bool,
+    /// The DPI of the monitor on which the window was last seen
+    dpi: f64,
+    /// The mouse buttons that are currently held down
+    mouse_buttons: MouseButtons,
+    /// Whether we have keyboard focus, as last reported to the callbacks
+    has_focus: bool,
+    size_hints: SizeHints,
     cursors: CursorInfo,
     copy_and_paste: CopyAndPaste,
+    drag_and_drop: DragAndDrop,
     config: WindowConfigHandle,
     gl_state: Option<Rc<glium::backend::Context>>,
-}
-
-fn enclosing_boundary_with(a: &Rect, b: &Rect) -> Rect {
-    let left = a.min_x().min(b.min_x());
-    let right = a.max_x().max(b.max_x());
-
-    let top = a.min_y().min(b.min_y());
-    let bottom = a.max_y().max(b.max_y());
-
-    Rect::new(Point::new(left, top), Size::new(right - left, bottom - top))
+    /// Whether to report `PaintMetrics` to the callbacks
+    paint_metrics: bool,
 }
 
 impl Drop for XWindowInner {
@@ -116,10 +170,86 @@ impl XWindowInner {
         self.callbacks.created(&window_handle, gl_state)
     }
 
+    /// Deliver the most recently configured size to the callbacks.
+    /// This is deferred until we paint so that the flood of events
+    /// produced by an interactive resize results in a single resize.
+    fn dispatch_pending_resize(&mut self) {
+        let (width, height) = match self.pending_resize.take() {
+            Some(size) => size,
+            None => return,
+        };
+        let dpi = self.current_dpi(width, height);
+        if width == self.width && height == self.height && dpi == self.dpi {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.dpi = dpi;
+        self.paint_all = true;
+        self.callbacks.resize(
+            Dimensions {
+                pixel_width: self.width as usize,
+                pixel_height: self.height as usize,
+                dpi: self.dpi as usize,
+            },
+            self.fullscreen,
+        )
+    }
+
+    /// Called when the effective DPI may have changed; re-evaluate
+    /// it on the next paint, which will call the resize callback
+    /// if it differs from the DPI that we last reported.
+    pub(crate) fn dpi_changed(&mut self) {
+        if self.pending_resize.is_none() {
+            self.pending_resize.replace((self.width, self.height));
+        }
+    }
+
+    /// Determine the DPI of the monitor containing the center of the window
+    fn current_dpi(&self, width: u16, height: u16) -> f64 {
+        let conn = self.conn();
+        match xcb::xproto::translate_coordinates(
+            &conn,
+            self.window_id,
+            conn.root,
+            (width / 2) as i16,
+            (height / 2) as i16,
+        )
+        .get_reply()
+        {
+            Ok(reply) => conn.dpi_at(reply.dst_x().into(), reply.dst_y().into()),
+            Err(err) => {
+                log::error!("translate_coordinates failed: {:?}", err);
+                self.dpi
+            }
+        }
+    }
+
+    /// Determine where the client area is on the screen.  The
+    /// coordinates in CONFIGURE_NOTIFY are relative to the parent,
+    /// which is the frame window when running under a reparenting
+    /// window manager, so we ask the server to translate for us.
+    fn window_position(&self) -> anyhow::Result<ScreenPoint> {
+        let conn = self.conn();
+        let reply = xcb::xproto::translate_coordinates(&conn, self.window_id, conn.root, 0, 0)
+            .get_reply()?;
+        Ok(ScreenPoint::new(reply.dst_x().into(), reply.dst_y().into()))
+    }
+
     pub fn paint(&mut self) -> anyhow::Result<()> {
+        self.dispatch_pending_resize();
+        // We're called periodically by the event loop, which makes
+        // this a convenient place to check for a stalled transfer
+        self.copy_and_paste.expire_requests();
+
         if !self.paint_all && self.expose.is_empty() {
             return Ok(());
         }
+        let mut metrics = if self.paint_metrics {
+            Some(self.damage_metrics())
+        } else {
+            None
+        };
         self.paint_all = false;
         self.expose.clear();
 
@@ -136,30 +266,64 @@ impl XWindowInner {
                 (u32::from(self.width), u32::from(self.height)),
             );
 
+            let start = Instant::now();
             self.callbacks.paint(&mut frame);
+            let painted = Instant::now();
             frame.finish()?;
+
+            if let Some(metrics) = metrics.as_mut() {
+                metrics.paint = painted - start;
+                metrics.present = painted.elapsed();
+                self.callbacks.paint_metrics(metrics);
+            }
         }
 
         Ok(())
     }
 
+    /// Describes the regions that are about to be painted
+    fn damage_metrics(&self) -> PaintMetrics {
+        if self.paint_all {
+            PaintMetrics {
+                rects: 1,
+                pixels: usize::from(self.width) * usize::from(self.height),
+                ..PaintMetrics::default()
+            }
+        } else {
+            PaintMetrics {
+                rects: self.expose.len(),
+                pixels: self
+                    .expose
+                    .iter()
+                    .map(|r| (r.size.width * r.size.height) as usize)
+                    .sum(),
+                ..PaintMetrics::default()
+            }
+        }
+    }
+
     /// Add a region to the list of exposed/damaged/dirty regions.
-    /// Note that a window resize will likely invalidate the entire window.
-    /// If the new region intersects with the prior region, then we expand
-    /// it to encompass both.  This avoids bloating the list with a series
-    /// of increasing rectangles when resizing larger or smaller.
+    /// Note that a window resize will likely invalidate the entire window,
+    /// and that a live resize generates a stream of overlapping regions;
+    /// those are coalesced here so that `paint` has only a small set of
+    /// disjoint regions to consider, and collapse to `paint_all` once
+    /// they cover the whole window.
     fn expose(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        if self.paint_all {
+            return;
+        }
+        let window = Rect::new(
+            Point::new(0, 0),
+            Size::new(self.width as isize, self.height as isize),
+        );
         let expose = Rect::new(
             Point::new(x as isize, y as isize),
             Size::new(width as isize, height as isize),
         );
-        if let Some(prior) = self.expose.back_mut() {
-            if prior.intersects(&expose) {
-                *prior = enclosing_boundary_with(&prior, &expose);
-                return;
-            }
+        if merge_expose(&mut self.expose, expose, &window) {
+            self.expose.clear();
+            self.paint_all = true;
         }
-        self.expose.push_back(expose);
     }
 
     fn do_mouse_event(&mut self, event: &MouseEvent) -> anyhow::Result<()> {
@@ -172,6 +336,34 @@ impl XWindowInner {
         self.cursors.set_cursor(self.window_id, cursor)
     }
 
+    /// Dispatches `ticks` movements of the wheel button pressed in
+    /// `button_press` as a single event.  The connection folds bursts
+    /// of wheel presses that are already queued into one call.
+    pub fn dispatch_wheel(
+        &mut self,
+        button_press: &xcb::ButtonPressEvent,
+        ticks: i16,
+    ) -> anyhow::Result<()> {
+        self.dispatch_button(xcb::BUTTON_PRESS, button_press, ticks)
+    }
+
+    fn dispatch_button(
+        &mut self,
+        r: u8,
+        button_press: &xcb::ButtonPressEvent,
+        ticks: i16,
+    ) -> anyhow::Result<()> {
+        self.copy_and_paste.time = button_press.time();
+        self.mouse_buttons = xkeysyms::mouse_buttons_from_state(button_press.state());
+        match button_event(r, button_press, ticks) {
+            Some(event) => {
+                self.mouse_buttons = event.mouse_buttons;
+                self.do_mouse_event(&event)
+            }
+            None => Ok(()),
+        }
+    }
+
     pub fn dispatch_event(&mut self, event: &xcb::GenericEvent) -> anyhow::Result<()> {
         let r = event.response_type() & 0x7f;
         let conn = self.conn();
@@ -182,16 +374,12 @@ impl XWindowInner {
             }
             xcb::CONFIGURE_NOTIFY => {
                 let cfg: &xcb::ConfigureNotifyEvent = unsafe { xcb::cast_event(event) };
-                self.width = cfg.width();
-                self.height = cfg.height();
-                self.callbacks.resize(
-                    Dimensions {
-                        pixel_width: self.width as usize,
-                        pixel_height: self.height as usize,
-                        dpi: conn.default_dpi as usize,
-                    },
-                    self.is_fullscreen().unwrap_or(false),
-                )
+                // We also get this event when the window is moved, which
+                // may take it to a monitor with a different DPI, so we
+                // check for changes when the pending resize is dispatched.
+                // Coalesce with any other configure events that arrive
+                // before the next paint.
+                self.pending_resize.replace((cfg.width(), cfg.height()));
             }
             xcb::KEY_PRESS | xcb::KEY_RELEASE => {
                 let key_press: &xcb::KeyPressEvent = unsafe { xcb::cast_event(event) };
@@ -205,6 +393,10 @@ impl XWindowInner {
 
             xcb::MOTION_NOTIFY => {
                 let motion: &xcb::MotionNotifyEvent = unsafe { xcb::cast_event(event) };
+                // Refresh from the event state in case we missed a press
+                // or release; eg: the button was pressed before the pointer
+                // entered the window
+                self.mouse_buttons = xkeysyms::mouse_buttons_from_state(motion.state());
 
                 let event = MouseEvent {
                     kind: MouseEventKind::Move,
@@ -217,69 +409,24 @@ impl XWindowInner {
                         motion.root_y().try_into().unwrap(),
                     ),
                     modifiers: xkeysyms::modifiers_from_state(motion.state()),
-                    mouse_buttons: MouseButtons::default(),
+                    mouse_buttons: self.mouse_buttons,
                 };
                 self.do_mouse_event(&event)?;
             }
             xcb::BUTTON_PRESS | xcb::BUTTON_RELEASE => {
                 let button_press: &xcb::ButtonPressEvent = unsafe { xcb::cast_event(event) };
-                self.copy_and_paste.time = button_press.time();
-
-                let kind = match button_press.detail() {
-                    b @ 1..=3 => {
-                        let button = match b {
-                            1 => MousePress::Left,
-                            2 => MousePress::Middle,
-                            3 => MousePress::Right,
-                            _ => unreachable!(),
-                        };
-                        if r == xcb::BUTTON_PRESS {
-                            MouseEventKind::Press(button)
-                        } else {
-                            MouseEventKind::Release(button)
-                        }
-                    }
-                    b @ 4..=5 => {
-                        if r == xcb::BUTTON_RELEASE {
-                            return Ok(());
-                        }
-
-                        // Ideally this would be configurable, but it's currently a bit
-                        // awkward to configure this layer, so let's just improve the
-                        // default for now!
-                        const LINES_PER_TICK: i16 = 5;
-
-                        MouseEventKind::VertWheel(if b == 4 {
-                            LINES_PER_TICK
-                        } else {
-                            -LINES_PER_TICK
-                        })
-                    }
-                    _ => {
-                        eprintln!("button {} is not implemented", button_press.detail());
-                        return Ok(());
-                    }
-                };
-
-                let event = MouseEvent {
-                    kind,
-                    coords: Point::new(
-                        button_press.event_x().try_into().unwrap(),
-                        button_press.event_y().try_into().unwrap(),
-                    ),
-                    screen_coords: ScreenPoint::new(
-                        button_press.root_x().try_into().unwrap(),
-                        button_press.root_y().try_into().unwrap(),
-                    ),
-                    modifiers: xkeysyms::modifiers_from_state(button_press.state()),
-                    mouse_buttons: MouseButtons::default(),
-                };
-                self.do_mouse_event(&event)?;
+                self.dispatch_button(r, button_press, 1)?;
             }
             xcb::CLIENT_MESSAGE => {
                 let msg: &xcb::ClientMessageEvent = unsafe { xcb::cast_event(event) };
-                if msg.data().data32()[0] == conn.atom_delete() && self.callbacks.can_close() {
-                    xcb::destroy_window(conn.conn(), self.window_id);
+                if conn.xdnd.is_xdnd_message(msg.type_()) {
+                    self.xdnd_message(msg)?;
+                } else if msg.data().data32()[0] == conn.atom_delete() {
+                    // The callbacks are told about the close when
+                    // the resulting DESTROY_NOTIFY is processed
+                    if delete_window_destroys(&mut *self.callbacks, self.window_id) {
+                        xcb::destroy_window(conn.conn(), self.window_id);
+                    }
                 }
             }
             xcb::DESTROY_NOTIFY => {
@@ -302,17 +449,63 @@ impl XWindowInner {
                     msg.atom(),
                     conn.atom_xsel_data
                 );
+                if msg.atom() == conn.atom_xsel_data
+                    && msg.state() == xcb::xproto::PROPERTY_NEW_VALUE as u8
+                    && self.copy_and_paste.incr.is_some()
+                {
+                    self.incr_chunk()?;
+                }
+                if msg.atom() == conn.atom_net_wm_state {
+                    match self.query_net_wm_state() {
+                        Ok(state) => {
+                            self.fullscreen = state.contains(&conn.atom_net_wm_state_fullscreen);
+                            self.maximized = is_maximized(&conn, &state);
+                        }
+                        Err(err) => log::error!("Failed to determine window state: {}", err),
+                    }
+                }
             }
-            xcb::FOCUS_IN => {
-                log::trace!("Calling focus_change(true)");
-                self.callbacks.focus_change(true);
+            xcb::FOCUS_IN | xcb::FOCUS_OUT => {
+                let focus: &xcb::FocusInEvent = unsafe { xcb::cast_event(event) };
+                // Keyboard grabs (eg: by the window manager while
+                // alt-tabbing) generate focus events that don't reflect
+                // a real change in focus, and the NotifyPointer detail
+                // concerns the window beneath the pointer rather than us.
+                let mode = u32::from(focus.mode());
+                if mode == xcb::NOTIFY_MODE_GRAB
+                    || mode == xcb::NOTIFY_MODE_UNGRAB
+                    || u32::from(focus.detail()) == xcb::NOTIFY_DETAIL_POINTER
+                {
+                    log::trace!(
+                        "Ignoring focus event mode={} detail={}",
+                        focus.mode(),
+                        focus.detail()
+                    );
+                    return Ok(());
+                }
+                let focused = r == xcb::FOCUS_IN;
+                // Focus moving between our window and its ancestors can
+                // produce consecutive events of the same kind
+                if focused == self.has_focus {
+                    return Ok(());
+                }
+                self.has_focus = focused;
+                log::trace!("Calling focus_change({})", focused);
+                self.callbacks.focus_change(focused);
             }
-            xcb::FOCUS_OUT => {
-                log::trace!("Calling focus_change(false)");
-                self.callbacks.focus_change(false);
+            xcb::ENTER_NOTIFY | xcb::LEAVE_NOTIFY => {
+                let crossing: &xcb::EnterNotifyEvent = unsafe { xcb::cast_event(event) };
+                // Pointer grabs generate crossing events even though
+                // the pointer hasn't actually moved
+                if u32::from(crossing.mode()) != xcb::NOTIFY_MODE_NORMAL {
+                    return Ok(());
+                }
+                let entered = r == xcb::ENTER_NOTIFY;
+                log::trace!("Calling mouse_enter_leave({})", entered);
+                self.callbacks.mouse_enter_leave(entered);
             }
             _ => {
-                eprintln!("unhandled: {:x}", r);
+                log::trace!("unhandled: {} ({:x})", event_name(r), r);
             }
         }
 
@@ -384,21 +577,33 @@ impl XWindowInner {
             conn.atom_clipboard,
         );
 
+        // ICCCM: obsolete clients may not specify a property, in
+        // which case we should use the target atom as the property
+        let property = if request.property() == xcb::NONE {
+            request.target()
+        } else {
+            request.property()
+        };
+
         let selprop = if request.target() == conn.atom_targets {
             // They want to know which targets we support
-            let atoms: [u32; 1] = [conn.atom_utf8_string];
+            let atoms: [u32; 3] = [
+                conn.atom_targets,
+                conn.atom_utf8_string,
+                xcb::xproto::ATOM_STRING,
+            ];
             xcb::xproto::change_property(
                 &conn,
                 xcb::xproto::PROP_MODE_REPLACE as u8,
                 request.requestor(),
-                request.property(),
+                property,
                 xcb::xproto::ATOM_ATOM,
                 32, /* 32-bit atom value */
                 &atoms,
             );
 
             // let the requestor know that we set their property
-            request.property()
+            property
         } else if request.target() == conn.atom_utf8_string
             || request.target() == xcb::xproto::ATOM_STRING
         {
@@ -412,13 +617,13 @@ impl XWindowInner {
                         &conn,
                         xcb::xproto::PROP_MODE_REPLACE as u8,
                         request.requestor(),
-                        request.property(),
+                        property,
                         request.target(),
                         8, /* 8-bit string data */
                         text.as_bytes(),
                     );
                     // let the requestor know that we set their property
-                    request.property()
+                    property
                 } else {
                     // We have no clipboard so there is nothing to report
                     xcb::NONE
@@ -462,8 +667,17 @@ impl XWindowInner {
             selection.property()
         );
 
-        if let Some(clipboard) = self.selection_atom_to_clipboard(selection.selection()) {
-            if selection.property() != xcb::NONE {
+        if selection.selection() == conn.xdnd.selection {
+            self.xdnd_selection_notify(selection);
+        } else if let Some(clipboard) = self.selection_atom_to_clipboard(selection.selection()) {
+            if selection.property() != xcb::NONE && self.is_incr(selection)? {
+                // The owner will send the data in chunks; deleting the
+                // property tells it to send the first of them, and each
+                // chunk is then announced via PROPERTY_NOTIFY
+                log::trace!("clipboard: starting INCR transfer");
+                self.copy_and_paste.incr.replace((clipboard, vec![]));
+                xcb::delete_property(&conn, self.window_id, selection.property());
+            } else if selection.property() != xcb::NONE {
                 match xcb_util::icccm::get_text_property(
                     &conn,
                     selection.requestor(),
@@ -480,6 +694,12 @@ impl XWindowInner {
                     }
                     Err(err) => {
                         log::error!("clipboard: err while getting clipboard property: {:?}", err);
+                        // Fail the request now rather than leaving it
+                        // to time out
+                        if let Some(mut promise) = self.copy_and_paste.request_mut(clipboard).take()
+                        {
+                            promise.err(anyhow!("failed to read selection: {:?}", err));
+                        }
                     }
                 }
             } else if let Some(mut promise) = self.copy_and_paste.request_mut(clipboard).take() {
@@ -489,50 +709,237 @@ impl XWindowInner {
         Ok(())
     }
 
-    fn is_fullscreen(&self) -> anyhow::Result<bool> {
-        let conn = self.conn();
+    /// The bell was rung for `window`, which is NONE when it was rung
+    /// via the core protocol rather than for a particular window; that
+    /// is taken to be for whichever window has the focus
+    pub(crate) fn bell_rung(&mut self, window: xcb::xproto::Window) {
+        if window == self.window_id || (window == xcb::NONE && self.has_focus) {
+            self.callbacks.bell();
+        }
+    }
 
-        let net_wm_state = xcb::intern_atom(conn.conn(), false, "_NET_WM_STATE")
-            .get_reply()?
-            .atom();
-        let net_wm_state_fullscreen =
-            xcb::intern_atom(conn.conn(), false, "_NET_WM_STATE_FULLSCREEN")
+    /// Handles the client messages sent by the source of a drag
+    fn xdnd_message(&mut self, msg: &xcb::ClientMessageEvent) -> anyhow::Result<()> {
+        let conn = self.conn();
+        let data = msg.data().data32();
+        let source = data[0];
+        let msg_type = msg.type_();
+
+        if msg_type == conn.xdnd.enter {
+            // The source lists up to three types in the message itself,
+            // and sets the low bit if there are more in XdndTypeList
+            let types = if data[1] & 1 != 0 {
+                xcb::xproto::get_property(
+                    &conn,
+                    false,
+                    source,
+                    conn.xdnd.type_list,
+                    xcb::xproto::ATOM_ATOM,
+                    0,
+                    1024,
+                )
                 .get_reply()?
-                .atom();
+                .value::<u32>()
+                .to_vec()
+            } else {
+                data[2..5].to_vec()
+            };
+            self.drag_and_drop = DragAndDrop {
+                source: Some(source),
+                version: (data[1] >> 24).min(XDND_VERSION),
+                acceptable: types.contains(&conn.xdnd.text_uri_list),
+            };
+        } else if msg_type == conn.xdnd.position {
+            // The source waits for us to say whether we'd accept a drop
+            let accept = self.drag_and_drop.source == Some(source) && self.drag_and_drop.acceptable;
+            self.send_xdnd_message(
+                source,
+                conn.xdnd.status,
+                [
+                    self.window_id,
+                    if accept { 1 } else { 0 },
+                    0,
+                    0,
+                    if accept {
+                        conn.xdnd.action_copy
+                    } else {
+                        xcb::NONE
+                    },
+                ],
+            );
+        } else if msg_type == conn.xdnd.leave {
+            self.drag_and_drop = DragAndDrop::default();
+        } else if msg_type == conn.xdnd.drop {
+            if self.drag_and_drop.source == Some(source) && self.drag_and_drop.acceptable {
+                // The drop completes when the data arrives via SELECTION_NOTIFY
+                let time = if self.drag_and_drop.version >= 1 {
+                    data[2]
+                } else {
+                    xcb::CURRENT_TIME
+                };
+                xcb::convert_selection(
+                    &conn,
+                    self.window_id,
+                    conn.xdnd.selection,
+                    conn.xdnd.text_uri_list,
+                    conn.xdnd.selection,
+                    time,
+                );
+            } else {
+                self.send_xdnd_message(source, conn.xdnd.finished, [self.window_id, 0, 0, 0, 0]);
+                self.drag_and_drop = DragAndDrop::default();
+            }
+        }
+        Ok(())
+    }
+
+    /// The dropped data has arrived; pass the files to the callbacks and
+    /// let the source know that the drop is complete
+    fn xdnd_selection_notify(&mut self, selection: &xcb::SelectionNotifyEvent) {
+        let conn = self.conn();
+        let mut accepted = false;
+
+        if selection.property() != xcb::NONE {
+            match xcb::xproto::get_property(
+                &conn,
+                true,
+                self.window_id,
+                selection.property(),
+                xcb::xproto::ATOM_ANY,
+                0,
+                1024 * 1024,
+            )
+            .get_reply()
+            {
+                Ok(reply) => {
+                    let paths = parse_uri_list(&String::from_utf8_lossy(reply.value::<u8>()));
+                    if !paths.is_empty() {
+                        self.callbacks.dropped_files(paths);
+                        accepted = true;
+                    }
+                }
+                Err(err) => log::error!("failed to read dropped data: {:?}", err),
+            }
+        }
+
+        if let Some(source) = self.drag_and_drop.source.take() {
+            self.send_xdnd_message(
+                source,
+                conn.xdnd.finished,
+                [
+                    self.window_id,
+                    if accepted { 1 } else { 0 },
+                    if accepted {
+                        conn.xdnd.action_copy
+                    } else {
+                        xcb::NONE
+                    },
+                    0,
+                    0,
+                ],
+            );
+        }
+        self.drag_and_drop = DragAndDrop::default();
+    }
+
+    fn send_xdnd_message(&self, target: xcb::xproto::Window, msg_type: xcb::Atom, data: [u32; 5]) {
+        let conn = self.conn();
+        xcb::xproto::send_event(
+            &conn,
+            false,
+            target,
+            xcb::xproto::EVENT_MASK_NO_EVENT,
+            &xcb::xproto::ClientMessageEvent::new(
+                32,
+                target,
+                msg_type,
+                xcb::ClientMessageData::from_data32(data),
+            ),
+        );
+    }
+
+    /// Returns true if the selection owner has elected to transfer
+    /// the data using the INCR protocol
+    fn is_incr(&self, selection: &xcb::SelectionNotifyEvent) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let reply = xcb::xproto::get_property(
+            &conn,
+            false,
+            selection.requestor(),
+            selection.property(),
+            xcb::xproto::ATOM_ANY,
+            0,
+            0,
+        )
+        .get_reply()?;
+        Ok(reply.type_() == conn.atom_incr)
+    }
+
+    /// Consume the next chunk of an INCR transfer.  The owner signals
+    /// the end of the transfer by sending a zero length chunk.
+    fn incr_chunk(&mut self) -> anyhow::Result<()> {
+        let conn = self.conn();
+        let reply = xcb::xproto::get_property(
+            &conn,
+            true,
+            self.window_id,
+            conn.atom_xsel_data,
+            xcb::xproto::ATOM_ANY,
+            0,
+            u32::max_value() / 4,
+        )
+        .get_reply()?;
+        let chunk = reply.value::<u8>();
+
+        if chunk.is_empty() {
+            if let Some((clipboard, data)) = self.copy_and_paste.incr.take() {
+                log::trace!("clipboard: INCR transfer complete, {} bytes", data.len());
+                if let Some(mut promise) = self.copy_and_paste.request_mut(clipboard).take() {
+                    promise.ok(String::from_utf8_lossy(&data).into_owned());
+                }
+            }
+        } else if let Some((_, data)) = self.copy_and_paste.incr.as_mut() {
+            data.extend_from_slice(chunk);
+            // The transfer is making progress, so don't time it out
+            self.copy_and_paste.request_started.replace(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Returns the atoms that are currently set in _NET_WM_STATE.
+    /// If the window manager doesn't support EWMH then this is empty.
+    fn query_net_wm_state(&self) -> anyhow::Result<Vec<xcb::Atom>> {
+        let conn = self.conn();
 
         let reply = xcb::xproto::get_property(
             &conn,
             false,
             self.window_id,
-            net_wm_state,
+            conn.atom_net_wm_state,
             xcb::xproto::ATOM_ATOM,
             0,
             1024,
         )
         .get_reply()?;
 
-        let state = reply.value::<u32>();
+        Ok(reply.value::<u32>().to_vec())
+    }
 
-        Ok(state
-            .iter()
-            .position(|&x| x == net_wm_state_fullscreen)
-            .is_some())
+    /// Ask the X server whether _NET_WM_STATE currently includes
+    /// _NET_WM_STATE_FULLSCREEN
+    fn query_fullscreen(&self) -> anyhow::Result<bool> {
+        let fullscreen = self.conn().atom_net_wm_state_fullscreen;
+        Ok(self.query_net_wm_state()?.contains(&fullscreen))
     }
 
-    fn set_fullscreen_hint(&mut self, enable: bool) -> anyhow::Result<()> {
+    /// Ask the window manager to change up to two _NET_WM_STATE
+    /// properties.  If the window manager doesn't support EWMH
+    /// then nothing will happen.
+    fn change_net_wm_state(&self, action: u32, first: xcb::Atom, second: xcb::Atom) {
         let conn = self.conn();
 
-        let net_wm_state = xcb::intern_atom(conn.conn(), false, "_NET_WM_STATE")
-            .get_reply()?
-            .atom();
-        let net_wm_state_fullscreen =
-            xcb::intern_atom(conn.conn(), false, "_NET_WM_STATE_FULLSCREEN")
-                .get_reply()?
-                .atom();
-
-        let data: [u32; 5] = [if enable { 1 } else { 0 }, net_wm_state_fullscreen, 0, 0, 0];
+        let data: [u32; 5] = [action, first, second, NET_WM_SOURCE_APPLICATION, 0];
 
-        // Ask window manager to change our fullscreen state
         xcb::xproto::send_event(
             &conn,
             true,
@@ -542,15 +949,126 @@ impl XWindowInner {
             &xcb::xproto::ClientMessageEvent::new(
                 32,
                 self.window_id,
-                net_wm_state,
+                conn.atom_net_wm_state,
                 xcb::ClientMessageData::from_data32(data),
             ),
         );
+    }
+
+    fn set_fullscreen_hint(&mut self, enable: bool) -> anyhow::Result<()> {
+        let conn = self.conn();
+
+        // Ask window manager to change our fullscreen state
+        self.change_net_wm_state(
+            if enable {
+                NET_WM_STATE_ADD
+            } else {
+                NET_WM_STATE_REMOVE
+            },
+            conn.atom_net_wm_state_fullscreen,
+            0,
+        );
         self.adjust_decorations(self.config.decorations())?;
 
+        // Size increments can prevent the window from exactly filling
+        // the screen, so suspend them while we're fullscreen
+        self.apply_size_hints(!enable);
+
         Ok(())
     }
 
+    /// Ask the window manager to iconify the window
+    fn minimize_hint(&self) {
+        let conn = self.conn();
+        let data: [u32; 5] = [ICONIC_STATE, 0, 0, 0, 0];
+        xcb::xproto::send_event(
+            &conn,
+            false,
+            conn.root,
+            xcb::xproto::EVENT_MASK_SUBSTRUCTURE_REDIRECT
+                | xcb::xproto::EVENT_MASK_SUBSTRUCTURE_NOTIFY,
+            &xcb::xproto::ClientMessageEvent::new(
+                32,
+                self.window_id,
+                conn.atom_wm_change_state,
+                xcb::ClientMessageData::from_data32(data),
+            ),
+        );
+    }
+
+    /// Ask the window manager to maximize or restore the window.
+    /// As with fullscreen, the new geometry arrives via CONFIGURE_NOTIFY.
+    fn set_maximized_hint(&self, enable: bool) {
+        let conn = self.conn();
+        self.change_net_wm_state(
+            if enable {
+                NET_WM_STATE_ADD
+            } else {
+                NET_WM_STATE_REMOVE
+            },
+            conn.atom_net_wm_state_maximized_horz,
+            conn.atom_net_wm_state_maximized_vert,
+        );
+    }
+
+    /// Ask the server to resize the client area.  The size is limited
+    /// to that of the screen; the buffers are reallocated when the
+    /// resulting CONFIGURE_NOTIFY is processed.
+    fn try_set_inner_size(&mut self, width: usize, height: usize) -> anyhow::Result<()> {
+        let width: u16 = width
+            .try_into()
+            .with_context(|| format!("requested window width {} is too large", width))?;
+        let height: u16 = height
+            .try_into()
+            .with_context(|| format!("requested window height {} is too large", height))?;
+
+        let conn = self.conn();
+        let setup = conn.conn().get_setup();
+        let screen = setup
+            .roots()
+            .nth(conn.screen_num() as usize)
+            .ok_or_else(|| anyhow!("no screen?"))?;
+        let width = width.min(screen.width_in_pixels());
+        let height = height.min(screen.height_in_pixels());
+
+        xcb::configure_window(
+            conn.conn(),
+            self.window_id,
+            &[
+                (xcb::CONFIG_WINDOW_WIDTH as u16, width as u32),
+                (xcb::CONFIG_WINDOW_HEIGHT as u16, height as u32),
+            ],
+        );
+        Ok(())
+    }
+
+    /// Set WM_NORMAL_HINTS from our stored hints.  If `enable` is
+    /// false, the hints are cleared but remain stored so that they
+    /// can be applied again later.
+    fn apply_size_hints(&self, enable: bool) {
+        let mut hints = xcb_util::icccm::SizeHints::empty();
+        if enable {
+            if let Some((x, y, base_width, base_height)) = self.size_hints.increments {
+                hints = hints
+                    .resize(x.into(), y.into())
+                    .base(base_width.into(), base_height.into());
+            }
+            // Without an explicit minimum, don't let the window shrink
+            // below a single increment, such as a single terminal cell
+            let min_size = self.size_hints.min_size.or_else(|| {
+                self.size_hints
+                    .increments
+                    .map(|(x, y, base_width, base_height)| {
+                        (base_width.saturating_add(x), base_height.saturating_add(y))
+                    })
+            });
+            if let Some((width, height)) = min_size {
+                hints = hints.min_size(width.into(), height.into());
+            }
+        }
+        xcb_util::icccm::set_wm_normal_hints(self.conn().conn(), self.window_id, &hints.build());
+    }
+
     #[allow(clippy::identity_op)]
     fn adjust_decorations(&mut self, decorations: WindowDecorations) -> anyhow::Result<()> {
         // Set the motif hints to disable decorations.
@@ -690,6 +1208,8 @@ impl XWindow {
                         xcb::CW_EVENT_MASK,
                         xcb::EVENT_MASK_EXPOSURE
                             | xcb::EVENT_MASK_FOCUS_CHANGE
+                            | xcb::EVENT_MASK_ENTER_WINDOW
+                            | xcb::EVENT_MASK_LEAVE_WINDOW
                             | xcb::EVENT_MASK_KEY_PRESS
                             | xcb::EVENT_MASK_BUTTON_PRESS
                             | xcb::EVENT_MASK_BUTTON_RELEASE
@@ -717,9 +1237,18 @@ impl XWindow {
                 height: height.try_into()?,
                 expose: VecDeque::new(),
                 paint_all: true,
+                pending_resize: None,
+                fullscreen: false,
+                maximized: false,
+                dpi: conn.default_dpi(),
+                mouse_buttons: MouseButtons::NONE,
+                has_focus: false,
+                size_hints: SizeHints::default(),
                 copy_and_paste: CopyAndPaste::default(),
+                drag_and_drop: DragAndDrop::default(),
                 cursors: CursorInfo::new(&conn),
                 gl_state: None,
+                paint_metrics: std::env::var_os("WEZTERM_PAINT_METRICS").is_some(),
                 config: Arc::clone(&config),
             }))
         };
@@ -739,6 +1268,17 @@ impl XWindow {
             &[conn.atom_delete],
         );
 
+        // Let drag sources know that we accept drops
+        xcb::change_property(
+            &*conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            window_id,
+            conn.xdnd.aware,
+            xcb::ATOM_ATOM,
+            32,
+            &[XDND_VERSION],
+        );
+
         window
             .lock()
             .unwrap()
@@ -773,14 +1313,51 @@ impl WindowOpsMut for XWindowInner {
     }
 
     fn toggle_fullscreen(&mut self) {
-        let fullscreen = match self.is_fullscreen() {
-            Ok(f) => f,
-            Err(err) => {
-                log::error!("Failed to determine fullscreen state: {}", err);
-                return;
+        self.set_fullscreen(!self.fullscreen);
+    }
+
+    fn set_fullscreen(&mut self, enable: bool) {
+        if enable != self.fullscreen {
+            // The window manager restores our prior geometry when we leave
+            // fullscreen, and we learn of it via CONFIGURE_NOTIFY as usual.
+            // Fullscreen and maximized are mutually exclusive for us, so
+            // that leaving one doesn't leave us in the other.
+            if enable && self.maximized {
+                self.set_maximized_hint(false);
             }
-        };
-        self.set_fullscreen_hint(!fullscreen).ok();
+            self.set_fullscreen_hint(enable).ok();
+        }
+    }
+
+    fn set_maximized(&mut self, enable: bool) {
+        if enable && self.fullscreen {
+            self.set_fullscreen_hint(false).ok();
+        }
+        self.set_maximized_hint(enable);
+    }
+
+    fn minimize(&mut self) {
+        self.minimize_hint();
+    }
+
+    fn set_opacity(&mut self, opacity: f64) {
+        let conn = self.conn();
+        if opacity >= 1.0 {
+            // Fully opaque is the same as having no opacity at all,
+            // and allows the compositor to skip blending the window
+            xcb::delete_property(conn.conn(), self.window_id, conn.atom_net_wm_window_opacity);
+        } else {
+            let opacity = opacity_cardinal(opacity);
+            xcb::change_property(
+                conn.conn(),
+                xcb::PROP_MODE_REPLACE as u8,
+                self.window_id,
+                conn.atom_net_wm_window_opacity,
+                xcb::xproto::ATOM_CARDINAL,
+                32,
+                &[opacity],
+            );
+        }
     }
 
     fn config_did_change(&mut self, config: &WindowConfigHandle) {
@@ -789,14 +1366,19 @@ impl WindowOpsMut for XWindowInner {
     }
 
     fn set_inner_size(&mut self, width: usize, height: usize) {
-        xcb::configure_window(
-            self.conn().conn(),
-            self.window_id,
-            &[
-                (xcb::CONFIG_WINDOW_WIDTH as u16, width as u32),
-                (xcb::CONFIG_WINDOW_HEIGHT as u16, height as u32),
-            ],
-        );
+        if let Err(err) = self.try_set_inner_size(width, height) {
+            log::error!("{:#}", err);
+        }
+    }
+
+    fn set_resize_increments(&mut self, x: u16, y: u16, base_width: u16, base_height: u16) {
+        self.size_hints.increments = Some((x, y, base_width, base_height));
+        self.apply_size_hints(!self.fullscreen);
+    }
+
+    fn set_min_size(&mut self, width: u16, height: u16) {
+        self.size_hints.min_size = Some((width, height));
+        self.apply_size_hints(!self.fullscreen);
     }
 
     fn set_window_position(&self, coords: ScreenPoint) {
@@ -828,17 +1410,11 @@ impl WindowOpsMut for XWindowInner {
     }
 
     fn set_icon(&mut self, image: &dyn BitmapImage) {
-        let (width, height) = image.image_dimensions();
-
-        // https://specifications.freedesktop.org/wm-spec/wm-spec-1.3.html#idm44927025355360
-        // says that this is an array of 32bit ARGB data.
-        // The first two elements are width, height, with the remainder
-        // being the the row data, left-to-right, top-to-bottom.
-        let mut icon_data = Vec::with_capacity((2 + (width * height)) * 4);
-        icon_data.push(width as u32);
-        icon_data.push(height as u32);
-        icon_data.extend_from_slice(image.pixels());
+        self.set_icons(&[image]);
+    }
 
+    fn set_icons(&mut self, images: &[&dyn BitmapImage]) {
+        let icon_data = encode_net_wm_icon(images);
         xcb_util::ewmh::set_wm_icon(
             self.conn().ewmh_conn(),
             xcb::PROP_MODE_REPLACE as u8,
@@ -848,6 +1424,33 @@ impl WindowOpsMut for XWindowInner {
     }
 }
 
+/// Encodes images in the format expected by the _NET_WM_ICON property.
+/// https://specifications.freedesktop.org/wm-spec/wm-spec-1.3.html#idm44927025355360
+/// says that this is an array of 32bit ARGB data.
+/// The first two elements are width, height, with the remainder
+/// being the the row data, left-to-right, top-to-bottom.
+/// Multiple images may be concatenated, and the window manager
+/// will choose the size that best suits its needs.
+/// The spec doesn't say so explicitly, but window managers and
+/// toolkits treat the data as straight (not premultiplied) alpha,
+/// which is also what our images hold, so the pixels are passed
+/// through without adjusting the color channels.
+fn encode_net_wm_icon(images: &[&dyn BitmapImage]) -> Vec<u32> {
+    let mut icon_data = vec![];
+    for image in images {
+        let (width, height) = image.image_dimensions();
+        icon_data.reserve(2 + (width * height));
+        icon_data.push(width as u32);
+        icon_data.push(height as u32);
+        for pixel in image.pixels() {
+            // Our pixels are stored as bgra bytes in memory
+            let [b, g, r, a] = pixel.to_ne_bytes();
+            icon_data.push(u32::from_be_bytes([a, r, g, b]));
+        }
+    }
+    icon_data
+}
+
 impl WindowOps for XWindow {
     fn close(&self) -> Future<()> {
         XConnection::with_window_inner(self.0, |inner| {
@@ -870,6 +1473,42 @@ impl WindowOps for XWindow {
         })
     }
 
+    fn set_fullscreen(&self, enable: bool) -> Future<()> {
+        XConnection::with_window_inner(self.0, move |inner| {
+            inner.set_fullscreen(enable);
+            Ok(())
+        })
+    }
+
+    fn is_fullscreen(&self) -> Future<bool> {
+        XConnection::with_window_inner(self.0, |inner| inner.query_fullscreen())
+    }
+
+    fn set_maximized(&self, enable: bool) -> Future<()> {
+        XConnection::with_window_inner(self.0, move |inner| {
+            inner.set_maximized(enable);
+            Ok(())
+        })
+    }
+
+    fn is_maximized(&self) -> Future<bool> {
+        XConnection::with_window_inner(self.0, |inner| Ok(inner.maximized))
+    }
+
+    fn minimize(&self) -> Future<()> {
+        XConnection::with_window_inner(self.0, |inner| {
+            inner.minimize();
+            Ok(())
+        })
+    }
+
+    fn set_opacity(&self, opacity: f64) -> Future<()> {
+        XConnection::with_window_inner(self.0, move |inner| {
+            inner.set_opacity(opacity);
+            Ok(())
+        })
+    }
+
     fn config_did_change(&self, config: &WindowConfigHandle) -> Future<()> {
         let config = Arc::clone(config);
         XConnection::with_window_inner(self.0, move |inner| {
@@ -908,9 +1547,16 @@ impl WindowOps for XWindow {
     }
 
     fn set_inner_size(&self, width: usize, height: usize) -> Future<()> {
-        XConnection::with_window_inner(self.0, move |inner| {
-            inner.set_inner_size(width, height);
-            Ok(())
+        XConnection::with_window_inner(self.0, move |inner| inner.try_set_inner_size(width, height))
+    }
+
+    fn get_inner_size(&self) -> Future<Dimensions> {
+        XConnection::with_window_inner(self.0, |inner| {
+            Ok(Dimensions {
+                pixel_width: inner.width as usize,
+                pixel_height: inner.height as usize,
+                dpi: inner.dpi as usize,
+            })
         })
     }
 
@@ -921,6 +1567,30 @@ impl WindowOps for XWindow {
         })
     }
 
+    fn get_window_position(&self) -> Future<ScreenPoint> {
+        XConnection::with_window_inner(self.0, |inner| inner.window_position())
+    }
+
+    fn set_resize_increments(
+        &self,
+        x: u16,
+        y: u16,
+        base_width: u16,
+        base_height: u16,
+    ) -> Future<()> {
+        XConnection::with_window_inner(self.0, move |inner| {
+            inner.set_resize_increments(x, y, base_width, base_height);
+            Ok(())
+        })
+    }
+
+    fn set_min_size(&self, width: u16, height: u16) -> Future<()> {
+        XConnection::with_window_inner(self.0, move |inner| {
+            inner.set_min_size(width, height);
+            Ok(())
+        })
+    }
+
     fn set_icon(&self, image: Image) -> Future<()> {
         XConnection::with_window_inner(self.0, move |inner| {
             inner.set_icon(&image);
@@ -928,6 +1598,17 @@ impl WindowOps for XWindow {
         })
     }
 
+    fn set_icons(&self, images: Vec<Image>) -> Future<()> {
+        XConnection::with_window_inner(self.0, move |inner| {
+            let images: Vec<&dyn BitmapImage> = images
+                .iter()
+                .map(|image| image as &dyn BitmapImage)
+                .collect();
+            inner.set_icons(&images);
+            Ok(())
+        })
+    }
+
     fn apply<R, F: Send + 'static + FnMut(&mut dyn Any, &dyn WindowOps) -> anyhow::Result<R>>(
         &self,
         mut func: F,
@@ -958,6 +1639,7 @@ impl WindowOps for XWindow {
             } else {
                 log::debug!("prepare promise, time={}", inner.copy_and_paste.time);
                 inner.copy_and_paste.request_mut(clipboard).replace(promise);
+                inner.copy_and_paste.request_started.replace(Instant::now());
                 let conn = inner.conn();
                 // Find the owner and ask them to send us the buffer
                 xcb::convert_selection(
@@ -992,3 +1674,344 @@ impl WindowOps for XWindow {
         })
     }
 }
+
+/// Merge `expose`, clipped to `window`, into `regions`.
+/// Any prior region may intersect, not just the most recent one,
+/// and growing the region may cause it to intersect with others
+/// that it didn't previously, so keep merging until it is disjoint
+/// from all of the others.
+/// Returns true if the resulting region covers the entire window.
+fn merge_expose(regions: &mut VecDeque<Rect>, expose: Rect, window: &Rect) -> bool {
+    let mut expose = match expose.intersection(window) {
+        Some(expose) => expose,
+        None => return false,
+    };
+    while let Some(idx) = regions.iter().position(|r| r.intersects(&expose)) {
+        let prior = regions.remove(idx).unwrap();
+        expose = prior.union(&expose);
+    }
+    let covers_window = expose.contains_rect(window);
+    regions.push_back(expose);
+    covers_window
+}
+
+/// Returns true if the _NET_WM_STATE atoms in `state` indicate that
+/// the window is maximized in both directions
+fn is_maximized(conn: &XConnection, state: &[xcb::Atom]) -> bool {
+    state.contains(&conn.atom_net_wm_state_maximized_horz)
+        && state.contains(&conn.atom_net_wm_state_maximized_vert)
+}
+
+/// Returns the symbolic name of the core X event with response type
+/// `r`, for logging purposes
+fn event_name(r: u8) -> &'static str {
+    match r {
+        xcb::KEY_PRESS => "KeyPress",
+        xcb::KEY_RELEASE => "KeyRelease",
+        xcb::BUTTON_PRESS => "ButtonPress",
+        xcb::BUTTON_RELEASE => "ButtonRelease",
+        xcb::MOTION_NOTIFY => "MotionNotify",
+        xcb::ENTER_NOTIFY => "EnterNotify",
+        xcb::LEAVE_NOTIFY => "LeaveNotify",
+        xcb::FOCUS_IN => "FocusIn",
+        xcb::FOCUS_OUT => "FocusOut",
+        xcb::KEYMAP_NOTIFY => "KeymapNotify",
+        xcb::EXPOSE => "Expose",
+        xcb::GRAPHICS_EXPOSURE => "GraphicsExposure",
+        xcb::NO_EXPOSURE => "NoExposure",
+        xcb::VISIBILITY_NOTIFY => "VisibilityNotify",
+        xcb::CREATE_NOTIFY => "CreateNotify",
+        xcb::DESTROY_NOTIFY => "DestroyNotify",
+        xcb::UNMAP_NOTIFY => "UnmapNotify",
+        xcb::MAP_NOTIFY => "MapNotify",
+        xcb::MAP_REQUEST => "MapRequest",
+        xcb::REPARENT_NOTIFY => "ReparentNotify",
+        xcb::CONFIGURE_NOTIFY => "ConfigureNotify",
+        xcb::CONFIGURE_REQUEST => "ConfigureRequest",
+        xcb::GRAVITY_NOTIFY => "GravityNotify",
+        xcb::RESIZE_REQUEST => "ResizeRequest",
+        xcb::CIRCULATE_NOTIFY => "CirculateNotify",
+        xcb::CIRCULATE_REQUEST => "CirculateRequest",
+        xcb::PROPERTY_NOTIFY => "PropertyNotify",
+        xcb::SELECTION_CLEAR => "SelectionClear",
+        xcb::SELECTION_REQUEST => "SelectionRequest",
+        xcb::SELECTION_NOTIFY => "SelectionNotify",
+        xcb::COLORMAP_NOTIFY => "ColormapNotify",
+        xcb::CLIENT_MESSAGE => "ClientMessage",
+        xcb::MAPPING_NOTIFY => "MappingNotify",
+        xcb::GE_GENERIC => "GeGeneric",
+        _ => "unknown",
+    }
+}
+
+/// Converts an opacity in the range 0.0-1.0 into the CARDINAL value
+/// used by _NET_WM_WINDOW_OPACITY, where 0xffffffff is fully opaque
+fn opacity_cardinal(opacity: f64) -> u32 {
+    (opacity.max(0.0).min(1.0) * f64::from(u32::max_value())).round() as u32
+}
+
+/// Returns true if `button` is one of the buttons that X11 uses to
+/// report movement of the vertical or horizontal scroll wheel
+pub fn is_wheel_button(button: u8) -> bool {
+    (4..=7).contains(&button)
+}
+
+/// Returns true if the window should be destroyed in response to a
+/// WM_DELETE_WINDOW request from the window manager.  A window whose
+/// close was deferred stays open until the application calls
+/// `Window::close`, which destroys it unconditionally.
+fn delete_window_destroys(callbacks: &mut dyn WindowCallbacks, window_id: xcb::Window) -> bool {
+    match callbacks.close_requested() {
+        CloseAction::Close => true,
+        CloseAction::Defer => {
+            log::trace!("close of window {} deferred", window_id);
+            false
+        }
+        CloseAction::Cancel => false,
+    }
+}
+
+/// Translates a button press or release (as given by `r`) into a
+/// mouse event.  `ticks` is the number of movements of the wheel that
+/// the event represents, when it is a wheel button.  Returns None for
+/// events that we don't report, such as the release of a wheel button.
+fn button_event(r: u8, button_press: &xcb::ButtonPressEvent, ticks: i16) -> Option<MouseEvent> {
+    // The state reflects the buttons that were held prior
+    // to this event, so adjust it to include a newly pressed
+    // button and to exclude a newly released button.
+    let mut mouse_buttons = xkeysyms::mouse_buttons_from_state(button_press.state());
+
+    let kind = match button_press.detail() {
+        b @ 1..=3 => {
+            let (button, mask) = match b {
+                1 => (MousePress::Left, MouseButtons::LEFT),
+                2 => (MousePress::Middle, MouseButtons::MIDDLE),
+                3 => (MousePress::Right, MouseButtons::RIGHT),
+                _ => unreachable!(),
+            };
+            if r == xcb::BUTTON_PRESS {
+                mouse_buttons |= mask;
+                MouseEventKind::Press(button)
+            } else {
+                mouse_buttons -= mask;
+                MouseEventKind::Release(button)
+            }
+        }
+        b @ 4..=7 => {
+            if r == xcb::BUTTON_RELEASE {
+                return None;
+            }
+            wheel_event_kind(b, ticks)?
+        }
+        // 8 and 9 are usually the back/forward buttons
+        b => {
+            log::trace!("button {} is not implemented", b);
+            return None;
+        }
+    };
+
+    Some(MouseEvent {
+        kind,
+        coords: Point::new(
+            button_press.event_x().try_into().unwrap(),
+            button_press.event_y().try_into().unwrap(),
+        ),
+        screen_coords: ScreenPoint::new(
+            button_press.root_x().try_into().unwrap(),
+            button_press.root_y().try_into().unwrap(),
+        ),
+        modifiers: xkeysyms::modifiers_from_state(button_press.state()),
+        mouse_buttons,
+    })
+}
+
+/// Computes the event for `ticks` movements of the wheel `button`.
+/// 4 and 5 are the vertical wheel.  6 and 7 are the horizontal wheel;
+/// 6 scrolls left, which is a positive delta, consistent with the other
+/// backends and with VertWheel where positive values scroll towards
+/// the start.
+fn wheel_event_kind(button: u8, ticks: i16) -> Option<MouseEventKind> {
+    // Ideally this would be configurable, but it's currently a bit
+    // awkward to configure this layer, so let's just improve the
+    // default for now!
+    const LINES_PER_TICK: i16 = 5;
+
+    match button {
+        4 => Some(MouseEventKind::VertWheel(
+            ticks.saturating_mul(LINES_PER_TICK),
+        )),
+        5 => Some(MouseEventKind::VertWheel(
+            ticks.saturating_mul(-LINES_PER_TICK),
+        )),
+        6 => Some(MouseEventKind::HorzWheel(ticks)),
+        7 => Some(MouseEventKind::HorzWheel(-ticks)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn opacity() {
+        assert_eq!(opacity_cardinal(1.0), 0xffff_ffff);
+        assert_eq!(opacity_cardinal(0.0), 0);
+        assert_eq!(opacity_cardinal(0.5), 0x8000_0000);
+        assert_eq!(opacity_cardinal(-1.0), 0);
+        assert_eq!(opacity_cardinal(2.0), 0xffff_ffff);
+    }
+
+    #[test]
+    fn wheel_delta() {
+        assert_eq!(wheel_event_kind(4, 1), Some(MouseEventKind::VertWheel(5)));
+        assert_eq!(wheel_event_kind(5, 3), Some(MouseEventKind::VertWheel(-15)));
+        assert_eq!(wheel_event_kind(6, 2), Some(MouseEventKind::HorzWheel(2)));
+        assert_eq!(wheel_event_kind(7, 2), Some(MouseEventKind::HorzWheel(-2)));
+        assert_eq!(
+            wheel_event_kind(4, i16::max_value()),
+            Some(MouseEventKind::VertWheel(i16::max_value()))
+        );
+        assert_eq!(wheel_event_kind(8, 1), None);
+    }
+
+    #[test]
+    fn delete_window() {
+        /// Asks the user to confirm the first close request, and
+        /// closes when asked again after the user confirmed
+        #[derive(Default)]
+        struct Confirming {
+            asked: usize,
+            confirmed: bool,
+            destroyed: bool,
+        }
+        impl WindowCallbacks for Confirming {
+            fn close_requested(&mut self) -> CloseAction {
+                self.asked += 1;
+                if self.confirmed {
+                    CloseAction::Close
+                } else {
+                    CloseAction::Defer
+                }
+            }
+            fn destroy(&mut self) {
+                self.destroyed = true;
+            }
+            fn as_any(&mut self) -> &mut dyn Any {
+                self
+            }
+        }
+
+        let mut callbacks = Confirming::default();
+        // Deferring keeps the window, however often the window
+        // manager asks, and the callbacks aren't told of a destroy
+        assert!(!delete_window_destroys(&mut callbacks, 1));
+        assert!(!delete_window_destroys(&mut callbacks, 1));
+        assert_eq!(callbacks.asked, 2);
+        assert!(!callbacks.destroyed);
+
+        callbacks.confirmed = true;
+        assert!(delete_window_destroys(&mut callbacks, 1));
+
+        struct Refusing;
+        impl WindowCallbacks for Refusing {
+            fn can_close(&mut self) -> bool {
+                false
+            }
+            fn as_any(&mut self) -> &mut dyn Any {
+                self
+            }
+        }
+        assert!(!delete_window_destroys(&mut Refusing, 1));
+    }
+
+    #[test]
+    fn button_events() {
+        let press = |detail: u8, state: u32| {
+            xcb::ButtonPressEvent::new(detail, 0, 0, 0, 0, 100, 200, 10, 20, state as u16, true)
+        };
+        let ctrl_shift = xcb::MOD_MASK_CONTROL | xcb::MOD_MASK_SHIFT;
+
+        for &(detail, delta) in &[(6, 5), (7, -5)] {
+            let event = button_event(xcb::BUTTON_PRESS, &press(detail, ctrl_shift), 5).unwrap();
+            assert_eq!(event.kind, MouseEventKind::HorzWheel(delta));
+            assert_eq!(
+                event.modifiers,
+                crate::Modifiers::CTRL | crate::Modifiers::SHIFT
+            );
+            assert_eq!(event.coords, Point::new(10, 20));
+            assert_eq!(event.screen_coords, ScreenPoint::new(100, 200));
+            assert_eq!(
+                button_event(xcb::BUTTON_RELEASE, &press(detail, ctrl_shift), 1),
+                None
+            );
+        }
+
+        let event = button_event(xcb::BUTTON_PRESS, &press(1, 0), 1).unwrap();
+        assert_eq!(event.kind, MouseEventKind::Press(MousePress::Left));
+        assert_eq!(event.mouse_buttons, MouseButtons::LEFT);
+        let event = button_event(xcb::BUTTON_RELEASE, &press(1, xcb::BUTTON_MASK_1), 1).unwrap();
+        assert_eq!(event.kind, MouseEventKind::Release(MousePress::Left));
+        assert_eq!(event.mouse_buttons, MouseButtons::NONE);
+
+        assert_eq!(button_event(xcb::BUTTON_PRESS, &press(8, 0), 1), None);
+    }
+
+    #[test]
+    fn coalesce_expose() {
+        let window = Rect::new(Point::new(0, 0), Size::new(800, 600));
+        let mut regions = VecDeque::new();
+
+        // A live resize produces a stream of growing, overlapping regions
+        for step in 1..100 {
+            let expose = Rect::new(Point::new(0, 0), Size::new(step * 4, step * 3));
+            assert!(!merge_expose(&mut regions, expose, &window));
+            assert_eq!(regions.len(), 1);
+        }
+
+        // Disjoint regions are kept separate
+        let corner = Rect::new(Point::new(700, 500), Size::new(10, 10));
+        assert!(!merge_expose(&mut regions, corner, &window));
+        assert_eq!(regions.len(), 2);
+
+        // Regions outside of the window are discarded, and those that
+        // straddle the edge are clipped to it
+        let outside = Rect::new(Point::new(900, 900), Size::new(10, 10));
+        assert!(!merge_expose(&mut regions, outside, &window));
+        assert_eq!(regions.len(), 2);
+
+        // Once the window is covered, we can paint all of it
+        let oversized = Rect::new(Point::new(0, 0), Size::new(1000, 1000));
+        assert!(merge_expose(&mut regions, oversized, &window));
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0], window);
+
+        // The event fields are u16, but the arithmetic is done in
+        // isize, so regions near the limit don't overflow
+        let max = u16::max_value() as isize;
+        let window = Rect::new(Point::new(0, 0), Size::new(max, max));
+        let mut regions = VecDeque::new();
+        let edge = Rect::new(Point::new(max - 1, max - 1), Size::new(max, max));
+        assert!(!merge_expose(&mut regions, edge, &window));
+        assert_eq!(
+            regions[0],
+            Rect::new(Point::new(max - 1, max - 1), Size::new(1, 1))
+        );
+    }
+
+    #[test]
+    fn net_wm_icon() {
+        #[rustfmt::skip]
+        let image = Image::from_raw(2, 2, vec![
+            // b, g, r, a
+            0x00, 0x00, 0xff, 0xff,
+            0x00, 0xff, 0x00, 0x80,
+            0xff, 0x00, 0x00, 0x00,
+            0x10, 0x20, 0x30, 0x40,
+        ]);
+        assert_eq!(
+            encode_net_wm_icon(&[&image]),
+            vec![2, 2, 0xffff0000, 0x8000ff00, 0x000000ff, 0x40302010]
+        );
+    }
+}
@@ -19,6 +19,10 @@ pub fn parse_root_resource_manager(
     .get_reply()?;
 
     let text = String::from_utf8_lossy(reply.value::<u8>());
+    Ok(parse_resource_string(&text))
+}
+
+fn parse_resource_string(text: &str) -> HashMap<String, String> {
     let mut map = HashMap::new();
     for line in text.split('\n') {
         if let Some(colon) = line.find(':') {
@@ -29,6 +33,29 @@ pub fn parse_root_resource_manager(
             map.insert(key.to_string(), value.to_string());
         }
     }
+    map
+}
+
+/// Returns the DPI specified by the `Xft.dpi` resource, if any
+pub fn xft_dpi(xrm: &HashMap<String, String>) -> Option<f64> {
+    xrm.get("Xft.dpi")?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    Ok(map)
+    #[test]
+    fn dpi() {
+        let xrm =
+            parse_resource_string("Xft.dpi:\t144\nXft.hinting:\t1\nXcursor.theme:\tAdwaita\n");
+        assert_eq!(xft_dpi(&xrm), Some(144.0));
+        assert_eq!(
+            xrm.get("Xcursor.theme").map(String::as_str),
+            Some("Adwaita")
+        );
+
+        let xrm = parse_resource_string("Xft.hinting:\t1\n");
+        assert_eq!(xft_dpi(&xrm), None);
+    }
 }
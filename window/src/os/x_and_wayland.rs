@@ -7,8 +7,12 @@ use crate::os::wayland::connection::WaylandConnection;
 use crate::os::wayland::window::WaylandWindow;
 use crate::os::x11::connection::XConnection;
 use crate::os::x11::window::XWindow;
+use crate::timerlist::TimerHandle;
 use crate::WindowConfigHandle;
-use crate::{config, Clipboard, MouseCursor, ScreenPoint, WindowCallbacks, WindowOps};
+use crate::{
+    config, Clipboard, Dimensions, MonitorInfo, MouseCursor, ScreenPoint, WindowCallbacks,
+    WindowOps,
+};
 use promise::*;
 use std::any::Any;
 use std::rc::Rc;
@@ -71,6 +75,37 @@ impl Connection {
         }
     }
 
+    /// Like `schedule_timer`, but returns a handle that can be
+    /// passed to `cancel_timer` to stop the timer
+    pub fn schedule_cancellable_timer<F: FnMut() + 'static>(
+        &self,
+        interval: std::time::Duration,
+        callback: F,
+    ) -> TimerHandle {
+        match self {
+            Self::X11(x) => x.schedule_cancellable_timer(interval, callback),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.schedule_cancellable_timer(interval, callback),
+        }
+    }
+
+    pub fn cancel_timer(&self, handle: TimerHandle) {
+        match self {
+            Self::X11(x) => x.cancel_timer(handle),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.cancel_timer(handle),
+        }
+    }
+
+    /// Returns the monitors that are currently attached to the display
+    pub fn monitors(&self) -> anyhow::Result<Vec<MonitorInfo>> {
+        match self {
+            Self::X11(x) => x.monitors(),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(_) => anyhow::bail!("monitors are not available on Wayland"),
+        }
+    }
+
     #[cfg(feature = "wayland")]
     pub(crate) fn wayland(&self) -> Rc<WaylandConnection> {
         match self {
@@ -145,6 +180,54 @@ impl WindowOps for Window {
         }
     }
 
+    fn set_fullscreen(&self, enable: bool) -> Future<()> {
+        match self {
+            Self::X11(x) => x.set_fullscreen(enable),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.set_fullscreen(enable),
+        }
+    }
+
+    fn is_fullscreen(&self) -> Future<bool> {
+        match self {
+            Self::X11(x) => x.is_fullscreen(),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.is_fullscreen(),
+        }
+    }
+
+    fn set_maximized(&self, enable: bool) -> Future<()> {
+        match self {
+            Self::X11(x) => x.set_maximized(enable),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.set_maximized(enable),
+        }
+    }
+
+    fn minimize(&self) -> Future<()> {
+        match self {
+            Self::X11(x) => x.minimize(),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.minimize(),
+        }
+    }
+
+    fn is_maximized(&self) -> Future<bool> {
+        match self {
+            Self::X11(x) => x.is_maximized(),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.is_maximized(),
+        }
+    }
+
+    fn set_opacity(&self, opacity: f64) -> Future<()> {
+        match self {
+            Self::X11(x) => x.set_opacity(opacity),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.set_opacity(opacity),
+        }
+    }
+
     fn config_did_change(&self, config: &WindowConfigHandle) -> Future<()> {
         match self {
             Self::X11(x) => x.config_did_change(config),
@@ -193,6 +276,14 @@ impl WindowOps for Window {
         }
     }
 
+    fn set_icons(&self, images: Vec<crate::bitmaps::Image>) -> Future<()> {
+        match self {
+            Self::X11(x) => x.set_icons(images),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.set_icons(images),
+        }
+    }
+
     fn set_inner_size(&self, width: usize, height: usize) -> Future<()> {
         match self {
             Self::X11(x) => x.set_inner_size(width, height),
@@ -201,6 +292,14 @@ impl WindowOps for Window {
         }
     }
 
+    fn get_inner_size(&self) -> Future<Dimensions> {
+        match self {
+            Self::X11(x) => x.get_inner_size(),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.get_inner_size(),
+        }
+    }
+
     fn set_window_position(&self, coords: ScreenPoint) -> Future<()> {
         match self {
             Self::X11(x) => x.set_window_position(coords),
@@ -209,6 +308,36 @@ impl WindowOps for Window {
         }
     }
 
+    fn get_window_position(&self) -> Future<ScreenPoint> {
+        match self {
+            Self::X11(x) => x.get_window_position(),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.get_window_position(),
+        }
+    }
+
+    fn set_resize_increments(
+        &self,
+        x: u16,
+        y: u16,
+        base_width: u16,
+        base_height: u16,
+    ) -> Future<()> {
+        match self {
+            Self::X11(w) => w.set_resize_increments(x, y, base_width, base_height),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.set_resize_increments(x, y, base_width, base_height),
+        }
+    }
+
+    fn set_min_size(&self, width: u16, height: u16) -> Future<()> {
+        match self {
+            Self::X11(x) => x.set_min_size(width, height),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.set_min_size(width, height),
+        }
+    }
+
     fn apply<R, F: Send + 'static + FnMut(&mut dyn Any, &dyn WindowOps) -> anyhow::Result<R>>(
         &self,
         func: F,
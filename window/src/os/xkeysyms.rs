@@ -1,6 +1,6 @@
 #![cfg(all(unix, not(target_os = "macos")))]
 
-use crate::{KeyCode, Modifiers};
+use crate::{KeyCode, Modifiers, MouseButtons};
 
 pub fn modifiers_from_state(state: u16) -> Modifiers {
     use xcb::xproto::*;
@@ -24,6 +24,25 @@ pub fn modifiers_from_state(state: u16) -> Modifiers {
     mods
 }
 
+pub fn mouse_buttons_from_state(state: u16) -> MouseButtons {
+    use xcb::xproto::*;
+
+    let mut buttons = MouseButtons::NONE;
+    let state = u32::from(state);
+
+    if state & BUTTON_MASK_1 != 0 {
+        buttons |= MouseButtons::LEFT;
+    }
+    if state & BUTTON_MASK_2 != 0 {
+        buttons |= MouseButtons::MIDDLE;
+    }
+    if state & BUTTON_MASK_3 != 0 {
+        buttons |= MouseButtons::RIGHT;
+    }
+
+    buttons
+}
+
 /// Translates non-printable X11 keysym to KeyCode
 /// for missing keys, look into `/usr/include/X11/keysymdef.h`
 /// and/or define them in KeyCode.
@@ -88,6 +107,8 @@ pub fn keysym_to_keycode(keysym: u32) -> Option<KeyCode> {
         KEY_KP_Enter => KeyCode::Char(0xdu8 as char),
         KEY_KP_Delete => KeyCode::Char('\u{7f}'),
         KEY_KP_Home => KeyCode::Home,
+        KEY_KP_End => KeyCode::End,
+        KEY_KP_Begin => KeyCode::Clear,
         KEY_KP_Page_Up => KeyCode::PageUp,
         KEY_KP_Page_Down => KeyCode::PageDown,
         KEY_KP_Multiply => KeyCode::Multiply,
@@ -120,3 +141,48 @@ pub fn keysym_to_keycode(keysym: u32) -> Option<KeyCode> {
         _ => return None,
     })
 }
+
+/// Returns the keypad-specific KeyCode for the keysyms produced by the
+/// numeric keypad.  `keysym_to_keycode` returns the text that these
+/// produce, such as `Char('1')` for `KP_1`, which is what should be sent
+/// to the terminal, but this allows them to be told apart from the keys
+/// in the main section of the keyboard.
+pub fn keypad_keysym_to_keycode(keysym: u32) -> Option<KeyCode> {
+    use xkbcommon::xkb::keysyms::*;
+    #[allow(non_upper_case_globals)]
+    Some(match keysym {
+        i @ KEY_KP_0..=KEY_KP_9 => KeyCode::Numpad((i - KEY_KP_0) as u8),
+        KEY_KP_Multiply => KeyCode::Multiply,
+        KEY_KP_Add => KeyCode::Add,
+        KEY_KP_Divide => KeyCode::Divide,
+        KEY_KP_Subtract => KeyCode::Subtract,
+        KEY_KP_Decimal => KeyCode::Decimal,
+        KEY_KP_Separator => KeyCode::Separator,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mouse_buttons() {
+        use xcb::xproto::*;
+
+        assert_eq!(mouse_buttons_from_state(0), MouseButtons::NONE);
+        assert_eq!(
+            mouse_buttons_from_state(BUTTON_MASK_1 as u16),
+            MouseButtons::LEFT
+        );
+        // Modifiers don't influence the buttons
+        assert_eq!(
+            mouse_buttons_from_state((BUTTON_MASK_3 | MOD_MASK_SHIFT) as u16),
+            MouseButtons::RIGHT
+        );
+        assert_eq!(
+            mouse_buttons_from_state((BUTTON_MASK_1 | BUTTON_MASK_2) as u16),
+            MouseButtons::LEFT | MouseButtons::MIDDLE
+        );
+    }
+}
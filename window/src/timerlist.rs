@@ -1,4 +1,5 @@
 #![cfg(all(unix, not(target_os = "macos")))]
+use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
@@ -9,9 +10,18 @@ pub struct TimerEntry {
     pub interval: Duration,
 }
 
+/// Identifies a scheduled timer so that it can be cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerHandle(usize);
+
 #[derive(Default)]
 pub struct TimerList {
-    timers: VecDeque<TimerEntry>,
+    timers: VecDeque<(TimerHandle, TimerEntry)>,
+    /// The timers whose callbacks are currently running
+    running: Vec<TimerHandle>,
+    /// Running timers that were cancelled by their callbacks
+    cancelled: Vec<TimerHandle>,
+    next_handle: usize,
 }
 
 impl TimerList {
@@ -20,7 +30,7 @@ impl TimerList {
     }
 
     fn find_index_after(&self, due: &Instant) -> usize {
-        for (idx, entry) in self.timers.iter().enumerate() {
+        for (idx, (_, entry)) in self.timers.iter().enumerate() {
             if entry.due.cmp(due) == Ordering::Greater {
                 return idx;
             }
@@ -28,14 +38,31 @@ impl TimerList {
         self.timers.len()
     }
 
-    pub fn insert(&mut self, mut entry: TimerEntry) {
-        entry.due = Instant::now() + entry.interval;
+    fn schedule(&mut self, handle: TimerHandle, entry: TimerEntry) {
         let idx = self.find_index_after(&entry.due);
-        self.timers.insert(idx, entry);
+        self.timers.insert(idx, (handle, entry));
+    }
+
+    pub fn insert(&mut self, mut entry: TimerEntry) -> TimerHandle {
+        let handle = TimerHandle(self.next_handle);
+        self.next_handle += 1;
+        entry.due = Instant::now() + entry.interval;
+        self.schedule(handle, entry);
+        handle
+    }
+
+    /// Stops the timer from running again.  This may be called from
+    /// within the callback of the timer itself.
+    pub fn cancel(&mut self, handle: TimerHandle) {
+        if let Some(idx) = self.timers.iter().position(|(h, _)| *h == handle) {
+            self.timers.remove(idx);
+        } else if self.running.contains(&handle) {
+            self.cancelled.push(handle);
+        }
     }
 
     pub fn time_until_due(&self, now: Instant) -> Option<Duration> {
-        self.timers.front().map(|entry| {
+        self.timers.front().map(|(_, entry)| {
             if entry.due <= now {
                 Duration::from_secs(0)
             } else {
@@ -45,24 +72,121 @@ impl TimerList {
     }
 
     fn first_is_ready(&self, now: Instant) -> bool {
-        if let Some(first) = self.timers.front() {
+        if let Some((_, first)) = self.timers.front() {
             first.due <= now
         } else {
             false
         }
     }
 
-    pub fn run_ready(&mut self) {
-        let now = Instant::now();
-        let mut requeue = vec![];
+    fn take_ready(&mut self, now: Instant) -> Vec<(TimerHandle, TimerEntry)> {
+        let mut ready = vec![];
         while self.first_is_ready(now) {
-            let mut first = self.timers.pop_front().expect("first_is_ready");
-            (first.callback)();
-            requeue.push(first);
+            ready.push(self.timers.pop_front().expect("first_is_ready"));
         }
+        self.running = ready.iter().map(|(handle, _)| *handle).collect();
+        ready
+    }
 
-        for entry in requeue.into_iter() {
-            self.insert(entry);
+    /// Schedules the next run of a timer that has just run.
+    /// The next due time is based on the previous one rather than the
+    /// current time so that the timer doesn't drift when the event loop
+    /// is busy, but runs that were missed entirely are not made up.
+    fn requeue(&mut self, handle: TimerHandle, mut entry: TimerEntry, now: Instant) {
+        self.running.retain(|h| *h != handle);
+        if let Some(idx) = self.cancelled.iter().position(|h| *h == handle) {
+            self.cancelled.remove(idx);
+            return;
+        }
+        entry.due += entry.interval;
+        if entry.due <= now {
+            entry.due = now + entry.interval;
         }
+        self.schedule(handle, entry);
+    }
+
+    /// Runs the callbacks of the timers that are due.
+    /// The list is not borrowed while the callbacks run, so they
+    /// are free to schedule or cancel timers.
+    pub fn run_ready(timers: &RefCell<Self>) {
+        let now = Instant::now();
+        let ready = timers.borrow_mut().take_ready(now);
+        for (handle, mut entry) in ready {
+            (entry.callback)();
+            timers.borrow_mut().requeue(handle, entry, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn reentrant_cancel() {
+        let timers = Rc::new(RefCell::new(TimerList::new()));
+        let runs = Rc::new(RefCell::new(0));
+
+        let handle = Rc::new(RefCell::new(None));
+        let h = timers.borrow_mut().insert(TimerEntry {
+            callback: Box::new({
+                let timers = Rc::clone(&timers);
+                let runs = Rc::clone(&runs);
+                let handle = Rc::clone(&handle);
+                move || {
+                    *runs.borrow_mut() += 1;
+                    if let Some(handle) = *handle.borrow() {
+                        timers.borrow_mut().cancel(handle);
+                    }
+                }
+            }),
+            due: Instant::now(),
+            interval: Duration::from_secs(0),
+        });
+        handle.borrow_mut().replace(h);
+
+        TimerList::run_ready(&timers);
+        assert_eq!(*runs.borrow(), 1);
+        assert!(timers.borrow().time_until_due(Instant::now()).is_none());
+
+        TimerList::run_ready(&timers);
+        assert_eq!(*runs.borrow(), 1);
+    }
+
+    #[test]
+    fn cancel_and_drift() {
+        let mut timers = TimerList::new();
+        let noop = || -> Box<dyn FnMut()> { Box::new(|| {}) };
+        let slow = timers.insert(TimerEntry {
+            callback: noop(),
+            due: Instant::now(),
+            interval: Duration::from_secs(60),
+        });
+        let fast = timers.insert(TimerEntry {
+            callback: noop(),
+            due: Instant::now(),
+            interval: Duration::from_secs(10),
+        });
+
+        let now = Instant::now();
+        assert!(timers.time_until_due(now).unwrap() <= Duration::from_secs(10));
+        timers.cancel(fast);
+        assert!(timers.time_until_due(now).unwrap() > Duration::from_secs(10));
+        timers.cancel(slow);
+        assert!(timers.time_until_due(now).is_none());
+
+        // A timer that ran late is next due relative to when it was
+        // supposed to run, not when it actually ran
+        let start = Instant::now();
+        let entry = TimerEntry {
+            callback: noop(),
+            due: start,
+            interval: Duration::from_secs(10),
+        };
+        let handle = TimerHandle(100);
+        timers.running.push(handle);
+        timers.requeue(handle, entry, start + Duration::from_secs(2));
+        assert_eq!(timers.time_until_due(start), Some(Duration::from_secs(10)));
     }
 }